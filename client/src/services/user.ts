@@ -10,6 +10,16 @@ import {
 import auth from './auth';
 
 const BASE_URL = import.meta.env.CFN_ApiBaseUrl + '/';
+const IMPORT_POLL_INTERVAL_MS = 1000;
+
+interface ImportJob {
+  job_id: string;
+  status: 'pending' | 'running' | 'succeeded' | 'failed';
+  written: number;
+  total: number;
+  error?: string;
+  version?: number;
+}
 
 export class CacheOutdatedError extends Error {}
 
@@ -186,5 +196,26 @@ export default new class {
     });
 
     this.checkErrors(res);
+
+    // The import is processed in the background so poll until it's finished.
+    let job: ImportJob = await res.json();
+
+    while (job.status === 'pending' || job.status === 'running') {
+      await new Promise(resolve => setTimeout(resolve, IMPORT_POLL_INTERVAL_MS));
+      job = await this.getImportJob(job.job_id);
+    }
+
+    if (job.status === 'failed') throw new BadResponseError(job.error);
+  }
+
+  async getImportJob(jobId: string): Promise<ImportJob> {
+    const res = await fetch(`${BASE_URL}user/import/${jobId}`, {
+      method: 'GET',
+      headers: await this.getHeaders(),
+    });
+
+    this.checkErrors(res);
+
+    return res.json();
   }
 }
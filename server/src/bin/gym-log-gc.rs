@@ -0,0 +1,86 @@
+use gym_log::{gc, store};
+use lambda_http::Error;
+use lambda_runtime::{LambdaEvent, service_fn};
+use serde::Deserialize;
+
+// Deletes the items that are left behind by failed imports (see gc). When this
+// is running as a Lambda function, it's invoked on a schedule and collects
+// every user. It can also be invoked manually with a `userId` in the event to
+// collect a single user. When it's run from the command line, the user IDs are
+// passed as arguments or `--all` collects every user.
+//
+// The store is chosen with the GYM_LOG_STORE environment variable in the same
+// way as the local server except that it defaults to "dynamodb" here.
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Event {
+    #[serde(default)]
+    user_id: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .init();
+
+    match std::env::var("GYM_LOG_STORE").as_deref() {
+        Ok("dynamodb") | Err(_) => run(store::DynamoStore::from_env().await).await,
+        Ok("memory") => run(store::MemoryStore::new()).await,
+        Ok(s) => Err(format!("unknown store \"{s}\"").into()),
+    }
+}
+
+async fn run(store: impl store::UserStore) -> Result<(), Error> {
+    if std::env::var("AWS_LAMBDA_RUNTIME_API").is_ok() {
+        return lambda_runtime::run(service_fn(|event: LambdaEvent<Event>| {
+            handle_event(&store, event.payload)
+        })).await;
+    }
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        [] => Err("usage: gym-log-gc (--all | <user-id>...)".into()),
+        [all] if all == "--all" => {
+            let summary = gc::collect_all(&store).await?;
+            print_summary(&summary);
+            Ok(())
+        }
+        user_ids => {
+            for user_id in user_ids {
+                match gc::collect_user(&store, user_id).await? {
+                    gc::Collected::Clean => println!("{user_id}: nothing to delete"),
+                    gc::Collected::Deleted(count) => println!("{user_id}: deleted {count} items"),
+                    gc::Collected::Locked => println!("{user_id}: skipped, import in progress"),
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn handle_event(store: &impl store::UserStore, event: Event) -> Result<(), Error> {
+    match event.user_id {
+        Some(user_id) => {
+            if let gc::Collected::Deleted(count) = gc::collect_user(store, &user_id).await? {
+                tracing::info!("Deleted {count} orphaned items of {user_id}");
+            }
+        }
+        None => print_summary(&gc::collect_all(store).await?),
+    }
+
+    Ok(())
+}
+
+fn print_summary(summary: &gc::Summary) {
+    tracing::info!(
+        "Collected {} users: deleted {} items, skipped {} locked, {} failed",
+        summary.users,
+        summary.deleted,
+        summary.locked,
+        summary.failed,
+    );
+}
@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+use gym_log::{import, store};
+use lambda_http::Error;
+use lambda_runtime::{LambdaEvent, service_fn};
+use serde::Deserialize;
+
+// Processes import jobs (see import). The function is triggered by the
+// DynamoDB stream of the user table, filtered to inserts and modifications of
+// import jobs that are pending or running. Each time the worker records its
+// progress, the job item changes which triggers the function again. So if the
+// worker runs out of time, the next invocation carries on from where it left
+// off. If the worker fails, the stream retries the record. The filter is
+// checked again here so that nothing else under the prefix is processed if the
+// filter is ever loosened.
+
+/// Stop starting new chunks this long before the function times out.
+const DEADLINE_MARGIN: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StreamEvent {
+    records: Vec<StreamRecord>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamRecord {
+    event_name: String,
+    dynamodb: StreamRecordData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StreamRecordData {
+    keys: Keys,
+    #[serde(default)]
+    new_image: Option<JobImage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JobImage {
+    #[serde(default)]
+    status: Option<StringAttribute>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Keys {
+    user_id: StringAttribute,
+    id: StringAttribute,
+}
+
+#[derive(Deserialize)]
+struct StringAttribute {
+    #[serde(rename = "S")]
+    s: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .without_time()
+        .init();
+
+    let store = store::DynamoStore::from_env().await;
+
+    lambda_runtime::run(service_fn(|event: LambdaEvent<StreamEvent>| {
+        handle_event(&store, event)
+    })).await
+}
+
+async fn handle_event(
+    store: &impl store::UserStore,
+    event: LambdaEvent<StreamEvent>,
+) -> Result<(), Error> {
+    let remaining = Duration::from_millis(
+        event.context.deadline.saturating_sub(gym_log::common::now() * 1000)
+    );
+    let deadline = Instant::now() + remaining.saturating_sub(DEADLINE_MARGIN);
+
+    for record in event.payload.records {
+        if record.event_name != "INSERT" && record.event_name != "MODIFY" {
+            continue;
+        }
+
+        let keys = record.dynamodb.keys;
+        let Some(job_id) = keys.id.s.strip_prefix(import::KEY_PREFIX) else {
+            continue;
+        };
+
+        // Parts of the payload are also under the prefix.
+        if job_id.contains('#') {
+            continue;
+        }
+
+        let status = record.dynamodb.new_image
+            .and_then(|i| i.status)
+            .map(|s| s.s);
+
+        if !matches!(status.as_deref(), Some("pending" | "running")) {
+            continue;
+        }
+
+        let status = import::process(store, &keys.user_id.s, job_id, deadline).await?;
+
+        tracing::info!("Import job {job_id} of {} is {}", keys.user_id.s, status.as_str());
+    }
+
+    Ok(())
+}
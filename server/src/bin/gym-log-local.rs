@@ -0,0 +1,160 @@
+use std::{convert::Infallible, net::SocketAddr};
+use gym_log::{auth, common, function_handler, import, store};
+use hyper::{Server, service::{make_service_fn, service_fn}};
+use lambda_http::{
+    Body,
+    Error,
+    RequestExt,
+    aws_lambda_events::query_map::QueryMap,
+    http::{Method, StatusCode, header::HeaderValue},
+};
+
+// Serves the API over plain HTTP so that the client can be developed without
+// deploying anything. Each request is handled by the same function as the
+// Lambda. API Gateway handles CORS so that is done here too.
+//
+// The store is chosen with the GYM_LOG_STORE environment variable. It can be
+// either "memory" (the default) or "dynamodb". The port is chosen with the
+// GYM_LOG_PORT environment variable. There's no API Gateway in front of the
+// server so tokens should be verified by setting GYM_LOG_JWT_ISSUER (see auth).
+// There's no worker for import jobs either so they're processed in the
+// background by the server.
+
+const DEFAULT_PORT: u16 = 3000;
+
+/// How long an import job is processed for before its progress is recorded,
+/// which is the longest that the worker can run for.
+const IMPORT_PASS_DURATION: std::time::Duration = std::time::Duration::from_secs(900);
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .init();
+
+    let port = match std::env::var("GYM_LOG_PORT") {
+        Ok(p) => p.parse()?,
+        Err(_) => DEFAULT_PORT,
+    };
+
+    match std::env::var("GYM_LOG_STORE").as_deref() {
+        Ok("dynamodb") => serve(store::DynamoStore::from_env().await, port).await,
+        Ok("memory") | Err(_) => serve(store::MemoryStore::new(), port).await,
+        Ok(s) => Err(format!("unknown store \"{s}\"").into()),
+    }
+}
+
+async fn serve(store: impl store::UserStore + 'static, port: u16) -> Result<(), Error> {
+    // The server needs these for the rest of the process.
+    let store: &'static _ = Box::leak(Box::new(store));
+    let verifier: Option<&'static _> = auth::Verifier::from_env().await?
+        .map(|v| &*Box::leak(Box::new(v)));
+
+    if verifier.is_none() {
+        tracing::warn!("Tokens will not be verified");
+    }
+
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |req| handle(store, verifier, req)))
+    });
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    tracing::info!("Listening on http://{addr}");
+
+    Server::bind(&addr).serve(make_service).await?;
+
+    Ok(())
+}
+
+async fn handle(
+    store: &'static impl store::UserStore,
+    verifier: Option<&auth::Verifier>,
+    req: hyper::Request<hyper::Body>,
+) -> Result<hyper::Response<hyper::Body>, Error> {
+    let (parts, body) = req.into_parts();
+
+    let mut res = if parts.method == Method::OPTIONS {
+        hyper::Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header("Access-Control-Allow-Methods", "DELETE, GET, OPTIONS, POST, PUT")
+            .header("Access-Control-Allow-Headers", "Authorization, Content-Type, If-None-Match")
+            .header("Access-Control-Max-Age", "86400")
+            .body(hyper::Body::empty())?
+    } else {
+        let body = hyper::body::to_bytes(body).await?;
+        let body = if body.is_empty() {
+            Body::Empty
+        } else {
+            Body::from(body.to_vec())
+        };
+
+        let query = parts.uri.query()
+            .unwrap_or_default()
+            .parse::<QueryMap>()?;
+
+        let req = lambda_http::Request::from_parts(parts, body)
+            .with_query_string_parameters(query);
+        let user_id = common::get_user_id(&req).ok();
+
+        match function_handler(store, verifier, req).await {
+            Ok(res) => {
+                if let (StatusCode::ACCEPTED, Some(user_id)) = (res.status(), user_id) {
+                    spawn_import(store, user_id, &res);
+                }
+
+                res.map(|body| match body {
+                    Body::Empty => hyper::Body::empty(),
+                    Body::Text(t) => t.into(),
+                    Body::Binary(b) => b.into(),
+                })
+            }
+            Err(e) => {
+                tracing::error!("{e}");
+
+                hyper::Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(hyper::Body::empty())?
+            }
+        }
+    };
+
+    let headers = res.headers_mut();
+    headers.insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+    headers.insert("Access-Control-Expose-Headers", HeaderValue::from_static("Content-Disposition, ETag, Location, Retry-After"));
+
+    Ok(res)
+}
+
+fn spawn_import(
+    store: &'static impl store::UserStore,
+    user_id: String,
+    res: &lambda_http::Response<Body>,
+) {
+    let job_id = res.headers()
+        .get("Location")
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.strip_prefix("/user/import/"));
+
+    let Some(job_id) = job_id.map(str::to_owned) else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        // Unlike the worker, nothing triggers the job again when it records its
+        // progress, so it's processed again for as long as it's running. There's
+        // no time limit overall so the job runs until it finishes.
+        loop {
+            let deadline = std::time::Instant::now() + IMPORT_PASS_DURATION;
+
+            match import::process(store, &user_id, &job_id, deadline).await {
+                Ok(import::Status::Running) => continue,
+                Ok(status) => tracing::info!("Import job {job_id} is {}", status.as_str()),
+                Err(e) => tracing::error!("Import job {job_id} failed: {e}"),
+            }
+
+            break;
+        }
+    });
+}
@@ -0,0 +1,665 @@
+use std::collections::{BTreeSet, HashMap};
+use serde_json::{Value, json};
+
+// The CSV exports are meant for spreadsheets rather than for syncing. The sets
+// export has one row per set and repeats the workout and exercise fields on
+// each row. Workouts without exercises and exercises without sets get a single
+// row with the remaining fields left empty so that they aren't lost. The
+// measurements export has one row per day and one column per measurement type.
+//
+// Fields are quoted when they contain a comma, a quote or a line break and rows
+// end with CRLF as described in RFC 4180.
+//
+// The exports can be imported again, possibly after being edited or produced by
+// some other program. The rows are converted back into the JSON representation
+// of a user which is then imported like any other snapshot. Rows are grouped
+// into workouts by workout ID and into exercises by exercise ID. Entities
+// without an ID are given one that's derived from the fields that identify
+// them (the start time of a workout, the order and type of an exercise and the
+// index of a set) so that importing the same file again produces the same
+// entities rather than duplicates. When a workout or exercise spans several
+// rows, its fields are taken from the first row.
+//
+// Each entity is validated as soon as it's complete so that errors can refer to
+// the line that it came from.
+
+/// The namespace of the IDs that are derived for entities without an ID.
+const ID_NAMESPACE: uuid::Uuid = uuid::uuid!("6f1d0c4e-5b0a-4a53-9f64-1f3c2a7d8e90");
+
+pub const SET_COLUMNS: [&str; 15] = [
+    "workout_id",
+    "start_time",
+    "finish_time",
+    "workout_notes",
+    "exercise_id",
+    "exercise_order",
+    "exercise_type",
+    "exercise_notes",
+    "set_index",
+    "set_id",
+    "repetitions",
+    "resistance",
+    "speed",
+    "distance",
+    "duration",
+];
+
+pub const MEASUREMENT_COLUMNS: [&str; 2] = ["date", "notes"];
+
+/// Write the workouts, exercises and sets of a user with one row per set.
+pub fn sets_to_csv(user: &super::User) -> String {
+    let mut out = String::new();
+
+    write_row(&mut out, SET_COLUMNS);
+
+    let mut exercises = HashMap::<&str, Vec<(&str, &super::Exercise)>>::new();
+
+    for exercise in user.exercises.iter() {
+        let (workout_id, exercise_id) = exercise.workout_exercise_id
+            .split_once('#')
+            .unwrap_or((exercise.workout_exercise_id, ""));
+        exercises.entry(workout_id).or_default().push((exercise_id, exercise));
+    }
+
+    // Workouts are in chronological order with the ones that haven't started
+    // at the end. Exercises are in the order that they appear in the workout.
+
+    let mut workouts = user.workouts.iter().collect::<Vec<_>>();
+    workouts.sort_by_key(|w| (w.start_time.is_none(), w.start_time, w.workout_id));
+
+    for workout in workouts {
+        let workout_fields = [
+            workout.workout_id.to_owned(),
+            workout.start_time.unwrap_or_default().to_owned(),
+            workout.finish_time.unwrap_or_default().to_owned(),
+            workout.notes.0.to_string(),
+        ];
+
+        let mut workout_exercises = exercises.remove(workout.workout_id).unwrap_or_default();
+        workout_exercises.sort_by_key(|(id, e)| (e.order, *id));
+
+        if workout_exercises.is_empty() {
+            write_row(&mut out, workout_fields.iter().map(String::as_str).chain([""; 11]));
+            continue;
+        }
+
+        for (exercise_id, exercise) in workout_exercises {
+            let exercise_fields = [
+                exercise_id.to_owned(),
+                exercise.order.to_string(),
+                exercise.r#type.0.to_string(),
+                exercise.notes.0.to_string(),
+            ];
+
+            let prefix = workout_fields.iter().chain(exercise_fields.iter()).map(String::as_str);
+
+            if exercise.sets.0.is_empty() {
+                write_row(&mut out, prefix.chain([""; 7]));
+                continue;
+            }
+
+            for (index, set) in exercise.sets.0.iter().enumerate() {
+                let set_fields = [
+                    index.to_string(),
+                    set.set_id.0.to_owned(),
+                    optional_field(set.repetitions),
+                    optional_field(set.resistance),
+                    optional_field(set.speed),
+                    optional_field(set.distance),
+                    optional_field(set.duration),
+                ];
+
+                write_row(&mut out, prefix.clone().chain(set_fields.iter().map(String::as_str)));
+            }
+        }
+    }
+
+    out
+}
+
+/// Write the measurement sets of a user with one row per day and one column per
+/// measurement type.
+pub fn measurements_to_csv(user: &super::User) -> String {
+    let mut out = String::new();
+
+    let types = user.measurement_sets.iter()
+        .flat_map(|m| m.measurements.keys().copied())
+        .collect::<BTreeSet<_>>();
+
+    write_row(&mut out, MEASUREMENT_COLUMNS.into_iter().chain(types.iter().copied()));
+
+    let mut measurement_sets = user.measurement_sets.iter().collect::<Vec<_>>();
+    measurement_sets.sort_by_key(|m| m.date);
+
+    for measurement_set in measurement_sets {
+        let values = types.iter()
+            .map(|t| measurement_set.measurements.get(t).map(f64::to_string).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        write_row(
+            &mut out,
+            [measurement_set.date, &measurement_set.notes.0]
+                .into_iter()
+                .chain(values.iter().map(String::as_str)),
+        );
+    }
+
+    out
+}
+
+fn optional_field(value: Option<u32>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn write_row<'a>(out: &mut String, fields: impl IntoIterator<Item = &'a str>) {
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+
+        if field.contains([',', '"', '\r', '\n']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+
+    out.push_str("\r\n");
+}
+
+/// Convert the sets and measurements exports into the JSON representation of a
+/// user. Either may be empty.
+pub fn csv_to_user_json(sets: &str, measurements: &str) -> Result<Value, super::ApiError> {
+    let (workouts, exercises) = match parse_csv("sets", sets, ',')? {
+        Some(table) => table_to_workouts("sets", &table)?,
+        None => (Vec::new(), Vec::new()),
+    };
+
+    Ok(json!({
+        "measurement_sets": csv_to_measurement_sets(measurements)?,
+        "workouts": workouts,
+        "exercises": exercises,
+    }))
+}
+
+struct WorkoutRows {
+    id: String,
+    value: Value,
+    exercises: Vec<ExerciseRows>,
+    exercise_indexes: HashMap<String, usize>,
+}
+
+struct ExerciseRows {
+    id: String,
+    line: usize,
+    order: Option<u32>,
+    r#type: String,
+    notes: String,
+    sets: Vec<(Option<usize>, Value)>,
+}
+
+/// Convert a table with the columns of the sets export into workouts and
+/// exercises. Errors refer to the lines of the given file.
+pub fn table_to_workouts(
+    file: &str,
+    table: &CsvTable,
+) -> Result<(Vec<Value>, Vec<Value>), super::ApiError> {
+    let mut workouts = Vec::<WorkoutRows>::new();
+    let mut workout_indexes = HashMap::<String, usize>::new();
+
+    for record in table.records.iter() {
+        let row = CsvRow { columns: &table.columns, record };
+        let error = |message: &dyn std::fmt::Display| csv_error(file, record.line, message);
+
+        // Workout
+
+        let start_time = row.get("start_time");
+        let workout_id = match (row.get("workout_id"), start_time) {
+            (id, _) if !id.is_empty() => parse_id(id).map_err(|m| error(&m))?,
+            ("", time) if !time.is_empty() => make_id(&["workout", time]),
+            _ => return Err(error(&"workout_id or start_time is required")),
+        };
+
+        let workout_index = match workout_indexes.get(&workout_id) {
+            Some(i) => *i,
+            None => {
+                let finish_time = row.get("finish_time");
+                let value = json!({
+                    "workout_id": workout_id,
+                    "start_time": Some(start_time).filter(|t| !t.is_empty()),
+                    "finish_time": Some(finish_time).filter(|t| !t.is_empty()),
+                    "notes": row.get("workout_notes"),
+                });
+
+                validate(&value, |s| serde_json::from_str::<super::Workout>(s).map(drop))
+                    .map_err(|m| error(&m))?;
+
+                workouts.push(WorkoutRows {
+                    id: workout_id.clone(),
+                    value,
+                    exercises: Vec::new(),
+                    exercise_indexes: HashMap::new(),
+                });
+                workout_indexes.insert(workout_id.clone(), workouts.len() - 1);
+                workouts.len() - 1
+            }
+        };
+
+        let workout = &mut workouts[workout_index];
+
+        // Exercise
+
+        let exercise_order = row.get("exercise_order");
+        let exercise_type = row.get("exercise_type");
+        let exercise_id = match (row.get("exercise_id"), exercise_order, exercise_type) {
+            ("", "", "") => continue,
+            (id, _, _) if !id.is_empty() => parse_id(id).map_err(|m| error(&m))?,
+            (_, order, r#type) => make_id(&["exercise", &workout_id, order, r#type]),
+        };
+
+        let exercise_index = match workout.exercise_indexes.get(&exercise_id) {
+            Some(i) => *i,
+            None => {
+                let order = parse_optional("exercise_order", exercise_order)
+                    .map_err(|m| error(&m))?;
+
+                workout.exercises.push(ExerciseRows {
+                    id: exercise_id.clone(),
+                    line: record.line,
+                    order,
+                    r#type: exercise_type.to_owned(),
+                    notes: row.get("exercise_notes").to_owned(),
+                    sets: Vec::new(),
+                });
+                workout.exercise_indexes.insert(exercise_id.clone(), workout.exercises.len() - 1);
+                workout.exercises.len() - 1
+            }
+        };
+
+        let exercise = &mut workout.exercises[exercise_index];
+
+        // Set
+
+        const SET_FIELDS: [&str; 5] = ["repetitions", "resistance", "speed", "distance", "duration"];
+
+        let set_index = row.get("set_index");
+        let set_id = row.get("set_id");
+
+        if set_index.is_empty() && set_id.is_empty() && SET_FIELDS.iter().all(|f| row.get(f).is_empty()) {
+            continue;
+        }
+
+        let index = parse_optional::<usize>("set_index", set_index).map_err(|m| error(&m))?;
+        let set_id = match set_id {
+            "" => make_id(&[
+                "set",
+                &workout_id,
+                &exercise_id,
+                &index.unwrap_or(exercise.sets.len()).to_string(),
+            ]),
+            id => id.to_owned(),
+        };
+
+        let mut value = json!({ "set_id": set_id });
+
+        for field in SET_FIELDS {
+            if let Some(v) = parse_optional::<u32>(field, row.get(field)).map_err(|m| error(&m))? {
+                value[field] = v.into();
+            }
+        }
+
+        validate(&value, |s| serde_json::from_str::<super::Set>(s).map(drop))
+            .map_err(|m| error(&m))?;
+
+        exercise.sets.push((index, value));
+    }
+
+    let mut workout_values = Vec::new();
+    let mut exercise_values = Vec::new();
+
+    for workout in workouts {
+        for (position, mut exercise) in workout.exercises.into_iter().enumerate() {
+            exercise.sets.sort_by_key(|(index, _)| *index);
+
+            let value = json!({
+                "workout_exercise_id": format!("{}#{}", workout.id, exercise.id),
+                "order": exercise.order.unwrap_or(position as u32),
+                "type": exercise.r#type,
+                "notes": exercise.notes,
+                "sets": exercise.sets.into_iter().map(|(_, s)| s).collect::<Vec<_>>(),
+            });
+
+            validate(&value, |s| serde_json::from_str::<super::Exercise>(s).map(drop))
+                .map_err(|m| csv_error(file, exercise.line, &m))?;
+
+            exercise_values.push(value);
+        }
+
+        workout_values.push(workout.value);
+    }
+
+    Ok((workout_values, exercise_values))
+}
+
+fn csv_to_measurement_sets(text: &str) -> Result<Vec<Value>, super::ApiError> {
+    const FILE: &str = "measurements";
+
+    let Some(table) = parse_csv(FILE, text, ',')? else {
+        return Ok(Vec::new());
+    };
+
+    if !table.columns.contains_key("date") {
+        return Err(csv_error(FILE, 1, &"missing date column"));
+    }
+
+    let types = table.columns.keys()
+        .filter(|c| !MEASUREMENT_COLUMNS.contains(&c.as_str()))
+        .collect::<Vec<_>>();
+
+    let mut dates = HashMap::new();
+    let mut measurement_sets = Vec::new();
+
+    for record in table.records.iter() {
+        let row = CsvRow { columns: &table.columns, record };
+        let error = |message: &dyn std::fmt::Display| csv_error(FILE, record.line, message);
+
+        let date = row.get("date");
+
+        if !super::is_date(date) {
+            return Err(error(&"invalid date"));
+        }
+
+        if let Some(line) = dates.insert(date, record.line) {
+            return Err(error(&format!("duplicate date from line {line}")));
+        }
+
+        let mut measurements = serde_json::Map::new();
+
+        for r#type in types.iter() {
+            let value = row.get(r#type);
+
+            if value.is_empty() {
+                continue;
+            }
+
+            match value.trim().parse::<f64>() {
+                Ok(v) if v.is_finite() => {
+                    measurements.insert((*r#type).to_owned(), v.into());
+                }
+                _ => return Err(error(&format!("invalid {}", r#type))),
+            }
+        }
+
+        let value = json!({
+            "date": date,
+            "notes": row.get("notes"),
+            "measurements": measurements,
+        });
+
+        validate(&value, |s| serde_json::from_str::<super::MeasurementSet>(s).map(drop))
+            .map_err(|m| error(&m))?;
+
+        measurement_sets.push(value);
+    }
+
+    Ok(measurement_sets)
+}
+
+pub struct CsvTable {
+    /// The index of each column by name.
+    pub columns: HashMap<String, usize>,
+    pub records: Vec<CsvRecord>,
+}
+
+pub struct CsvRecord {
+    /// The line that the record starts on.
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+pub struct CsvRow<'r> {
+    pub columns: &'r HashMap<String, usize>,
+    pub record: &'r CsvRecord,
+}
+
+impl<'r> CsvRow<'r> {
+    /// Get a field by column name. Missing columns and fields are empty.
+    pub fn get(&self, column: &str) -> &'r str {
+        self.columns.get(column)
+            .and_then(|i| self.record.fields.get(*i))
+            .map_or("", String::as_str)
+    }
+}
+
+/// Parse the header and the records that follow it. Returns `None` if the text
+/// is empty. Blank lines are skipped.
+pub fn parse_csv(
+    file: &str,
+    text: &str,
+    delimiter: char,
+) -> Result<Option<CsvTable>, super::ApiError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            _ if quoted => field.push(c),
+            c if c == delimiter => fields.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+
+                if fields.len() > 1 || !fields[0].is_empty() {
+                    records.push(CsvRecord { line: record_line, fields: std::mem::take(&mut fields) });
+                } else {
+                    fields.clear();
+                }
+
+                line += 1;
+                record_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(csv_error(file, record_line, &"unterminated quoted field"));
+    }
+
+    if !fields.is_empty() || !field.is_empty() {
+        fields.push(field);
+        records.push(CsvRecord { line: record_line, fields });
+    }
+
+    let mut records = records.into_iter();
+
+    let Some(header) = records.next() else {
+        return Ok(None);
+    };
+
+    let mut columns = HashMap::new();
+
+    for (i, column) in header.fields.into_iter().enumerate() {
+        let column = column.trim().to_owned();
+
+        if columns.contains_key(&column) {
+            return Err(csv_error(file, header.line, &format!("duplicate column {column}")));
+        }
+
+        columns.insert(column, i);
+    }
+
+    Ok(Some(CsvTable { columns, records: records.collect() }))
+}
+
+pub fn csv_error(file: &str, line: usize, message: &dyn std::fmt::Display) -> super::ApiError {
+    super::ApiError::Validation(format!("{file} line {line}: {message}"))
+}
+
+fn parse_id(id: &str) -> Result<String, &'static str> {
+    if super::is_uuid(id) {
+        Ok(id.to_owned())
+    } else {
+        Err("invalid UUID")
+    }
+}
+
+fn parse_optional<T: std::str::FromStr>(column: &str, value: &str) -> Result<Option<T>, String> {
+    match value.trim() {
+        "" => Ok(None),
+        v => v.parse().map(Some).map_err(|_| format!("invalid {column}")),
+    }
+}
+
+/// Derive an ID from the given names. The hash is used as the random bytes of
+/// a version 4 UUID because that's the only version that's accepted as an ID.
+fn make_id(names: &[&str]) -> String {
+    let hash = uuid::Uuid::new_v5(&ID_NAMESPACE, names.join("#").as_bytes());
+
+    uuid::Builder::from_random_bytes(hash.into_bytes()).into_uuid().to_string()
+}
+
+/// Check that a value can be deserialized as an entity. The value is
+/// serialized first because the entities borrow from the input.
+fn validate(
+    value: &Value,
+    deserialize: impl FnOnce(&str) -> serde_json::Result<()>,
+) -> Result<(), String> {
+    // The position in the error refers to the serialized value rather than the
+    // CSV so it's left out.
+
+    deserialize(&value.to_string()).map_err(|e| {
+        let message = e.to_string();
+        match message.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_owned(),
+            None => message,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKOUT_ID: &str = "0f0c3a56-9f1e-4b53-8d2a-6b1f0e3c2a11";
+    const EXERCISE_ID: &str = "5b7d2c1e-3a4f-4e6d-9c8b-7a6f5e4d3c22";
+    const SET_ID: &str = "8e9f0a1b-2c3d-4e5f-8a7b-6c5d4e3f2a33";
+
+    fn header() -> String {
+        SET_COLUMNS.join(",") + "\r\n"
+    }
+
+    fn import(sets: &str, measurements: &str) -> Value {
+        match csv_to_user_json(sets, measurements) {
+            Ok(user) => user,
+            Err(super::super::ApiError::Validation(message)) => panic!("{message}"),
+            Err(_) => panic!("import failed"),
+        }
+    }
+
+    fn import_error(sets: &str, measurements: &str) -> String {
+        match csv_to_user_json(sets, measurements) {
+            Err(super::super::ApiError::Validation(message)) => message,
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    #[test]
+    fn sets_round_trip() {
+        // A workout with a quoted note, an exercise without sets and a workout
+        // without exercises, which is written last because it hasn't started.
+
+        let sets = header()
+            + &format!("{WORKOUT_ID},2026-10-01T10:00:00Z,2026-10-01T11:00:00Z,\"Hard, \"\"really\"\"\r\nhard\",{EXERCISE_ID},0,biceps-curl,,0,{SET_ID},10,40,,,\r\n")
+            + &format!("{WORKOUT_ID},2026-10-01T10:00:00Z,2026-10-01T11:00:00Z,\"Hard, \"\"really\"\"\r\nhard\",{EXERCISE_ID},0,biceps-curl,,1,{EXERCISE_ID},8,45,,,\r\n")
+            + &format!("{WORKOUT_ID},2026-10-01T10:00:00Z,2026-10-01T11:00:00Z,\"Hard, \"\"really\"\"\r\nhard\",{SET_ID},1,treadmill,Warm up,,,,,,,\r\n")
+            + &format!("{SET_ID},,,,,,,,,,,,,,\r\n");
+        let measurements = "date,notes,body-weight,waist\r\n2026-10-01,,80.5,90\r\n2026-10-02,Rest day,80,\r\n";
+
+        let json = import(&sets, measurements).to_string();
+        let user = serde_json::from_str::<super::super::User>(&json).unwrap();
+
+        assert_eq!(sets_to_csv(&user), sets);
+        assert_eq!(measurements_to_csv(&user), measurements);
+    }
+
+    #[test]
+    fn errors_refer_to_the_line_of_the_row() {
+        // The quoted note spans lines 2 and 3 so the next row starts on line 4.
+
+        let sets = header()
+            + &format!("{WORKOUT_ID},2026-10-01T10:00:00Z,,\"Two\nlines\",,0,biceps-curl,,0,,10,40,,,\n")
+            + &format!("{WORKOUT_ID},2026-10-01T10:00:00Z,,,,0,biceps-curl,,1,,ten,40,,,\n");
+        assert_eq!(import_error(&sets, ""), "sets line 4: invalid repetitions");
+
+        let sets = header() + "\nnot-a-uuid,2026-10-01T10:00:00Z,,,,,,,,,,,,,\n";
+        assert_eq!(import_error(&sets, ""), "sets line 3: invalid UUID");
+
+        let sets = header() + ",,,,,,,,,,,,,,\n";
+        assert_eq!(import_error(&sets, ""), "sets line 2: workout_id or start_time is required");
+
+        let sets = header() + &format!("{WORKOUT_ID},\"2026-10-01T10:00:00Z,,,,,,,,,,,,,\n");
+        assert_eq!(import_error(&sets, ""), "sets line 2: unterminated quoted field");
+
+        let measurements = "date,waist\n2026-10-01,90\n2026-10-02,91\n2026-10-01,92\n";
+        assert_eq!(import_error("", measurements), "measurements line 4: duplicate date from line 2");
+
+        assert_eq!(import_error("", "notes\n"), "measurements line 1: missing date column");
+    }
+
+    #[test]
+    fn derived_ids_are_stable() {
+        let sets = header()
+            + ",2026-10-01T10:00:00Z,,,,0,biceps-curl,,,,10,40,,,\n"
+            + ",2026-10-01T10:00:00Z,,,,0,biceps-curl,,,,8,45,,,\n"
+            + ",2026-10-01T10:00:00Z,,,,1,treadmill,,,,,2,10,1000,360\n";
+
+        let user = import(&sets, "");
+
+        assert_eq!(import(&sets, ""), user);
+        assert_eq!(user["workouts"].as_array().unwrap().len(), 1);
+        assert_eq!(user["exercises"].as_array().unwrap().len(), 2);
+
+        let workout_id = user["workouts"][0]["workout_id"].as_str().unwrap();
+        let exercise = &user["exercises"][0];
+        let (prefix, exercise_id) = exercise["workout_exercise_id"].as_str().unwrap().split_once('#').unwrap();
+        let set_ids = exercise["sets"].as_array().unwrap().iter()
+            .map(|s| s["set_id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(prefix, workout_id);
+        assert!([workout_id, exercise_id, set_ids[0], set_ids[1]].into_iter().all(super::super::is_uuid));
+        assert_ne!(set_ids[0], set_ids[1]);
+
+        // The IDs depend on what identifies the entities rather than on the
+        // values of the sets.
+
+        let edited = import(&sets.replace(",10,40,", ",12,50,"), "");
+        assert_eq!(edited["exercises"][0]["workout_exercise_id"], exercise["workout_exercise_id"]);
+        assert_eq!(edited["exercises"][0]["sets"][0]["set_id"], exercise["sets"][0]["set_id"]);
+        assert_eq!(edited["exercises"][0]["sets"][0]["repetitions"], 12);
+
+        let moved = import(&sets.replace("2026-10-01T10:00:00Z", "2026-10-02T10:00:00Z"), "");
+        assert_ne!(moved["workouts"][0]["workout_id"], workout_id);
+    }
+}
@@ -1,9 +1,6 @@
 use std::{collections::HashMap, borrow::Cow};
 use aws_sdk_dynamodb::types::AttributeValue;
 
-pub const TABLE_USER: &str = "gym-log.User";
-pub const INDEX_MODIFIED_VERSION: &str = "LSI-ModifiedVersion";
-
 pub const COLLECTION_LEN: usize = 1 + u32::ilog10(u32::MAX) as usize;
 
 pub fn get_collection_prefix(collection: u32) -> String {
@@ -18,12 +15,15 @@ pub fn version_from_collection(collection: u32) -> u64 {
     (collection as u64) << 32
 }
 
-type DynamoDbItem = HashMap<String, AttributeValue>;
+pub type DynamoDbItem = HashMap<String, AttributeValue>;
 
+// Items are matched to their entity type by the length of their key and then
+// by the prefix since different types can have keys of the same length.
 macro_rules! item_match {
     ($item:ident, $sk:ident, $deleted:ident, [$({ $type:ty, $entities:ident, $deleted_entities:ident }),*$(,)?]) => {
         match $sk.len() {
-            $(<$type>::KEY_LEN => {
+            $(len if len == <$type>::KEY_LEN
+                && $sk[COLLECTION_LEN + 1..].starts_with(<$type>::KEY_PREFIX) => {
                 let id = &$sk[<$type>::FULL_PREFIX_LEN..];
                 if $deleted {
                     $deleted_entities.push(super::Deleted {
@@ -45,14 +45,20 @@ macro_rules! item_match {
 pub fn db_to_user(
     version: u64,
     filter_collection: bool,
-    items: &Vec<DynamoDbItem>,
-) -> super::User {
+    items: &[DynamoDbItem],
+) -> super::User<'_> {
     let mut measurement_sets = Vec::new();
     let mut workouts = Vec::new();
     let mut exercises = Vec::new();
+    let mut exercise_types = Vec::new();
+    let mut routines = Vec::new();
+    let mut programs = Vec::new();
     let mut deleted_measurement_sets = Vec::new();
     let mut deleted_workouts = Vec::new();
     let mut deleted_exercises = Vec::new();
+    let mut deleted_exercise_types = Vec::new();
+    let mut deleted_routines = Vec::new();
+    let mut deleted_programs = Vec::new();
 
     let collection = super::get_collection_prefix(
         super::collection_from_version(version)
@@ -75,6 +81,9 @@ pub fn db_to_user(
             { super::MeasurementSet, measurement_sets, deleted_measurement_sets },
             { super::Workout, workouts, deleted_workouts },
             { super::Exercise, exercises, deleted_exercises },
+            { super::CustomExerciseType, exercise_types, deleted_exercise_types },
+            { super::Routine, routines, deleted_routines },
+            { super::Program, programs, deleted_programs },
         ]);
     }
 
@@ -83,9 +92,15 @@ pub fn db_to_user(
         measurement_sets,
         workouts,
         exercises,
+        exercise_types,
+        routines,
+        programs,
         deleted_measurement_sets,
         deleted_workouts,
         deleted_exercises,
+        deleted_exercise_types,
+        deleted_routines,
+        deleted_programs,
     }
 }
 
@@ -146,6 +161,30 @@ impl<'a> Identifiable<'a> for super::Exercise<'a> {
     }
 }
 
+impl<'a> Identifiable<'a> for super::CustomExerciseType<'a> {
+    const ID_LEN: usize = UUID_LEN;
+
+    fn get_id(&self) -> &'a str {
+        self.exercise_type_id
+    }
+}
+
+impl<'a> Identifiable<'a> for super::Routine<'a> {
+    const ID_LEN: usize = UUID_LEN;
+
+    fn get_id(&self) -> &'a str {
+        self.routine_id
+    }
+}
+
+impl<'a> Identifiable<'a> for super::Program<'a> {
+    const ID_LEN: usize = UUID_LEN;
+
+    fn get_id(&self) -> &'a str {
+        self.program_id
+    }
+}
+
 // -------- Equivalent -------- //
 
 impl Equivalent for super::MeasurementSet<'_> {
@@ -160,6 +199,7 @@ impl Equivalent for super::Workout<'_> {
         self.start_time == other.start_time
             && self.finish_time == other.finish_time
             && self.notes.0 == other.notes.0
+            && self.planned_session == other.planned_session
     }
 }
 
@@ -186,6 +226,62 @@ impl Equivalent for super::Set<'_> {
     }
 }
 
+impl Equivalent for super::CustomExerciseType<'_> {
+    fn equiv(&self, other: &Self) -> bool {
+        self.name.0 == other.name.0
+            && self.group.0 == other.group.0
+            && self.fields.0 == other.fields.0
+    }
+}
+
+impl Equivalent for super::Routine<'_> {
+    fn equiv(&self, other: &Self) -> bool {
+        self.name.0 == other.name.0
+            && self.exercises.0.len() == other.exercises.0.len()
+            && self.exercises.0.iter()
+                .zip(other.exercises.0.iter())
+                .all(|(a, b)| a.equiv(b))
+    }
+}
+
+impl Equivalent for super::RoutineExercise<'_> {
+    fn equiv(&self, other: &Self) -> bool {
+        self.r#type.0 == other.r#type.0
+            && self.sets.0.len() == other.sets.0.len()
+            && self.sets.0.iter()
+                .zip(other.sets.0.iter())
+                .all(|(a, b)| a.equiv(b))
+    }
+}
+
+impl Equivalent for super::TargetSet {
+    fn equiv(&self, other: &Self) -> bool {
+        self.repetitions == other.repetitions
+            && self.resistance == other.resistance
+    }
+}
+
+impl Equivalent for super::Program<'_> {
+    fn equiv(&self, other: &Self) -> bool {
+        self.name.0 == other.name.0
+            && self.start_date == other.start_date
+            && self.weeks == other.weeks
+            && self.sessions.0.len() == other.sessions.0.len()
+            && self.sessions.0.iter()
+                .zip(other.sessions.0.iter())
+                .all(|(a, b)| a.equiv(b))
+    }
+}
+
+impl Equivalent for super::ProgramSession<'_> {
+    fn equiv(&self, other: &Self) -> bool {
+        self.routine_id == other.routine_id
+            && self.day == other.day
+            && self.repetitions_per_week == other.repetitions_per_week
+            && self.resistance_per_week == other.resistance_per_week
+    }
+}
+
 // -------- ToDynamoDb -------- //
 
 impl<'a> ToDynamoDb<'a> for super::MeasurementSet<'a> {
@@ -231,6 +327,12 @@ impl<'a> ToDynamoDb<'a> for super::Workout<'a> {
             self.notes.0.as_ref().to_owned()
         ));
 
+        if let Some(session) = self.planned_session {
+            item.insert("ProgramId".into(), AttributeValue::S(session.program_id.0.to_owned()));
+            item.insert("PlannedDate".into(), AttributeValue::S(session.date.0.to_owned()));
+            item.insert("PlannedSession".into(), AttributeValue::N(session.session.to_string()));
+        }
+
         insert_modified_version(item, self.modified_version, modified_version);
     }
 }
@@ -288,6 +390,115 @@ impl<'a> ToDynamoDb<'a> for super::Exercise<'a> {
     }
 }
 
+impl<'a> ToDynamoDb<'a> for super::CustomExerciseType<'a> {
+    const KEY_PREFIX: &'static str = "EXERCISE_TYPE#";
+
+    fn insert_dynamo_db(&self,
+        item: &mut DynamoDbItem,
+        modified_version: Option<u64>,
+    ) {
+        item.insert("Name".into(), AttributeValue::S(
+            self.name.0.as_ref().to_owned()
+        ));
+
+        item.insert("Group".into(), AttributeValue::S(
+            self.group.0.as_ref().to_owned()
+        ));
+
+        item.insert("Fields".into(), AttributeValue::L(
+            self.fields.0.iter()
+                .map(|f| AttributeValue::S(String::from(*f)))
+                .collect()
+        ));
+
+        insert_modified_version(item, self.modified_version, modified_version);
+    }
+}
+
+impl<'a> ToDynamoDb<'a> for super::Routine<'a> {
+    const KEY_PREFIX: &'static str = "ROUTINE#";
+
+    fn insert_dynamo_db(&self,
+        item: &mut DynamoDbItem,
+        modified_version: Option<u64>,
+    ) {
+        item.insert("Name".into(), AttributeValue::S(
+            self.name.0.as_ref().to_owned()
+        ));
+
+        item.insert("Exercises".into(), AttributeValue::L(
+            self.exercises.0.iter()
+                .map(|exercise| {
+                    let mut map = HashMap::new();
+
+                    map.insert("Type".into(), AttributeValue::S(
+                        exercise.r#type.0.as_ref().to_owned()
+                    ));
+
+                    map.insert("Sets".into(), AttributeValue::L(
+                        exercise.sets.0.iter()
+                            .map(|set| {
+                                let mut map = HashMap::new();
+
+                                if let Some(a) = set.repetitions {
+                                    map.insert("Repetitions".into(), AttributeValue::N(a.to_string()));
+                                }
+
+                                if let Some(a) = set.resistance {
+                                    map.insert("Resistance".into(), AttributeValue::N(a.to_string()));
+                                }
+
+                                AttributeValue::M(map)
+                            })
+                            .collect()
+                    ));
+
+                    AttributeValue::M(map)
+                })
+                .collect()
+        ));
+
+        insert_modified_version(item, self.modified_version, modified_version);
+    }
+}
+
+impl<'a> ToDynamoDb<'a> for super::Program<'a> {
+    const KEY_PREFIX: &'static str = "PROGRAM#";
+
+    fn insert_dynamo_db(&self,
+        item: &mut DynamoDbItem,
+        modified_version: Option<u64>,
+    ) {
+        item.insert("Name".into(), AttributeValue::S(
+            self.name.0.as_ref().to_owned()
+        ));
+
+        item.insert("StartDate".into(), AttributeValue::S(self.start_date.0.to_owned()));
+        item.insert("Weeks".into(), AttributeValue::N(self.weeks.to_string()));
+
+        item.insert("Sessions".into(), AttributeValue::L(
+            self.sessions.0.iter()
+                .map(|session| {
+                    let mut map = HashMap::new();
+
+                    map.insert("RoutineId".into(), AttributeValue::S(session.routine_id.0.to_owned()));
+                    map.insert("Day".into(), AttributeValue::N(session.day.to_string()));
+                    map.insert("RepetitionsPerWeek".into(), AttributeValue::N(
+                        session.repetitions_per_week.to_string()
+                    ));
+                    map.insert("ResistancePerWeek".into(), AttributeValue::N(
+                        session.resistance_per_week.to_string()
+                    ));
+
+                    AttributeValue::M(map)
+                })
+                .collect()
+        ));
+
+        insert_modified_version(item, self.modified_version, modified_version);
+    }
+}
+
 fn insert_modified_version(
     item: &mut DynamoDbItem,
     entity_version: u64,
@@ -320,6 +531,11 @@ impl<'a> FromDynamoDb<'a> for super::Workout<'a> {
             start_time: item.get("StartTime").map(|a| a.as_s().unwrap().as_str()),
             finish_time: item.get("FinishTime").map(|a| a.as_s().unwrap().as_str()),
             notes: super::MaxLenStr(Cow::Borrowed(item["Notes"].as_s().unwrap())),
+            planned_session: item.get("ProgramId").map(|program_id| super::PlannedSession {
+                program_id: super::Uuid(program_id.as_s().unwrap()),
+                date: super::Date(item["PlannedDate"].as_s().unwrap()),
+                session: super::as_number(&item["PlannedSession"]),
+            }),
             modified_version: super::as_number(&item["ModifiedVersion"]),
         }
     }
@@ -338,7 +554,71 @@ impl<'a> FromDynamoDb<'a> for super::Exercise<'a> {
     }
 }
 
-fn sets_from_dynamo_db(sets: &Vec<AttributeValue>) -> Vec<super::Set> {
+impl<'a> FromDynamoDb<'a> for super::CustomExerciseType<'a> {
+    fn from_dynamo_db(id: &'a str, item: &'a DynamoDbItem) -> Self {
+        Self {
+            exercise_type_id: id,
+            name: super::MaxLenStr(Cow::Borrowed(item["Name"].as_s().unwrap())),
+            group: super::MaxLenStr(Cow::Borrowed(item["Group"].as_s().unwrap())),
+            fields: super::SetFields(item["Fields"].as_l().unwrap().iter()
+                .map(|f| f.as_s().unwrap().as_str())
+                .collect()),
+            modified_version: super::as_number(&item["ModifiedVersion"]),
+        }
+    }
+}
+
+impl<'a> FromDynamoDb<'a> for super::Routine<'a> {
+    fn from_dynamo_db(id: &'a str, item: &'a DynamoDbItem) -> Self {
+        Self {
+            routine_id: id,
+            name: super::MaxLenStr(Cow::Borrowed(item["Name"].as_s().unwrap())),
+            exercises: super::MaxLenVec(item["Exercises"].as_l().unwrap().iter()
+                .map(|exercise| {
+                    let map = exercise.as_m().unwrap();
+                    super::RoutineExercise {
+                        r#type: super::MaxLenStr(Cow::Borrowed(map["Type"].as_s().unwrap())),
+                        sets: super::MaxLenVec(map["Sets"].as_l().unwrap().iter()
+                            .map(|set| {
+                                let map = set.as_m().unwrap();
+                                super::TargetSet {
+                                    repetitions: map.get("Repetitions").map(super::as_number),
+                                    resistance: map.get("Resistance").map(super::as_number),
+                                }
+                            })
+                            .collect()),
+                    }
+                })
+                .collect()),
+            modified_version: super::as_number(&item["ModifiedVersion"]),
+        }
+    }
+}
+
+impl<'a> FromDynamoDb<'a> for super::Program<'a> {
+    fn from_dynamo_db(id: &'a str, item: &'a DynamoDbItem) -> Self {
+        Self {
+            program_id: id,
+            name: super::MaxLenStr(Cow::Borrowed(item["Name"].as_s().unwrap())),
+            start_date: super::Date(item["StartDate"].as_s().unwrap()),
+            weeks: super::as_number(&item["Weeks"]),
+            sessions: super::MaxLenVec(item["Sessions"].as_l().unwrap().iter()
+                .map(|session| {
+                    let map = session.as_m().unwrap();
+                    super::ProgramSession {
+                        routine_id: super::Uuid(map["RoutineId"].as_s().unwrap()),
+                        day: super::as_number(&map["Day"]),
+                        repetitions_per_week: super::as_number(&map["RepetitionsPerWeek"]),
+                        resistance_per_week: super::as_number(&map["ResistancePerWeek"]),
+                    }
+                })
+                .collect()),
+            modified_version: super::as_number(&item["ModifiedVersion"]),
+        }
+    }
+}
+
+fn sets_from_dynamo_db(sets: &[AttributeValue]) -> Vec<super::Set<'_>> {
     sets.iter()
         .map(|set| {
             let map = set.as_m().unwrap();
@@ -366,3 +646,14 @@ pub fn make_key_from_entity<'a, T: ToDynamoDb<'a>>(
 ) -> String {
     make_key_from_id::<T>(collection_prefix, entity.get_id())
 }
+
+pub fn make_deleted_item(modified_version: u64) -> DynamoDbItem {
+    let mut item = HashMap::new();
+
+    item.insert("Deleted".into(), AttributeValue::Bool(true));
+    item.insert("ModifiedVersion".into(), AttributeValue::N(
+        modified_version.to_string()
+    ));
+
+    item
+}
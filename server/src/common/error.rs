@@ -0,0 +1,214 @@
+use lambda_http::{Body, Error, Response, http::{Method, StatusCode}};
+use serde::Serialize;
+
+// Every error response is an RFC 7807 problem details object with an extra
+// `code` member. The code is stable so that the client can branch on the kind
+// of error without relying on the status code alone. For example, a 503 could
+// mean that an import is in progress or that a read was inconsistent.
+
+pub enum ApiError {
+    /// The request body, query string or path was invalid.
+    Validation(String),
+    /// The referenced entity doesn't exist.
+    NotFound,
+    /// The path exists but not with this method.
+    MethodNotAllowed(Vec<Method>),
+    /// The client's version is not the current version. If the client asked
+    /// for them, the changes since the client's version are included in the
+    /// same shape as GET /user.
+    VersionConflict {
+        version: u64,
+        changes: Option<serde_json::Value>,
+    },
+    /// Some of the operations in a batch failed. The operations before
+    /// `applied` were committed and the user's data is now at `version`.
+    BatchFailed {
+        version: u64,
+        applied: usize,
+        failures: Vec<OperationFailure>,
+    },
+    /// Some of the exercises in a third-party export don't map to an exercise
+    /// type. Nothing was imported.
+    UnmappedExercises(Vec<UnmappedExercise>),
+    /// An import is in progress and writes are not allowed.
+    Locked {
+        retry_after: u64,
+    },
+    /// The data changed while it was being read. The request can be retried
+    /// immediately.
+    RetryLater,
+    /// The Authorization header is missing or doesn't contain a valid token.
+    Unauthorized(&'static str),
+    /// Something went wrong while talking to the store.
+    Store(Error),
+    /// Something else went wrong.
+    Internal(Error),
+}
+
+/// The reason that an operation in a batch failed.
+#[derive(Serialize)]
+pub struct OperationFailure {
+    /// Index of the operation within the batch.
+    pub index: usize,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// An exercise name in a third-party export that doesn't map to an exercise
+/// type.
+#[derive(Serialize)]
+pub struct UnmappedExercise {
+    pub name: String,
+    /// The first line that the name appears on.
+    pub line: usize,
+    /// The number of rows with the name.
+    pub rows: usize,
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    r#type: &'static str,
+    title: &'static str,
+    status: u16,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changes: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    applied: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failures: Option<&'a [OperationFailure]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unmapped: Option<&'a [UnmappedExercise]>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Self::VersionConflict { .. } => StatusCode::CONFLICT,
+            Self::BatchFailed { .. } | Self::UnmappedExercises(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            Self::Locked { .. } | Self::RetryLater => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Store(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(_) => "validation",
+            Self::NotFound => "not-found",
+            Self::MethodNotAllowed(_) => "method-not-allowed",
+            Self::VersionConflict { .. } => "version-conflict",
+            Self::BatchFailed { .. } => "batch-failed",
+            Self::UnmappedExercises(_) => "unmapped-exercises",
+            Self::Locked { .. } => "locked",
+            Self::RetryLater => "retry-later",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Store(_) => "store-failure",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    fn detail(&self) -> Option<&str> {
+        match self {
+            Self::Validation(message) => Some(message),
+            Self::Unauthorized(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        let mut builder = Response::builder()
+            .status(status)
+            .header("Content-Type", "application/problem+json");
+
+        match &self {
+            Self::MethodNotAllowed(allowed) => {
+                let allowed = allowed.iter()
+                    .map(Method::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                builder = builder.header("Allow", allowed);
+            }
+            Self::Locked { retry_after } => {
+                builder = builder.header("Retry-After", *retry_after);
+            }
+            Self::RetryLater => {
+                builder = builder.header("Retry-After", 0);
+            }
+            Self::Unauthorized(reason) => {
+                builder = builder.header(
+                    "WWW-Authenticate",
+                    format!("Bearer error=\"invalid_token\", error_description=\"{reason}\""),
+                );
+            }
+            Self::Store(e) | Self::Internal(e) => {
+                // The details of internal errors aren't exposed to the client.
+                tracing::error!("{e}");
+            }
+            _ => {}
+        }
+
+        let (version, changes) = match &self {
+            Self::VersionConflict { version, changes } => (Some(*version), changes.as_ref()),
+            Self::BatchFailed { version, .. } => (Some(*version), None),
+            _ => (None, None),
+        };
+        let (applied, failures) = match &self {
+            Self::BatchFailed { applied, failures, .. } => (Some(*applied), Some(&failures[..])),
+            _ => (None, None),
+        };
+        let unmapped = match &self {
+            Self::UnmappedExercises(unmapped) => Some(&unmapped[..]),
+            _ => None,
+        };
+
+        let body = serde_json::to_string(&Problem {
+            r#type: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            code: self.code(),
+            detail: self.detail(),
+            version,
+            changes,
+            applied,
+            failures,
+            unmapped,
+        }).unwrap();
+
+        builder.body(body.into()).unwrap()
+    }
+}
+
+impl From<crate::store::Error> for ApiError {
+    fn from(e: crate::store::Error) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        // Errors from the store are often passed along by other modules before
+        // they get here.
+        match e.downcast::<crate::store::Error>() {
+            Ok(e) => Self::Store(e),
+            Err(e) => Self::Internal(e),
+        }
+    }
+}
+
+impl From<lambda_http::http::Error> for ApiError {
+    fn from(e: lambda_http::http::Error) -> Self {
+        Self::Internal(e.into())
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use crate::store::UserStore;
+
+// The exercise types and the set fields that each of them records. This mirrors
+// the exercise types in the client (model/api.ts). Every set of an exercise
+// must have exactly the fields that its type records.
+//
+// Users can also define their own exercise types (see CustomExerciseType).
+// Exercises of a custom type use the UUID of the type as their type. Custom
+// types are stored in the collection like any other entity so an exercise can
+// only be checked against its custom type when it's written. Deleting a custom
+// type doesn't affect the exercises that were already written with it.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SetKind {
+    /// Exercises that involve repeating an action multiple times.
+    Repeating,
+    /// Exercises whose speed is variable and depends on how the user performs.
+    Variable,
+    /// Exercises whose speed is fixed and depends on the machine.
+    Fixed,
+}
+
+pub const SET_FIELDS: [&str; 5] = ["repetitions", "resistance", "speed", "distance", "duration"];
+
+pub const EXERCISE_TYPES: [(&str, SetKind); 16] = [
+    ("biceps-curl", SetKind::Repeating),
+    ("chest-press", SetKind::Repeating),
+    ("dumbbell-wrist-curl", SetKind::Repeating),
+    ("fixed-pulldown", SetKind::Repeating),
+    ("leg-curl", SetKind::Repeating),
+    ("leg-extension", SetKind::Repeating),
+    ("pectoral-fly", SetKind::Repeating),
+    ("seated-leg-curl", SetKind::Repeating),
+    ("seated-row", SetKind::Repeating),
+    ("shoulder-press", SetKind::Repeating),
+    ("standing-calf", SetKind::Repeating),
+    ("triceps-extension", SetKind::Repeating),
+    ("elliptical-cross-trainer", SetKind::Variable),
+    ("recumbent-bike", SetKind::Variable),
+    ("upright-bike", SetKind::Variable),
+    ("treadmill", SetKind::Fixed),
+];
+
+impl SetKind {
+    /// The set fields that exercises of this kind record.
+    pub fn fields(self) -> &'static [&'static str] {
+        match self {
+            Self::Repeating => &["repetitions", "resistance"],
+            Self::Variable => &["resistance", "distance", "duration"],
+            Self::Fixed => &["resistance", "speed", "distance", "duration"],
+        }
+    }
+}
+
+pub fn get_set_kind(r#type: &str) -> Option<SetKind> {
+    EXERCISE_TYPES.iter()
+        .find(|(t, _)| *t == r#type)
+        .map(|(_, k)| *k)
+}
+
+/// Check that every set has exactly the given fields. The error names the
+/// offending field.
+pub fn validate_sets(
+    r#type: &str,
+    fields: &[&str],
+    sets: &[super::Set],
+) -> Result<(), String> {
+    for (i, set) in sets.iter().enumerate() {
+        for field in SET_FIELDS {
+            match (fields.contains(&field), set.get_field(field).is_some()) {
+                (true, false) => {
+                    return Err(format!("sets[{i}].{field} is required for {type}"));
+                }
+                (false, true) => {
+                    return Err(format!("sets[{i}].{field} is not recorded for {type}"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the defaults of the target sets of a routine are only for fields
+/// that are recorded.
+pub fn validate_targets(
+    r#type: &str,
+    fields: &[&str],
+    sets: &[super::TargetSet],
+) -> Result<(), String> {
+    for (i, set) in sets.iter().enumerate() {
+        for (field, value) in [("repetitions", set.repetitions), ("resistance", set.resistance)] {
+            if value.is_some() && !fields.contains(&field) {
+                return Err(format!("sets[{i}].{field} is not recorded for {type}"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check an exercise of a custom type against that type, or `None` if the type
+/// doesn't exist.
+pub fn validate_custom_exercise(
+    exercise: &super::Exercise,
+    custom: Option<&super::CustomExerciseType>,
+) -> Result<(), String> {
+    let Some(custom) = custom else {
+        return Err(format!("unknown exercise type {}", exercise.r#type.0));
+    };
+
+    validate_sets(&custom.name.0, &custom.fields.0, &exercise.sets.0)
+}
+
+/// Check an exercise of a routine with a custom type against that type, or
+/// `None` if the type doesn't exist.
+pub fn validate_custom_targets(
+    exercise: &super::RoutineExercise,
+    custom: Option<&super::CustomExerciseType>,
+) -> Result<(), String> {
+    let Some(custom) = custom else {
+        return Err(format!("unknown exercise type {}", exercise.r#type.0));
+    };
+
+    validate_targets(&custom.name.0, &custom.fields.0, &exercise.sets.0)
+}
+
+/// Check an exercise against its custom type in the collection. Exercises of
+/// the built-in types were already checked when they were deserialized. The
+/// type is read before the write so the write must be conditional on the
+/// version that the collection was read at.
+pub async fn check_custom_type(
+    store: &impl UserStore,
+    user_id: &str,
+    collection_prefix: &str,
+    exercise: &super::Exercise<'_>,
+) -> Result<(), super::ApiError> {
+    let type_id = exercise.r#type.0.as_ref();
+
+    if !super::is_uuid(type_id) {
+        return Ok(());
+    }
+
+    let item = get_custom_type(store, user_id, collection_prefix, type_id).await?;
+    let custom = item.as_ref()
+        .map(|item| <super::CustomExerciseType as super::FromDynamoDb>::from_dynamo_db(type_id, item));
+
+    validate_custom_exercise(exercise, custom.as_ref()).map_err(super::ApiError::Validation)
+}
+
+/// Get the item of a custom type in the collection unless it doesn't exist or
+/// was deleted.
+pub async fn get_custom_type(
+    store: &impl UserStore,
+    user_id: &str,
+    collection_prefix: &str,
+    type_id: &str,
+) -> Result<Option<super::DynamoDbItem>, super::ApiError> {
+    let key = super::make_key_from_id::<super::CustomExerciseType>(collection_prefix, type_id);
+
+    Ok(store.get_item(user_id, &key).await?
+        .filter(|item| !item.contains_key("Deleted")))
+}
+
+/// Get the items of the custom types with the given IDs. Types that don't exist
+/// or were deleted are `None`. IDs that aren't UUIDs are built-in types and are
+/// left out.
+pub async fn get_custom_types<'i>(
+    store: &impl UserStore,
+    user_id: &str,
+    collection_prefix: &str,
+    type_ids: &[&'i str],
+) -> Result<HashMap<&'i str, Option<super::DynamoDbItem>>, super::ApiError> {
+    let mut items = HashMap::new();
+
+    for &type_id in type_ids {
+        if super::is_uuid(type_id) && !items.contains_key(type_id) {
+            let item = get_custom_type(store, user_id, collection_prefix, type_id).await?;
+            items.insert(type_id, item);
+        }
+    }
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use super::*;
+
+    const WORKOUT_EXERCISE_ID: &str = "0f0c3a56-9f1e-4b53-8d2a-6b1f0e3c2a11#5b7d2c1e-3a4f-4e6d-9c8b-7a6f5e4d3c22";
+    const SET_ID: &str = "8e9f0a1b-2c3d-4e5f-8a7b-6c5d4e3f2a33";
+    const CUSTOM_TYPE: &str = "3c4d5e6f-7a8b-4c9d-8e0f-1a2b3c4d5e44";
+
+    fn exercise_json(r#type: &str, sets: Vec<Value>) -> String {
+        let sets = sets.into_iter()
+            .map(|mut set| {
+                set["set_id"] = SET_ID.into();
+                set
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "workout_exercise_id": WORKOUT_EXERCISE_ID,
+            "order": 0,
+            "type": r#type,
+            "notes": "",
+            "sets": sets,
+        }).to_string()
+    }
+
+    /// Deserialize an exercise and return the error without its position.
+    fn exercise_error(json: &str) -> Option<String> {
+        serde_json::from_str::<super::super::Exercise>(json).err()
+            .map(|e| e.to_string().split(" at line ").next().unwrap().to_owned())
+    }
+
+    #[test]
+    fn built_in_types_require_exactly_their_fields() {
+        let cases = [
+            ("biceps-curl", json!({ "repetitions": 10, "resistance": 40 })),
+            ("upright-bike", json!({ "resistance": 5, "distance": 5000, "duration": 900 })),
+            ("treadmill", json!({ "resistance": 2, "speed": 10, "distance": 1000, "duration": 360 })),
+        ];
+
+        for (r#type, set) in cases {
+            assert_eq!(exercise_error(&exercise_json(r#type, vec![set.clone()])), None, "{type}");
+
+            for field in set.as_object().unwrap().keys() {
+                let mut missing = set.clone();
+                missing.as_object_mut().unwrap().remove(field);
+
+                assert_eq!(
+                    exercise_error(&exercise_json(r#type, vec![set.clone(), missing])),
+                    Some(format!("sets[1].{field} is required for {type}")),
+                );
+            }
+
+            for field in SET_FIELDS.into_iter().filter(|f| set.get(f).is_none()) {
+                let mut extra = set.clone();
+                extra[field] = 1.into();
+
+                assert_eq!(
+                    exercise_error(&exercise_json(r#type, vec![extra])),
+                    Some(format!("sets[0].{field} is not recorded for {type}")),
+                );
+            }
+        }
+
+        // A field that's recorded can be 0 but not null.
+
+        let json = exercise_json("biceps-curl", vec![json!({ "repetitions": 0, "resistance": 0 })]);
+        assert_eq!(exercise_error(&json), None);
+
+        let json = exercise_json("biceps-curl", vec![json!({ "repetitions": 10, "resistance": null })]);
+        assert_eq!(exercise_error(&json), Some("sets[0].resistance is required for biceps-curl".into()));
+    }
+
+    #[test]
+    fn unknown_built_in_types_are_rejected() {
+        let json = exercise_json("rowing-machine", vec![]);
+        assert_eq!(exercise_error(&json), Some("unknown exercise type rowing-machine".into()));
+    }
+
+    #[test]
+    fn custom_types_are_checked_against_their_fields() {
+        // Exercises of custom types pass deserialization whatever their sets
+        // are because the type is only known when the exercise is written.
+
+        let json = exercise_json(CUSTOM_TYPE, vec![json!({ "duration": 60 })]);
+        assert_eq!(exercise_error(&json), None);
+
+        let exercise = serde_json::from_str::<super::super::Exercise>(&json).unwrap();
+
+        let custom_json = json!({ "name": "Plank", "group": "core", "fields": ["duration"] }).to_string();
+        let custom = serde_json::from_str::<super::super::CustomExerciseType>(&custom_json).unwrap();
+        assert_eq!(validate_custom_exercise(&exercise, Some(&custom)), Ok(()));
+
+        let custom_json = json!({ "name": "Rope", "group": "arms", "fields": ["repetitions", "duration"] }).to_string();
+        let custom = serde_json::from_str::<super::super::CustomExerciseType>(&custom_json).unwrap();
+        assert_eq!(
+            validate_custom_exercise(&exercise, Some(&custom)),
+            Err("sets[0].repetitions is required for Rope".into()),
+        );
+
+        assert_eq!(
+            validate_custom_exercise(&exercise, None),
+            Err(format!("unknown exercise type {CUSTOM_TYPE}")),
+        );
+    }
+
+    #[test]
+    fn custom_types_need_distinct_known_fields() {
+        let error = |fields: Value| {
+            let json = json!({ "name": "Custom", "group": "other", "fields": fields }).to_string();
+            serde_json::from_str::<super::super::CustomExerciseType>(&json).err()
+                .map(|e| e.to_string().split(" at line ").next().unwrap().to_owned())
+        };
+
+        assert_eq!(error(json!(["repetitions", "resistance"])), None);
+        assert!(error(json!([])).is_some_and(|e| e.contains("at least one set field")));
+        assert_eq!(error(json!(["weight"])), Some("unknown set field weight".into()));
+        assert_eq!(error(json!(["speed", "speed"])), Some("duplicate set field speed".into()));
+    }
+
+    #[test]
+    fn routine_targets_are_only_for_recorded_fields() {
+        let targets = serde_json::from_value::<Vec<super::super::TargetSet>>(json!([
+            { "repetitions": 10 },
+            { "resistance": 40 },
+        ])).unwrap();
+
+        assert_eq!(validate_targets("biceps-curl", &["repetitions", "resistance"], &targets), Ok(()));
+        assert_eq!(
+            validate_targets("treadmill", SetKind::Fixed.fields(), &targets),
+            Err("sets[0].repetitions is not recorded for treadmill".into()),
+        );
+        assert_eq!(
+            validate_targets("Plank", &["repetitions"], &targets),
+            Err("sets[1].resistance is not recorded for Plank".into()),
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+// Workouts are rendered as an iCalendar (RFC 5545) feed with one event per
+// workout that has a start time. Workouts that haven't finished have no end so
+// they appear as an instant. The summary lists the exercise types in order,
+// using the names of custom types rather than their UUIDs, and the description
+// is the notes of the workout. The UID is derived from the workout ID so that
+// calendar apps update events rather than duplicating them.
+
+const MAX_LINE_LEN: usize = 75;
+
+/// Render the workouts of a user as a calendar. `now` is used for the DTSTAMP of
+/// each event.
+pub fn workouts_to_ical(user: &super::User, now: u64) -> String {
+    let mut out = String::new();
+
+    write_line(&mut out, "BEGIN:VCALENDAR");
+    write_line(&mut out, "VERSION:2.0");
+    write_line(&mut out, "PRODID:-//gym-log//workouts//EN");
+    write_line(&mut out, "CALSCALE:GREGORIAN");
+    write_line(&mut out, "X-WR-CALNAME:Workouts");
+
+    let mut exercises = HashMap::<&str, Vec<&super::Exercise>>::new();
+
+    for exercise in user.exercises.iter() {
+        let workout_id = exercise.workout_exercise_id
+            .split_once('#')
+            .map_or(exercise.workout_exercise_id, |(w, _)| w);
+        exercises.entry(workout_id).or_default().push(exercise);
+    }
+
+    let custom_types = user.exercise_types.iter()
+        .map(|t| (t.exercise_type_id, t.name.0.as_ref()))
+        .collect::<HashMap<_, _>>();
+
+    let dtstamp = chrono::NaiveDateTime::from_timestamp_opt(now as i64, 0)
+        .unwrap_or_default()
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string();
+
+    let mut workouts = user.workouts.iter()
+        .filter(|w| w.start_time.is_some())
+        .collect::<Vec<_>>();
+    workouts.sort_by_key(|w| (w.start_time, w.workout_id));
+
+    for workout in workouts {
+        let mut workout_exercises = exercises.remove(workout.workout_id).unwrap_or_default();
+        workout_exercises.sort_by_key(|e| e.order);
+
+        let summary = if workout_exercises.is_empty() {
+            "Workout".to_owned()
+        } else {
+            workout_exercises.iter()
+                .map(|e| {
+                    let r#type = e.r#type.0.as_ref();
+                    custom_types.get(r#type).copied().unwrap_or(r#type)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        write_line(&mut out, "BEGIN:VEVENT");
+        write_line(&mut out, &format!("UID:{}@gym-log", workout.workout_id));
+        write_line(&mut out, &format!("DTSTAMP:{dtstamp}"));
+        write_line(&mut out, &format!("DTSTART:{}", format_time(workout.start_time.unwrap())));
+
+        if let Some(finish_time) = workout.finish_time {
+            write_line(&mut out, &format!("DTEND:{}", format_time(finish_time)));
+        }
+
+        write_line(&mut out, &format!("SUMMARY:{}", escape_text(&summary)));
+
+        if !workout.notes.0.is_empty() {
+            write_line(&mut out, &format!("DESCRIPTION:{}", escape_text(&workout.notes.0)));
+        }
+
+        write_line(&mut out, "END:VEVENT");
+    }
+
+    write_line(&mut out, "END:VCALENDAR");
+
+    out
+}
+
+/// Convert an ISO 8601 time like 2024-01-01T10:00:00Z into the basic format
+/// like 20240101T100000Z.
+fn format_time(time: &str) -> String {
+    time.chars().filter(|c| *c != '-' && *c != ':').collect()
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Write a content line, folding it so that no line is longer than 75 octets.
+/// Continuation lines start with a space.
+fn write_line(out: &mut String, line: &str) {
+    let mut len = 0;
+
+    for c in line.chars() {
+        if len + c.len_utf8() > MAX_LINE_LEN {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+
+        out.push(c);
+        len += c.len_utf8();
+    }
+
+    out.push_str("\r\n");
+}
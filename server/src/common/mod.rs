@@ -1,13 +1,21 @@
+mod csv;
 mod db_conv;
 mod db_util;
+mod error;
+mod exercise_type;
+mod ical;
 mod model;
 mod request;
 mod response;
 mod time;
 mod version;
 
+pub use csv::*;
 pub use db_conv::*;
 pub use db_util::*;
+pub use error::*;
+pub use exercise_type::*;
+pub use ical::*;
 pub use model::*;
 pub use request::*;
 pub use response::*;
@@ -4,9 +4,13 @@ use serde::{Serialize, Deserialize};
 pub const MAX_EXERCISES: usize = 25;
 pub const MAX_SETS: usize = 25;
 pub const MAX_TYPE_LEN: usize = 100;
+pub const MAX_NAME_LEN: usize = 100;
 pub const MAX_NOTES_LEN: usize = 10000;
+pub const MAX_SESSIONS: usize = 14;
+pub const MAX_WEEKS: u32 = 52;
+pub const MAX_RESISTANCE_PER_WEEK: f64 = 1000.0;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct User<'a> {
     /// The current version of the user's data.
     #[serde(skip_deserializing)]
@@ -17,6 +21,21 @@ pub struct User<'a> {
     pub workouts: Vec<Workout<'a>>,
     #[serde(borrow)]
     pub exercises: Vec<Exercise<'a>>,
+    /// The exercise types defined by the user. Snapshots from before custom
+    /// types existed don't have them.
+    #[serde(borrow)]
+    #[serde(default)]
+    pub exercise_types: Vec<CustomExerciseType<'a>>,
+    /// The routines that workouts can be created from. Snapshots from before
+    /// routines existed don't have them.
+    #[serde(borrow)]
+    #[serde(default)]
+    pub routines: Vec<Routine<'a>>,
+    /// The training programs that schedule routines. Snapshots from before
+    /// programs existed don't have them.
+    #[serde(borrow)]
+    #[serde(default)]
+    pub programs: Vec<Program<'a>>,
     /// A list of measurements that were deleted since the given version.
     #[serde(borrow)]
     #[serde(skip_serializing_if="Vec::is_empty")]
@@ -32,6 +51,22 @@ pub struct User<'a> {
     #[serde(skip_serializing_if="Vec::is_empty")]
     #[serde(skip_deserializing)]
     pub deleted_exercises: Vec<Deleted<'a>>,
+    /// A list of custom exercise types that were deleted since the given
+    /// version.
+    #[serde(borrow)]
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    #[serde(skip_deserializing)]
+    pub deleted_exercise_types: Vec<Deleted<'a>>,
+    /// A list of routines that were deleted since the given version.
+    #[serde(borrow)]
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    #[serde(skip_deserializing)]
+    pub deleted_routines: Vec<Deleted<'a>>,
+    /// A list of programs that were deleted since the given version.
+    #[serde(borrow)]
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    #[serde(skip_deserializing)]
+    pub deleted_programs: Vec<Deleted<'a>>,
 }
 
 pub struct Deleted<'a> {
@@ -77,11 +112,32 @@ pub struct Workout<'a> {
     /// Any user provided notes associated with the workout.
     #[serde(borrow)]
     pub notes: MaxLenStr<'a, MAX_NOTES_LEN>,
+    /// The session of a program that the workout was performed for. It isn't
+    /// checked against the programs and is left as is when the program is
+    /// deleted or its sessions change, so it may not refer to a session.
+    #[serde(borrow)]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub planned_session: Option<PlannedSession<'a>>,
     #[serde(skip)]
     pub modified_version: u64,
 }
 
+/// A reference to a session in the schedule of a program.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlannedSession<'a> {
+    /// UUID of the program.
+    #[serde(borrow)]
+    pub program_id: Uuid<'a>,
+    /// The date that the session was scheduled for.
+    #[serde(borrow)]
+    pub date: Date<'a>,
+    /// Index of the session within the sessions of the program.
+    pub session: u32,
+}
+
 #[derive(Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Exercise<'a> {
     /// UUID of the workout concatenated with the UUID of the exercise separated
     /// by a `#`.
@@ -90,7 +146,8 @@ pub struct Exercise<'a> {
     /// Index of the exercise within the workout.
     pub order: u32,
     /// The type of exercise which defines the meaning of various properties on
-    /// sets.
+    /// sets. This is either one of the built-in types or the UUID of a custom
+    /// exercise type.
     #[serde(borrow)]
     pub r#type: MaxLenStr<'a, MAX_TYPE_LEN>,
     /// Any user provided notes associated with the exercise.
@@ -103,6 +160,219 @@ pub struct Exercise<'a> {
     pub modified_version: u64,
 }
 
+// The derived implementations are used by the implementations below which also
+// check that the sets match the exercise type.
+
+impl<'a> Serialize for Exercise<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        Exercise::serialize(self, serializer)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Exercise<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let exercise = Exercise::deserialize(deserializer)?;
+        let r#type = exercise.r#type.0.as_ref();
+
+        // Custom types are stored alongside the exercises so they can't be
+        // checked here. They're checked before the exercise is written (see
+        // check_custom_types).
+
+        if super::is_uuid(r#type) {
+            return Ok(exercise);
+        }
+
+        let Some(kind) = super::get_set_kind(r#type) else {
+            return Err(serde::de::Error::custom(format!("unknown exercise type {type}")));
+        };
+
+        super::validate_sets(r#type, kind.fields(), &exercise.sets.0)
+            .map_err(serde::de::Error::custom)?;
+
+        Ok(exercise)
+    }
+}
+
+/// An exercise type defined by the user for a machine that isn't one of the
+/// built-in types.
+#[derive(Serialize, Deserialize)]
+pub struct CustomExerciseType<'a> {
+    /// UUID of the exercise type. Exercises of this type use it as their type.
+    #[serde(default)]
+    pub exercise_type_id: &'a str,
+    /// The name that is displayed for the exercise type.
+    #[serde(borrow)]
+    pub name: MaxLenStr<'a, MAX_NAME_LEN>,
+    /// The category that the exercise type is grouped under, such as "legs" or
+    /// "cardio".
+    #[serde(borrow)]
+    pub group: MaxLenStr<'a, MAX_TYPE_LEN>,
+    /// The set fields that exercises of this type record.
+    #[serde(borrow)]
+    pub fields: SetFields<'a>,
+    #[serde(skip)]
+    pub modified_version: u64,
+}
+
+/// A template that a workout can be created from.
+#[derive(Serialize, Deserialize)]
+pub struct Routine<'a> {
+    /// UUID of the routine.
+    #[serde(default)]
+    pub routine_id: &'a str,
+    /// The name that is displayed for the routine.
+    #[serde(borrow)]
+    pub name: MaxLenStr<'a, MAX_NAME_LEN>,
+    /// The exercises of the routine in the order that they're performed.
+    #[serde(borrow)]
+    pub exercises: MaxLenVec<RoutineExercise<'a>, MAX_EXERCISES>,
+    #[serde(skip)]
+    pub modified_version: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct RoutineExercise<'a> {
+    /// The type of the exercise, which is either one of the built-in types or
+    /// the UUID of a custom exercise type.
+    #[serde(borrow)]
+    pub r#type: MaxLenStr<'a, MAX_TYPE_LEN>,
+    /// The sets to create when the routine is instantiated.
+    pub sets: MaxLenVec<TargetSet, MAX_SETS>,
+}
+
+impl<'a> Serialize for RoutineExercise<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        RoutineExercise::serialize(self, serializer)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for RoutineExercise<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let exercise = RoutineExercise::deserialize(deserializer)?;
+        let r#type = exercise.r#type.0.as_ref();
+
+        // As with exercises, custom types are checked before the routine is
+        // written.
+
+        if super::is_uuid(r#type) {
+            return Ok(exercise);
+        }
+
+        let Some(kind) = super::get_set_kind(r#type) else {
+            return Err(serde::de::Error::custom(format!("unknown exercise type {type}")));
+        };
+
+        super::validate_targets(r#type, kind.fields(), &exercise.sets.0)
+            .map_err(serde::de::Error::custom)?;
+
+        Ok(exercise)
+    }
+}
+
+/// A set of a routine. The values are the defaults of the set that is created
+/// when the routine is instantiated. The other fields of the set are 0.
+#[derive(Serialize, Deserialize)]
+pub struct TargetSet {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repetitions: Option<u32>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resistance: Option<u32>,
+}
+
+/// A schedule of routines over a number of weeks.
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct Program<'a> {
+    /// UUID of the program.
+    #[serde(default)]
+    pub program_id: &'a str,
+    /// The name that is displayed for the program.
+    #[serde(borrow)]
+    pub name: MaxLenStr<'a, MAX_NAME_LEN>,
+    /// The date of the first day of the first week.
+    #[serde(borrow)]
+    pub start_date: Date<'a>,
+    /// The number of weeks that the program runs for.
+    pub weeks: u32,
+    /// The sessions that are repeated every week.
+    #[serde(borrow)]
+    pub sessions: MaxLenVec<ProgramSession<'a>, MAX_SESSIONS>,
+    #[serde(skip)]
+    pub modified_version: u64,
+}
+
+impl<'a> Serialize for Program<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        Program::serialize(self, serializer)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Program<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let program = Program::deserialize(deserializer)?;
+
+        if !(1..=MAX_WEEKS).contains(&program.weeks) {
+            return Err(serde::de::Error::custom(format!("weeks must be from 1 to {MAX_WEEKS}")));
+        }
+
+        if let Some(i) = program.sessions.0.iter().position(|s| s.day > 6) {
+            return Err(serde::de::Error::custom(format!("sessions[{i}].day must be from 0 to 6")));
+        }
+
+        // The resistance is stored as a DynamoDB number so it's limited to a
+        // few decimal places to keep it within the precision of one.
+
+        let is_invalid_resistance = |r: f64| {
+            !(-MAX_RESISTANCE_PER_WEEK..=MAX_RESISTANCE_PER_WEEK).contains(&r)
+                || (r * 1000.0).round() / 1000.0 != r
+        };
+
+        let invalid = program.sessions.0.iter()
+            .position(|s| is_invalid_resistance(s.resistance_per_week));
+
+        if let Some(i) = invalid {
+            let max = MAX_RESISTANCE_PER_WEEK;
+            return Err(serde::de::Error::custom(format!(
+                "sessions[{i}].resistance_per_week must be from -{max} to {max} with no more than 3 decimal places"
+            )));
+        }
+
+        Ok(program)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProgramSession<'a> {
+    /// UUID of the routine that is performed in the session.
+    #[serde(borrow)]
+    pub routine_id: Uuid<'a>,
+    /// The day of the week that the session is on, counting from the weekday of
+    /// the start date.
+    pub day: u32,
+    /// The number of repetitions added to each set for every week since the
+    /// start.
+    #[serde(default)]
+    pub repetitions_per_week: i32,
+    /// The resistance added to each set for every week since the start.
+    #[serde(default)]
+    pub resistance_per_week: f64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Set<'a> {
     /// UUID of the set.
@@ -131,6 +401,20 @@ pub struct Set<'a> {
     pub duration: Option<u32>,
 }
 
+impl Set<'_> {
+    /// Get a field by name, where the name is one of SET_FIELDS.
+    pub fn get_field(&self, field: &str) -> Option<u32> {
+        match field {
+            "repetitions" => self.repetitions,
+            "resistance" => self.resistance,
+            "speed" => self.speed,
+            "distance" => self.distance,
+            "duration" => self.duration,
+            _ => None,
+        }
+    }
+}
+
 fn deserialize_time<'de: 'a, 'a, D>(d: D) -> Result<Option<&'a str>, D::Error>
     where D: serde::Deserializer<'de>
 {
@@ -147,7 +431,7 @@ fn deserialize_time<'de: 'a, 'a, D>(d: D) -> Result<Option<&'a str>, D::Error>
 
 /// A wrapper around a &str that validates it is a UUID when deserializing.
 #[repr(transparent)]
-#[derive(Serialize)]
+#[derive(Clone, Copy, PartialEq, Serialize)]
 pub struct Uuid<'a>(pub &'a str);
 
 impl<'de: 'a, 'a> Deserialize<'de> for Uuid<'a> {
@@ -163,6 +447,55 @@ impl<'de: 'a, 'a> Deserialize<'de> for Uuid<'a> {
     }
 }
 
+/// A wrapper around a &str that validates it is a date when deserializing.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub struct Date<'a>(pub &'a str);
+
+impl<'de: 'a, 'a> Deserialize<'de> for Date<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let s = <&str>::deserialize(deserializer)?;
+        if super::is_date(s) {
+            Ok(Date(s))
+        } else {
+            Err(serde::de::Error::custom("invalid date"))
+        }
+    }
+}
+
+/// A wrapper around a list of set field names that validates that each one is
+/// in SET_FIELDS, that there are no duplicates and that there is at least one
+/// when deserializing.
+#[repr(transparent)]
+#[derive(Serialize)]
+pub struct SetFields<'a>(pub Vec<&'a str>);
+
+impl<'de: 'a, 'a> Deserialize<'de> for SetFields<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        let v = Vec::<&str>::deserialize(deserializer)?;
+
+        if v.is_empty() {
+            return Err(serde::de::Error::custom("at least one set field"));
+        }
+
+        for (i, field) in v.iter().enumerate() {
+            if !super::SET_FIELDS.contains(field) {
+                return Err(serde::de::Error::custom(format!("unknown set field {field}")));
+            }
+
+            if v[..i].contains(field) {
+                return Err(serde::de::Error::custom(format!("duplicate set field {field}")));
+            }
+        }
+
+        Ok(SetFields(v))
+    }
+}
+
 /// A wrapper around a Vec<T> that validates its length when deserializing.
 #[repr(transparent)]
 #[derive(Serialize)]
@@ -235,3 +568,33 @@ impl<'a> UserField<'a> for Exercise<'a> {
         &user.deleted_exercises
     }
 }
+
+impl<'a> UserField<'a> for CustomExerciseType<'a> {
+    fn extract_from_user<'b>(user: &'b User<'a>) -> &'b [Self] {
+        &user.exercise_types
+    }
+
+    fn extract_deleted_from_user<'b>(user: &'b User<'a>) -> &'b [Deleted<'a>] {
+        &user.deleted_exercise_types
+    }
+}
+
+impl<'a> UserField<'a> for Routine<'a> {
+    fn extract_from_user<'b>(user: &'b User<'a>) -> &'b [Self] {
+        &user.routines
+    }
+
+    fn extract_deleted_from_user<'b>(user: &'b User<'a>) -> &'b [Deleted<'a>] {
+        &user.deleted_routines
+    }
+}
+
+impl<'a> UserField<'a> for Program<'a> {
+    fn extract_from_user<'b>(user: &'b User<'a>) -> &'b [Self] {
+        &user.programs
+    }
+
+    fn extract_deleted_from_user<'b>(user: &'b User<'a>) -> &'b [Deleted<'a>] {
+        &user.deleted_programs
+    }
+}
@@ -1,10 +1,28 @@
 use base64::Engine;
-use lambda_http::{Request, http::StatusCode};
+use chrono::NaiveDate;
+use lambda_http::{Request, RequestExt};
 use serde::Deserialize;
 
-pub fn get_user_id(req: &Request) -> String {
-    // API Gateway validates that the header exists and the JWT within has a
-    // valid signature. We can safely assume that the JWT is completely valid.
+/// Get the token from the Authorization header. The token may optionally be
+/// prefixed with "Bearer". If there isn't a token, the reason is returned.
+pub fn get_access_token(req: &Request) -> Result<&str, &'static str> {
+    let header = match req.headers().get("Authorization").map(|h| h.to_str()) {
+        Some(Ok(h)) => h.trim(),
+        Some(Err(_)) => return Err("malformed Authorization header"),
+        None => return Err("missing Authorization header"),
+    };
+
+    match header.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => Ok(token.trim()),
+        Some(_) => Err("unsupported authorization scheme"),
+        None => Ok(header),
+    }
+}
+
+pub fn get_user_id(req: &Request) -> Result<String, super::ApiError> {
+    // Either API Gateway or the verifier in auth has validated that the JWT has
+    // a valid signature by this point. We only need to check that it's well
+    // formed.
 
     #[derive(Deserialize)]
     struct Claims {
@@ -13,25 +31,57 @@ pub fn get_user_id(req: &Request) -> String {
 
     let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
 
-    let access_token = req.headers()["Authorization"].as_bytes();
-    let mut parts = access_token.split(|c| *c == b'.');
+    let access_token = get_access_token(req).map_err(super::ApiError::Unauthorized)?;
+
+    let mut parts = access_token.split('.');
 
     parts.next();
 
-    let claims_b64 = parts.next().unwrap();
-    let claims_bytes = engine.decode(claims_b64).unwrap();
-    let claims: Claims = serde_json::from_slice(&claims_bytes).unwrap();
+    let Some(claims_b64) = parts.next() else {
+        return Err(super::ApiError::Unauthorized("malformed token"));
+    };
+
+    let claims = engine.decode(claims_b64)
+        .ok()
+        .and_then(|b| serde_json::from_slice::<Claims>(&b).ok());
 
-    claims.sub
+    match claims {
+        Some(c) => Ok(c.sub),
+        None => Err(super::ApiError::Unauthorized("malformed token")),
+    }
 }
 
 pub fn parse_request_json<'de, T: Deserialize<'de>>(
     req: &'de Request,
-) -> Result<T, super::Result> {
-    match serde_json::from_slice::<T>(req.body().as_ref()) {
-        Ok(t) => Ok(t),
-        Err(e) => Err(super::error_response(StatusCode::BAD_REQUEST, &e.to_string()))
-    }
+) -> Result<T, super::ApiError> {
+    serde_json::from_slice::<T>(req.body().as_ref())
+        .map_err(|e| super::ApiError::Validation(e.to_string()))
+}
+
+/// Check whether the If-None-Match header matches the given entity tag. The
+/// comparison is weak so the `W/` prefix is ignored.
+pub fn if_none_match(req: &Request, etag: &str) -> bool {
+    let Some(Ok(header)) = req.headers().get("If-None-Match").map(|h| h.to_str()) else {
+        return false;
+    };
+
+    let etag = etag.trim_start_matches("W/");
+
+    header.split(',')
+        .map(str::trim)
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+/// Check whether the Accept header lists the given media type. Quality values
+/// aren't considered.
+pub fn accepts(req: &Request, media_type: &str) -> bool {
+    let Some(Ok(header)) = req.headers().get("Accept").map(|h| h.to_str()) else {
+        return false;
+    };
+
+    header.split(',')
+        .map(|t| t.split(';').next().unwrap().trim())
+        .any(|t| t.eq_ignore_ascii_case(media_type))
 }
 
 pub fn is_uuid(id: &str) -> bool {
@@ -67,22 +117,32 @@ pub fn is_uuid(id: &str) -> bool {
     true
 }
 
-pub fn validate_uuid(id: &str) -> Result<(), super::Result> {
-    if is_uuid(id) {
-        Ok(())
-    } else {
-        Err(super::error_response(StatusCode::BAD_REQUEST, "invalid UUID in path"))
-    }
+pub fn is_date(date: &str) -> bool {
+    parse_date(date).is_some()
 }
 
-pub fn is_date(date: &str) -> bool {
-    chrono::NaiveDate::parse_from_str(date, "%F").is_ok()
+pub fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%F").ok()
 }
 
-pub fn validate_date(date: &str) -> Result<(), super::Result> {
-    if is_date(date) {
-        Ok(())
-    } else {
-        Err(super::error_response(StatusCode::BAD_REQUEST, "invalid date in path"))
+/// Get the optional from and to dates of the query string. If both are given,
+/// from must not be after to.
+pub fn get_date_range(
+    req: &Request,
+) -> Result<(Option<NaiveDate>, Option<NaiveDate>), super::ApiError> {
+    let query_map = req.query_string_parameters();
+    let parse = |name| match query_map.first(name) {
+        Some(d) => parse_date(d)
+            .map(Some)
+            .ok_or_else(|| super::ApiError::Validation(format!("invalid {name} date"))),
+        None => Ok(None),
+    };
+    let from = parse("from")?;
+    let to = parse("to")?;
+
+    if from.zip(to).is_some_and(|(from, to)| from > to) {
+        return Err(super::ApiError::Validation("from date is after to date".into()));
     }
+
+    Ok((from, to))
 }
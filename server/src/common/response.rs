@@ -1,36 +1,51 @@
-use lambda_http::{Response, Body, Error, http::StatusCode};
+use lambda_http::{Response, Body, http::{StatusCode, header::{ETAG, HeaderValue}}};
 use serde::Serialize;
 
-pub type Result = std::result::Result<Response<Body>, Error>;
+pub type Result = std::result::Result<Response<Body>, super::ApiError>;
 
 pub fn empty_response(status: StatusCode) -> Result {
-    Response::builder()
+    Ok(Response::builder()
         .status(status)
-        .body(().into())
-        .map_err(|e| e.into())
-}
-
-pub fn retry_later_response(delay_s: u64) -> Result {
-    Response::builder()
-        .status(StatusCode::SERVICE_UNAVAILABLE)
-        .header("Retry-After", delay_s)
-        .body(().into())
-        .map_err(|e| e.into())
+        .body(().into())?)
 }
 
 pub fn json_response<T: Serialize>(status: StatusCode, value: T) -> Result {
-    Response::builder()
+    Ok(Response::builder()
         .status(status)
         .header("Content-Type", "application/json")
-        .body(serde_json::to_string(&value).unwrap().into())
-        .map_err(|e| e.into())
+        .body(serde_json::to_string(&value).unwrap().into())?)
+}
+
+pub fn csv_response(status: StatusCode, body: String, filename: &str) -> Result {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "text/csv; charset=utf-8")
+        .header("Content-Disposition", format!("attachment; filename=\"{filename}\""))
+        .body(body.into())?)
 }
 
-pub fn error_response(status: StatusCode, message: &str) -> Result {
-    #[derive(Serialize)]
-    struct Error<'a> {
-        message: &'a str,
-    }
+pub fn ical_response(status: StatusCode, body: String) -> Result {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "text/calendar; charset=utf-8")
+        .body(body.into())?)
+}
+
+/// The entity tag for a response that represents the user's data at a version.
+/// It's weak because the representation also depends on the query parameters.
+pub fn version_etag(version: u64) -> String {
+    format!("W/\"{version}\"")
+}
+
+pub fn not_modified_response(etag: &str) -> Result {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(ETAG, etag)
+        .body(().into())?)
+}
 
-    json_response(status, Error { message })
+pub fn with_etag(result: Result, etag: &str) -> Result {
+    let mut res = result?;
+    res.headers_mut().insert(ETAG, HeaderValue::from_str(etag).unwrap());
+    Ok(res)
 }
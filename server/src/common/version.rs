@@ -1,18 +1,7 @@
 use std::{ops::ControlFlow, collections::HashMap};
-use aws_sdk_dynamodb::{
-    operation::transact_write_items::builders::TransactWriteItemsFluentBuilder,
-    types::{
-        AttributeValue,
-        CancellationReason,
-        ConditionCheck,
-        Put,
-        ReturnValuesOnConditionCheckFailure,
-        TransactWriteItem,
-        Update,
-    },
-};
-use lambda_http::{Request, http::StatusCode};
+use lambda_http::{Request, RequestExt, http::StatusCode};
 use serde::Deserialize;
+use crate::store::{TransactItem, UserStore, WriteOutcome};
 
 // When an item is edited in some way, the modified version of that item is
 // updated and the root version for all of the user's data is also updated. The
@@ -26,7 +15,10 @@ use serde::Deserialize;
 // client will need to get the changes made since its own version before trying
 // again. It may find that it's trying to modify something that was modified by
 // another client of the same user (a merge conflict), in which case the user
-// will need to be prompted on how to resolve it.
+// will need to be prompted on how to resolve it. The 409 response includes the
+// current version. If the request has the `changes=true` query parameter, then
+// the response also includes the changes since the client's version so that
+// the client doesn't need to request them separately.
 //
 // When deleting specifically, there are other failure cases. If the request
 // references an item that doesn't exist at all, that's an indication of a bug
@@ -54,27 +46,26 @@ pub struct VersionModifyReq<T> {
 }
 
 pub async fn version_delete<'a, T: super::ToDynamoDb<'a>>(
+    store: &impl UserStore,
     req: &Request,
     id: &str,
 ) -> super::Result {
-    let client_version = match super::parse_request_json::<VersionDeleteReq>(req) {
-        Ok(b) => b.version,
-        Err(r) => return r,
-    };
+    let client_version = super::parse_request_json::<VersionDeleteReq>(req)?.version;
     let collection_prefix = super::get_collection_prefix(
         super::collection_from_version(client_version)
     );
     let key = super::make_key_from_id::<T>(&collection_prefix, id);
 
     version_apply(
+        store,
         req,
         client_version,
-        |builder, user_id, new_version| {
-            version_delete_item(builder, user_id, key, new_version)
+        |items, new_version| {
+            items.push(TransactItem::Delete { id: key, new_version });
         },
-        |reasons| {
-            if reasons[0].code() == Some("ConditionalCheckFailed") {
-                return ControlFlow::Break(super::empty_response(StatusCode::NOT_FOUND));
+        |failed| {
+            if failed[0] {
+                return ControlFlow::Break(super::ApiError::NotFound);
             }
 
             ControlFlow::Continue(())
@@ -83,164 +74,172 @@ pub async fn version_delete<'a, T: super::ToDynamoDb<'a>>(
 }
 
 pub async fn version_modify<'r, T, P>(
+    store: &impl UserStore,
     req: &'r Request,
     patch: P,
 ) -> super::Result
     where
         T: Deserialize<'r>,
-        P: FnOnce(TransactWriteItemsFluentBuilder, T, String, u64) -> TransactWriteItemsFluentBuilder,
+        P: FnOnce(&mut Vec<TransactItem>, T, u64),
 {
-    version_modify_checked(req, patch, |_| ControlFlow::Continue(())).await
+    version_modify_checked(store, req, patch, |_| ControlFlow::Continue(())).await
 }
 
 pub async fn version_modify_checked<'r, T, P, C>(
+    store: &impl UserStore,
     req: &'r Request,
     patch: P,
     check: C,
 ) -> super::Result
     where
         T: Deserialize<'r>,
-        P: FnOnce(TransactWriteItemsFluentBuilder, T, String, u64) -> TransactWriteItemsFluentBuilder,
-        C: FnOnce(&[CancellationReason]) -> ControlFlow<super::Result, ()>,
+        P: FnOnce(&mut Vec<TransactItem>, T, u64),
+        C: FnOnce(&[bool]) -> ControlFlow<super::ApiError, ()>,
 {
-    let body = match super::parse_request_json::<VersionModifyReq<T>>(req) {
-        Ok(b) => b,
-        Err(e) => return e,
-    };
+    let body = super::parse_request_json::<VersionModifyReq<T>>(req)?;
 
     version_apply(
+        store,
         req,
         body.version,
-        |builder, user_id, new_version| {
-            patch(builder, body.item, user_id, new_version)
+        |items, new_version| {
+            patch(items, body.item, new_version)
         },
         check,
     ).await
 }
 
+/// Make the error for a version conflict, including the changes since the
+/// client's version if they were requested.
+pub async fn conflict_error(
+    store: &impl UserStore,
+    req: &Request,
+    user_id: &str,
+    version: u64,
+    client_version: u64,
+) -> Result<super::ApiError, super::ApiError> {
+    let include_changes = req.query_string_parameters()
+        .first("changes")
+        .is_some_and(|c| c == "true");
+
+    // If an import completed while the changes were being read, then they're
+    // left out and the client will need to request them.
+
+    let changes = if include_changes {
+        query_changes(store, user_id, version, client_version).await?
+            .map(|items| {
+                serde_json::to_value(super::db_to_user(version, true, &items)).unwrap()
+            })
+    } else {
+        None
+    };
+
+    Ok(super::ApiError::VersionConflict { version, changes })
+}
+
+/// Query the items that were modified after the client's version, given the
+/// version that was read before querying. Returns `None` if an import completed
+/// while querying, in which case the items are probably incomplete.
+pub async fn query_changes(
+    store: &impl UserStore,
+    user_id: &str,
+    version: u64,
+    client_version: u64,
+) -> Result<Option<Vec<super::DynamoDbItem>>, super::ApiError> {
+    let items = store.query_changed_since(user_id, client_version).await?;
+
+    // Now that we've queried for all of the items, the version is checked
+    // again.
+
+    let new_version = store.read_version(user_id).await?;
+
+    // If the collection changed, then an import completed while we were
+    // reading. So what we read was probably in the process of being deleted
+    // while we were reading it. This means what we've read is probably
+    // incomplete and shouldn't be trusted.
+
+    // If the version changed, then we have a mix of the state of the previous
+    // version and the state of the new version. This is fine when fetching the
+    // changes but not for exporting a full snapshot.
+
+    if super::collection_from_version(new_version) != super::collection_from_version(version) {
+        Ok(None)
+    } else {
+        Ok(Some(items))
+    }
+}
+
+/// Query the whole collection at the version that was read before querying.
+/// Deleted items are only included if requested. The version is read again
+/// afterwards and if it changed, the items are a mix of two versions and the
+/// client needs to try again.
+pub async fn query_snapshot(
+    store: &impl UserStore,
+    user_id: &str,
+    version: u64,
+    include_deleted: bool,
+) -> Result<Vec<super::DynamoDbItem>, super::ApiError> {
+    let collection = super::collection_from_version(version);
+    let items = store.query_collection(user_id, collection, include_deleted).await?;
+    let new_version = store.read_version(user_id).await?;
+
+    if new_version != version {
+        Err(super::ApiError::RetryLater)
+    } else {
+        Ok(items)
+    }
+}
+
 pub fn version_put_item<'a, 'b, T: super::ToDynamoDb<'a>>(
     id: &'b str,
-) -> impl FnOnce(TransactWriteItemsFluentBuilder, T, String, u64) -> TransactWriteItemsFluentBuilder + 'b {
-    move |builder, entity, user_id, new_version| {
+) -> impl FnOnce(&mut Vec<TransactItem>, T, u64) + 'b {
+    move |items, entity, new_version| {
         let mut item = HashMap::new();
 
         let collection_prefix = super::get_collection_prefix(
             super::collection_from_version(new_version)
         );
 
-        item.insert("UserId".into(), AttributeValue::S(user_id));
-        item.insert("Id".into(), AttributeValue::S(
-            super::make_key_from_id::<T>(&collection_prefix, id)
-        ));
-
         entity.insert_dynamo_db(&mut item, Some(new_version));
 
-        builder.transact_items(
-            TransactWriteItem::builder()
-                .put(Put::builder()
-                    .table_name(super::TABLE_USER)
-                    .set_item(Some(item))
-                    .build())
-                .build()
-        )
+        items.push(TransactItem::Put {
+            id: super::make_key_from_id::<T>(&collection_prefix, id),
+            item,
+        });
     }
 }
 
-pub fn version_delete_item(
-    builder: TransactWriteItemsFluentBuilder,
-    user_id: String,
-    key: String,
-    new_version: u64,
-) -> TransactWriteItemsFluentBuilder {
-    builder.transact_items(TransactWriteItem::builder()
-        .update(Update::builder()
-            .table_name(super::TABLE_USER)
-            .key("UserId", AttributeValue::S(user_id))
-            .key("Id", AttributeValue::S(key))
-            .expression_attribute_values(":newVersion", AttributeValue::N(new_version.to_string()))
-            .expression_attribute_values(":deleted", AttributeValue::Bool(true))
-            .condition_expression("attribute_exists(UserId) AND attribute_not_exists(Deleted)")
-            .update_expression("SET ModifiedVersion = :newVersion, Deleted = :deleted")
-            .build())
-        .build())
-}
-
-pub fn check_exists(
-    builder: TransactWriteItemsFluentBuilder,
-    user_id: String,
-    key: String,
-) -> TransactWriteItemsFluentBuilder {
-    builder.transact_items(TransactWriteItem::builder()
-        .condition_check(ConditionCheck::builder()
-            .table_name(super::TABLE_USER)
-            .key("UserId", AttributeValue::S(user_id))
-            .key("Id", AttributeValue::S(key))
-            .condition_expression("attribute_exists(UserId) AND attribute_not_exists(Deleted)")
-            .build())
-        .build())
-}
-
 pub async fn version_apply<P, C>(
+    store: &impl UserStore,
     req: &Request,
     client_version: u64,
     patch: P,
     check: C,
 ) -> super::Result
     where
-        P: FnOnce(TransactWriteItemsFluentBuilder, String, u64) -> TransactWriteItemsFluentBuilder,
-        C: FnOnce(&[CancellationReason]) -> ControlFlow<super::Result, ()>,
+        P: FnOnce(&mut Vec<TransactItem>, u64),
+        C: FnOnce(&[bool]) -> ControlFlow<super::ApiError, ()>,
 {
-    let db = super::get_db_client();
-    let user_id = super::get_user_id(req);
+    let user_id = super::get_user_id(req)?;
     let new_version = client_version + 1;
-    let client_version = client_version.to_string();
-    let now = super::now();
-
-    let builder = db.transact_write_items()
-        .transact_items(TransactWriteItem::builder()
-            .update(Update::builder()
-                .table_name(super::TABLE_USER)
-                .key("UserId", AttributeValue::S(user_id.clone()))
-                .key("Id", AttributeValue::S("VERSION".into()))
-                .expression_attribute_values(":clientVersion", AttributeValue::N(client_version.clone()))
-                .expression_attribute_values(":newVersion", AttributeValue::N(new_version.to_string()))
-                .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
-                .condition_expression(
-                    "(attribute_not_exists(Version) OR Version = :clientVersion) \
-                    AND (attribute_not_exists(LockedUntil) OR LockedUntil <= :now)"
-                )
-                .update_expression("SET Version = :newVersion")
-                .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
-                .build())
-            .build());
-    let result = patch(builder, user_id, new_version).send().await;
-
-    match result {
-        Ok(_) => super::empty_response(StatusCode::OK),
-        Err(e) => {
-            if let Some(reasons) = super::transact_write_cancellation_reasons(&e) {
-                if reasons[0].code() == Some("ConditionalCheckFailed") {
-                    if let Some(item) = reasons[0].item() {
-                        let old_version = item["Version"].as_n().unwrap();
-                        if old_version != &client_version {
-                            return super::empty_response(StatusCode::CONFLICT);
-                        }
-
-                        let locked_until: u64 = super::as_number(&item["LockedUntil"]);
-                        if locked_until > now {
-                            return super::retry_later_response(
-                                locked_until.saturating_sub(super::now())
-                            );
-                        }
-                    }
-                }
-
-                if let ControlFlow::Break(r) = check(&reasons[1..]) {
-                    return r;
-                }
+    let mut items = Vec::new();
+
+    patch(&mut items, new_version);
+
+    match store.versioned_write(&user_id, client_version, items).await? {
+        WriteOutcome::Written => super::empty_response(StatusCode::OK),
+        WriteOutcome::Conflict { version } => {
+            Err(conflict_error(store, req, &user_id, version, client_version).await?)
+        }
+        WriteOutcome::Locked { until } => Err(super::ApiError::Locked {
+            retry_after: until.saturating_sub(super::now()),
+        }),
+        WriteOutcome::Canceled(failed) => {
+            if let ControlFlow::Break(e) = check(&failed) {
+                return Err(e);
             }
 
-            Err(e.into())
+            Err(super::ApiError::Store("transaction canceled".into()))
         }
     }
 }
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use aws_sdk_dynamodb::types::AttributeValue;
+use base64::Engine;
+use lambda_http::Error;
+use crate::{common, store::{BatchWrite, UserStore}};
+
+// Calendar apps subscribe to a feed by URL and can't send a Cognito token, so
+// the feed is authenticated with a long-lived token in the query string
+// instead. The token is the user ID followed by a random secret. The secret is
+// stored in the user's partition outside of any collection so that the user ID
+// in the token is enough to find it. Creating a new token replaces the old one
+// and deleting it revokes access to the feed.
+
+pub const ITEM_ID: &str = "FEED_TOKEN";
+
+/// Create a new token for the user, replacing any existing token.
+pub async fn create_token(store: &impl UserStore, user_id: &str) -> Result<String, Error> {
+    let secret = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple(),
+    );
+
+    let mut item = HashMap::new();
+    item.insert("Secret".into(), AttributeValue::S(secret.clone()));
+    item.insert("CreatedAt".into(), AttributeValue::N(common::now().to_string()));
+
+    store.batch_write(user_id, vec![BatchWrite::Put { id: ITEM_ID.into(), item }]).await?;
+
+    let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+    Ok(format!("{}.{secret}", engine.encode(user_id)))
+}
+
+pub async fn delete_token(store: &impl UserStore, user_id: &str) -> Result<(), Error> {
+    Ok(store.batch_write(user_id, vec![BatchWrite::Delete { id: ITEM_ID.into() }]).await?)
+}
+
+/// Get the user that a token belongs to.
+pub async fn verify_token(
+    store: &impl UserStore,
+    token: &str,
+) -> Result<String, common::ApiError> {
+    let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+    let user_id = token.split_once('.')
+        .and_then(|(user_id, secret)| {
+            let user_id = String::from_utf8(engine.decode(user_id).ok()?).ok()?;
+            Some((user_id, secret))
+        });
+
+    let Some((user_id, secret)) = user_id else {
+        return Err(common::ApiError::Unauthorized("malformed feed token"));
+    };
+
+    let stored = store.get_item(&user_id, ITEM_ID).await?;
+    let stored_secret = stored.as_ref()
+        .and_then(|item| item.get("Secret"))
+        .and_then(|s| s.as_s().ok());
+
+    match stored_secret {
+        Some(s) if constant_time_eq(s.as_bytes(), secret.as_bytes()) => Ok(user_id),
+        _ => Err(common::ApiError::Unauthorized("invalid feed token")),
+    }
+}
+
+/// Compare two secrets without leaking where they differ through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
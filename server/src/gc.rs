@@ -0,0 +1,139 @@
+use lambda_http::Error;
+use crate::{common, store::{BatchWrite, LockOutcome, UserStore}};
+
+// An import writes a new collection and then deletes the old one. If the import
+// fails part way through, then either the old collection or a partially written
+// new collection is left behind. Neither of these are visible to the client but
+// they take up space. The next import clears the collection that it writes to
+// but there may never be another import. This deletes every item that is in a
+// collection other than the current one. Items outside of any collection (such
+// as import jobs) are left alone.
+//
+// Deleting items from a collection that an import is writing to would corrupt
+// the import so the import lock is held while deleting. If an import is in
+// progress, then the user is skipped. Most users won't have any orphaned items
+// so the IDs are checked before taking the lock to avoid blocking writes. The
+// lock is extended between chunks of deletes in the same way as an import. If
+// it expires, then an import could have started writing to the collection
+// that is being deleted so deleting stops.
+
+/// How long the lock is held for before it needs to be renewed. Writes aren't
+/// allowed while the lock is held.
+const LOCK_DURATION_S: u64 = 60;
+
+/// The number of deletes between each renewal of the lock.
+const CHUNK_SIZE: usize = 250;
+
+pub enum Collected {
+    /// There was nothing to delete.
+    Clean,
+    /// This many orphaned items were deleted.
+    Deleted(usize),
+    /// An import is in progress so nothing was deleted.
+    Locked,
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub users: usize,
+    pub deleted: usize,
+    pub locked: usize,
+    pub failed: usize,
+}
+
+/// Delete the orphaned items of a single user.
+pub async fn collect_user(store: &impl UserStore, user_id: &str) -> Result<Collected, Error> {
+    let version = store.read_version(user_id).await?;
+
+    if find_orphans(store, user_id, version).await?.is_empty() {
+        return Ok(Collected::Clean);
+    }
+
+    let token = uuid::Uuid::new_v4().to_string();
+    let lock_expire = common::now() + LOCK_DURATION_S;
+
+    let version = match store.acquire_import_lock(user_id, &token, lock_expire).await? {
+        LockOutcome::Acquired { version } => version,
+        LockOutcome::Held { .. } => return Ok(Collected::Locked),
+    };
+
+    // The lock needs to be released even if deleting fails. Releasing the lock
+    // with the version that it was acquired with leaves the version unchanged.
+    // If the lock expired, then releasing it does nothing so a write that
+    // happened since isn't rolled back.
+
+    let result = delete_orphans(store, user_id, &token, version).await;
+
+    if !store.release_import_lock(user_id, &token, version).await? {
+        return Err(format!("the lock of {user_id} expired while deleting").into());
+    }
+
+    result.map(Collected::Deleted)
+}
+
+/// Delete the orphaned items of every user. A failure for one user doesn't stop
+/// the others from being collected.
+pub async fn collect_all(store: &impl UserStore) -> Result<Summary, Error> {
+    let mut summary = Summary::default();
+
+    for user_id in store.scan_user_ids().await? {
+        summary.users += 1;
+
+        match collect_user(store, &user_id).await {
+            Ok(Collected::Clean) => {}
+            Ok(Collected::Deleted(count)) => {
+                tracing::info!("Deleted {count} orphaned items of {user_id}");
+                summary.deleted += count;
+            }
+            Ok(Collected::Locked) => {
+                tracing::info!("Skipped {user_id} because an import is in progress");
+                summary.locked += 1;
+            }
+            Err(e) => {
+                tracing::error!("Collecting {user_id} failed: {e}");
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+async fn delete_orphans(
+    store: &impl UserStore,
+    user_id: &str,
+    token: &str,
+    version: u64,
+) -> Result<usize, Error> {
+    let orphans = find_orphans(store, user_id, version).await?;
+
+    for chunk in orphans.chunks(CHUNK_SIZE) {
+        if !store.extend_import_lock(user_id, token, common::now() + LOCK_DURATION_S).await? {
+            return Err(format!("the lock of {user_id} expired while deleting").into());
+        }
+
+        store.batch_write(
+            user_id,
+            chunk.iter().map(|id| BatchWrite::Delete { id: id.clone() }).collect(),
+        ).await?;
+    }
+
+    Ok(orphans.len())
+}
+
+async fn find_orphans(store: &impl UserStore, user_id: &str, version: u64) -> Result<Vec<String>, Error> {
+    let prefix = common::get_collection_prefix(common::collection_from_version(version));
+
+    Ok(store.query_ids(user_id).await?
+        .into_iter()
+        .filter(|id| is_in_collection(id) && !id.starts_with(&prefix))
+        .collect())
+}
+
+fn is_in_collection(id: &str) -> bool {
+    let bytes = id.as_bytes();
+
+    bytes.len() > common::COLLECTION_LEN
+        && bytes[..common::COLLECTION_LEN].iter().all(u8::is_ascii_digit)
+        && bytes[common::COLLECTION_LEN] == b'#'
+}
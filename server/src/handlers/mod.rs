@@ -1,6 +1,16 @@
 pub mod user;
+pub mod user_batch;
+pub mod user_exercise_type;
+pub mod user_export;
+pub mod user_feed_token;
+pub mod user_import;
 pub mod user_measurement;
+pub mod user_program;
+pub mod user_records;
+pub mod user_routine;
 pub mod user_snapshot;
+pub mod user_stats;
 pub mod user_workout;
 pub mod user_workout_exercise;
 pub mod user_workout_order;
+pub mod user_workouts;
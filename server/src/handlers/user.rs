@@ -1,28 +1,30 @@
-use aws_sdk_dynamodb::{types::{AttributeValue, Select}, Client};
 use lambda_http::{Request, http::StatusCode, RequestExt};
-use tokio_stream::StreamExt;
-use crate::common;
+use crate::{common, store::UserStore};
 
-pub async fn get(req: Request) -> common::Result {
-    let user_id = common::get_user_id(&req);
-    let db = common::get_db_client();
+pub async fn get(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
     let query_map = req.query_string_parameters();
     let since_version = query_map.first("since");
 
     let result = if let Some(version) = since_version {
         let version = match version.parse() {
             Ok(v) => v,
-            Err(_) => return common::empty_response(StatusCode::BAD_REQUEST),
+            Err(_) => return Err(common::ApiError::Validation("invalid since version".into())),
         };
-        get_changed(db, user_id, version).await
+        get_changed(store, &req, user_id, version).await
     } else {
-        get_changed(db, user_id, 0).await
+        get_changed(store, &req, user_id, 0).await
     };
 
     result
 }
 
-async fn get_changed(db: &Client, user_id: String, client_version: u64) -> common::Result {
+async fn get_changed(
+    store: &impl UserStore,
+    req: &Request,
+    user_id: String,
+    client_version: u64,
+) -> common::Result {
     // Get the version first. The objects that we return may have a greater
     // modified version than this if they are modified while we're querying
     // them but that's OK. The client knows that it has at least this version
@@ -31,16 +33,15 @@ async fn get_changed(db: &Client, user_id: String, client_version: u64) -> commo
     // bad time. The version and the modified version are updated in a
     // transaction so the version will never be greater than it should be.
 
-    let get_version = db.get_item()
-        .table_name(common::TABLE_USER)
-        .key("UserId", AttributeValue::S(user_id.clone()))
-        .key("Id", AttributeValue::S("VERSION".into()))
-        .send()
-        .await?;
+    let version = store.read_version(&user_id).await?;
+    let etag = common::version_etag(version);
 
-    let version = get_version.item()
-        .map_or(0, |i| common::as_number(&i["Version"]));
-    let collection = common::collection_from_version(version);
+    // The ETag is the version. If the client already has this version, then
+    // there's nothing to send back. This is the most common path when polling.
+
+    if common::if_none_match(req, &etag) {
+        return common::not_modified_response(&etag);
+    }
 
     // If the client is requesting changes after the current version, then we
     // know that there won't be anything so we can skip the extra queries and
@@ -49,72 +50,20 @@ async fn get_changed(db: &Client, user_id: String, client_version: u64) -> commo
     // path that will be taken.
 
     if version <= client_version {
-        // TODO: since this is so common, perhaps make it smaller
-        // we could use the If-None-Match header where the ETag is the version
-        // number. Reaching this branch would end up being a 304 response with
-        // the version in the ETag header.
-
-        return common::json_response(StatusCode::OK, common::User {
+        return common::with_etag(common::json_response(StatusCode::OK, common::User {
             version,
-            measurement_sets: Vec::new(),
-            workouts: Vec::new(),
-            exercises: Vec::new(),
-            deleted_measurement_sets: Vec::new(),
-            deleted_workouts: Vec::new(),
-            deleted_exercises: Vec::new(),
-        });
+            ..Default::default()
+        }), &etag);
     }
 
-    // Query for items that were modified after the given version. There's an
-    // LSI on the ModifiedVersion but the index only includes the keys as to
-    // avoid slowing down writes too much.
-
-    let items = db.query()
-        .table_name(common::TABLE_USER)
-        .index_name(common::INDEX_MODIFIED_VERSION)
-        .key_condition_expression("UserId = :userId AND ModifiedVersion > :clientVersion")
-        .expression_attribute_values(":userId", AttributeValue::S(user_id.clone()))
-        .expression_attribute_values(
-            ":clientVersion",
-            AttributeValue::N(client_version.to_string()),
-        )
-        .select(Select::AllAttributes)
-        .into_paginator()
-        .items()
-        .send()
-        .collect::<Result<Vec<_>, _>>()
-        .await?;
-
-    // Now that we've queried for all of the items, the version is checked
-    // again.
+    // Query for items that were modified after the given version. If an import
+    // completed while we were reading, we should tell the client to try again.
 
-    let get_version = db.get_item()
-        .table_name(common::TABLE_USER)
-        .key("UserId", AttributeValue::S(user_id))
-        .key("Id", AttributeValue::S("VERSION".into()))
-        .send()
-        .await?;
-
-    let new_version = get_version.item()
-        .map_or(0, |i| common::as_number(&i["Version"]));
-    let new_collection = common::collection_from_version(new_version);
-
-    // If the collection changed, then an import completed while we were
-    // reading. So what we read was probably in the process of being deleted
-    // while we were reading it. This means what we've read is probably
-    // incomplete and shouldn't be trusted. We should tell the client to try
-    // again.
-
-    // If the version changed, then we have a mix of the state of the previous
-    // version and the state of the new version. This is fine when fetching the
-    // changes but not for exporting a full snapshot.
-
-    if new_collection != collection {
-        common::retry_later_response(0)
-    } else {
-        common::json_response(
+    match common::query_changes(store, &user_id, version, client_version).await? {
+        Some(items) => common::with_etag(common::json_response(
             StatusCode::OK,
             common::db_to_user(version, true, &items),
-        )
+        ), &etag),
+        None => Err(common::ApiError::RetryLater),
     }
 }
@@ -0,0 +1,774 @@
+use std::collections::HashMap;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_http::{Request, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use crate::{common::{self, FromDynamoDb}, records, store::{MAX_TRANSACT_ITEMS, TransactItem, UserStore, WriteOutcome}};
+use super::user_workout_order::Exercises;
+
+// A batch is an ordered list of the same operations that the individual PUT
+// and DELETE routes perform, all relative to a single base version. This lets
+// a client that has been offline replay its edits without a round trip (and a
+// possible 409) for each one.
+//
+// DynamoDB doesn't allow a transaction to contain more than one operation on
+// the same item so the operations are coalesced. For example, putting a
+// workout and then deleting it becomes a single put of a deleted item. If an
+// operation needs an item to exist and an earlier operation in the batch
+// deleted it, then the operation fails before anything is written.
+//
+// The whole batch is applied in a single transaction if it fits. Otherwise, it
+// is split into chunks that are applied one after the other with each chunk
+// incrementing the version. Each chunk is atomic but the batch as a whole is
+// not. If a chunk fails, the response says how many operations were applied
+// and the version that the data is now at.
+
+const MAX_OPERATIONS: usize = 1000;
+
+/// The maximum number of entity items in a transaction. One item is reserved
+/// for the VERSION item.
+const MAX_CHUNK_ITEMS: usize = MAX_TRANSACT_ITEMS - 1;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Operation<'a> {
+    PutMeasurement {
+        measurement_id: &'a str,
+        #[serde(borrow)]
+        item: common::MeasurementSet<'a>,
+    },
+    DeleteMeasurement {
+        measurement_id: &'a str,
+    },
+    PutWorkout {
+        #[serde(borrow)]
+        workout_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        item: common::Workout<'a>,
+    },
+    DeleteWorkout {
+        #[serde(borrow)]
+        workout_id: common::Uuid<'a>,
+    },
+    PutExercise {
+        #[serde(borrow)]
+        workout_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        exercise_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        item: common::Exercise<'a>,
+    },
+    DeleteExercise {
+        #[serde(borrow)]
+        workout_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        exercise_id: common::Uuid<'a>,
+    },
+    PutWorkoutOrder {
+        #[serde(borrow)]
+        workout_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        item: Exercises<'a>,
+    },
+    PutExerciseType {
+        #[serde(borrow)]
+        exercise_type_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        item: common::CustomExerciseType<'a>,
+    },
+    DeleteExerciseType {
+        #[serde(borrow)]
+        exercise_type_id: common::Uuid<'a>,
+    },
+    PutRoutine {
+        #[serde(borrow)]
+        routine_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        item: common::Routine<'a>,
+    },
+    DeleteRoutine {
+        #[serde(borrow)]
+        routine_id: common::Uuid<'a>,
+    },
+    PutProgram {
+        #[serde(borrow)]
+        program_id: common::Uuid<'a>,
+        #[serde(borrow)]
+        item: common::Program<'a>,
+    },
+    DeleteProgram {
+        #[serde(borrow)]
+        program_id: common::Uuid<'a>,
+    },
+}
+
+#[derive(Deserialize)]
+struct BatchReq<'a> {
+    version: u64,
+    #[serde(borrow)]
+    operations: common::MaxLenVec<Operation<'a>, MAX_OPERATIONS>,
+}
+
+#[derive(Serialize)]
+struct BatchRes<'a> {
+    version: u64,
+    /// The personal records that the batch broke.
+    records: Vec<records::BrokenRecord<'a>>,
+}
+
+/// A transaction item along with the reason to give if its condition fails.
+type Step = (TransactItem, String);
+
+pub async fn post(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+    let body = common::parse_request_json::<BatchReq>(&req)?;
+    let base_version = body.version;
+    let collection_prefix = common::get_collection_prefix(
+        common::collection_from_version(base_version)
+    );
+
+    check_custom_types(store, &user_id, &collection_prefix, &body.operations.0).await?;
+
+    // The records are updated after each operation on an exercise and written
+    // in the same chunk as the operation. The chunk only keeps the last write of
+    // the records of a type so it has the records as they were after its last
+    // operation on that type. The records of every type are read since the
+    // types that the exercises have now aren't known.
+
+    let writes = body.operations.0.iter()
+        .filter_map(|operation| match operation {
+            Operation::PutExercise { workout_id, exercise_id, item } => {
+                Some(records::Write::Put(format!("{}#{}", workout_id.0, exercise_id.0), item))
+            }
+            Operation::DeleteExercise { workout_id, exercise_id } => {
+                Some(records::Write::Delete(format!("{}#{}", workout_id.0, exercise_id.0)))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let stored_records = records::read(store, &user_id).await?;
+    let tracked = records::track(store, &user_id, base_version, stored_records, &writes).await?;
+    let mut record_items = tracked.items.into_iter();
+
+    let mut chunks = Vec::<Chunk>::new();
+    let mut failures = Vec::new();
+
+    for (index, operation) in body.operations.0.into_iter().enumerate() {
+        // Each chunk increments the version so the modified version of the
+        // items depends on which chunk they end up in.
+
+        let new_version = base_version + chunks.len().max(1) as u64;
+        let is_exercise = matches!(
+            operation,
+            Operation::PutExercise { .. } | Operation::DeleteExercise { .. },
+        );
+        let mut steps = make_steps(operation, &collection_prefix, new_version)
+            .map_err(|e| common::ApiError::Validation(format!("operation {index}: {e}")))?;
+
+        if is_exercise {
+            let items = record_items.next().unwrap();
+            steps.extend(items.into_iter().map(|item| (item, String::new())));
+        }
+
+        if !chunks.last().is_some_and(|c| c.fits(&steps)) {
+            chunks.push(Chunk::new(index));
+            steps = retarget(steps, base_version + chunks.len() as u64);
+        }
+
+        let new_version = base_version + chunks.len() as u64;
+        failures.extend(chunks.last_mut().unwrap().merge(index, steps, new_version));
+    }
+
+    if !failures.is_empty() {
+        return Err(common::ApiError::BatchFailed {
+            version: base_version,
+            applied: 0,
+            failures,
+        });
+    }
+
+    // An empty batch doesn't write anything but the client still needs to know
+    // if its version is out of date.
+
+    if chunks.is_empty() {
+        let version = store.read_version(&user_id).await?;
+
+        if version != base_version {
+            return Err(common::conflict_error(store, &req, &user_id, version, base_version).await?);
+        }
+    }
+
+    let mut version = base_version;
+
+    for (n, chunk) in chunks.into_iter().enumerate() {
+        let failures = match store.versioned_write(&user_id, version, chunk.items).await? {
+            WriteOutcome::Written => {
+                version += 1;
+                continue;
+            }
+            WriteOutcome::Conflict { version: current } if n == 0 => {
+                return Err(common::conflict_error(
+                    store,
+                    &req,
+                    &user_id,
+                    current,
+                    base_version,
+                ).await?);
+            }
+            WriteOutcome::Locked { until } if n == 0 => {
+                return Err(common::ApiError::Locked {
+                    retry_after: until.saturating_sub(common::now()),
+                });
+            }
+            WriteOutcome::Conflict { .. } => vec![common::OperationFailure {
+                index: chunk.start,
+                code: "version-conflict",
+                detail: None,
+            }],
+            WriteOutcome::Locked { .. } => vec![common::OperationFailure {
+                index: chunk.start,
+                code: "locked",
+                detail: None,
+            }],
+            WriteOutcome::Canceled(failed) => {
+                let mut failures = failed.iter()
+                    .zip(chunk.reasons)
+                    .filter(|(failed, _)| **failed)
+                    .filter_map(|(_, reason)| reason)
+                    .map(|(index, detail)| not_found(index, detail))
+                    .collect::<Vec<_>>();
+
+                if failures.is_empty() {
+                    return Err(common::ApiError::Store("transaction canceled".into()));
+                }
+
+                failures.sort_by_key(|f| f.index);
+                failures
+            }
+        };
+
+        return Err(common::ApiError::BatchFailed {
+            version,
+            applied: chunk.start,
+            failures,
+        });
+    }
+
+    common::json_response(StatusCode::OK, BatchRes {
+        version,
+        records: tracked.records.broken_since(&tracked.stored),
+    })
+}
+
+/// The operations that are applied in a single transaction.
+struct Chunk {
+    /// Index of the first operation in the chunk.
+    start: usize,
+    items: Vec<TransactItem>,
+    /// For each item with a condition, the index of the operation that added
+    /// the condition and the reason to give if it fails.
+    reasons: Vec<Option<(usize, String)>>,
+    by_id: HashMap<String, usize>,
+}
+
+impl Chunk {
+    fn new(start: usize) -> Self {
+        Self {
+            start,
+            items: Vec::new(),
+            reasons: Vec::new(),
+            by_id: HashMap::new(),
+        }
+    }
+
+    fn fits(&self, steps: &[Step]) -> bool {
+        let new_items = steps.iter()
+            .filter(|(item, _)| !self.by_id.contains_key(transact_item_id(item)))
+            .count();
+
+        self.items.len() + new_items <= MAX_CHUNK_ITEMS
+    }
+
+    /// Merge the steps of an operation into the chunk. Either all of the steps
+    /// are merged or none of them are.
+    fn merge(
+        &mut self,
+        index: usize,
+        steps: Vec<Step>,
+        new_version: u64,
+    ) -> Option<common::OperationFailure> {
+        for (item, reason) in steps.iter() {
+            let Some(&i) = self.by_id.get(transact_item_id(item)) else {
+                continue;
+            };
+
+            if needs_existing(item) && is_deleted(&self.items[i]) {
+                return Some(not_found(index, reason.clone()));
+            }
+        }
+
+        for (item, reason) in steps {
+            let id = transact_item_id(&item).to_owned();
+
+            let Some(&i) = self.by_id.get(&id) else {
+                let reason = needs_existing(&item).then_some((index, reason));
+                self.by_id.insert(id, self.items.len());
+                self.items.push(item);
+                self.reasons.push(reason);
+                continue;
+            };
+
+            // The placeholder is immediately replaced.
+            let prev = std::mem::replace(&mut self.items[i], TransactItem::CheckExists {
+                id: String::new(),
+            });
+
+            self.items[i] = coalesce(prev, item, new_version);
+        }
+
+        None
+    }
+}
+
+fn make_steps(
+    operation: Operation,
+    collection_prefix: &str,
+    new_version: u64,
+) -> Result<Vec<Step>, &'static str> {
+    let mut items = Vec::new();
+    let mut reasons = Vec::new();
+
+    match operation {
+        Operation::PutMeasurement { measurement_id, item } => {
+            if !common::is_date(measurement_id) {
+                return Err("invalid measurement ID");
+            }
+
+            common::version_put_item::<common::MeasurementSet>(measurement_id)(&mut items, item, new_version);
+            reasons.push(String::new());
+        }
+        Operation::DeleteMeasurement { measurement_id } => {
+            if !common::is_date(measurement_id) {
+                return Err("invalid measurement ID");
+            }
+
+            items.push(TransactItem::Delete {
+                id: common::make_key_from_id::<common::MeasurementSet>(collection_prefix, measurement_id),
+                new_version,
+            });
+            reasons.push(format!("measurement {measurement_id} doesn't exist"));
+        }
+        Operation::PutWorkout { workout_id, item } => {
+            common::version_put_item::<common::Workout>(workout_id.0)(&mut items, item, new_version);
+            reasons.push(String::new());
+        }
+        Operation::DeleteWorkout { workout_id } => {
+            items.push(TransactItem::Delete {
+                id: common::make_key_from_id::<common::Workout>(collection_prefix, workout_id.0),
+                new_version,
+            });
+            reasons.push(format!("workout {} doesn't exist", workout_id.0));
+        }
+        Operation::PutExercise { workout_id, exercise_id, item } => {
+            items.push(TransactItem::CheckExists {
+                id: common::make_key_from_id::<common::Workout>(collection_prefix, workout_id.0),
+            });
+            reasons.push(format!("workout {} doesn't exist", workout_id.0));
+
+            common::version_put_item::<common::Exercise>(
+                &format!("{}#{}", workout_id.0, exercise_id.0)
+            )(&mut items, item, new_version);
+            reasons.push(String::new());
+        }
+        Operation::DeleteExercise { workout_id, exercise_id } => {
+            let workout_key = common::make_key_from_id::<common::Workout>(collection_prefix, workout_id.0);
+
+            items.push(TransactItem::CheckExists { id: workout_key.clone() });
+            reasons.push(format!("workout {} doesn't exist", workout_id.0));
+
+            items.push(TransactItem::Delete {
+                id: format!("{workout_key}#{}", exercise_id.0),
+                new_version,
+            });
+            reasons.push(format!("exercise {} doesn't exist", exercise_id.0));
+        }
+        Operation::PutWorkoutOrder { workout_id, item } => {
+            let workout_key = common::make_key_from_id::<common::Workout>(collection_prefix, workout_id.0);
+
+            items.push(TransactItem::CheckExists { id: workout_key.clone() });
+            reasons.push(format!("workout {} doesn't exist", workout_id.0));
+
+            for (i, exercise) in item.0.iter().map(|e| e.0).enumerate() {
+                items.push(TransactItem::SetOrder {
+                    id: format!("{workout_key}#{exercise}"),
+                    order: i as u32,
+                    new_version,
+                });
+                reasons.push(format!("exercise {exercise} doesn't exist"));
+            }
+        }
+        Operation::PutExerciseType { exercise_type_id, item } => {
+            common::version_put_item::<common::CustomExerciseType>(exercise_type_id.0)(&mut items, item, new_version);
+            reasons.push(String::new());
+        }
+        Operation::DeleteExerciseType { exercise_type_id } => {
+            items.push(TransactItem::Delete {
+                id: common::make_key_from_id::<common::CustomExerciseType>(collection_prefix, exercise_type_id.0),
+                new_version,
+            });
+            reasons.push(format!("exercise type {} doesn't exist", exercise_type_id.0));
+        }
+        Operation::PutRoutine { routine_id, item } => {
+            common::version_put_item::<common::Routine>(routine_id.0)(&mut items, item, new_version);
+            reasons.push(String::new());
+        }
+        Operation::DeleteRoutine { routine_id } => {
+            items.push(TransactItem::Delete {
+                id: common::make_key_from_id::<common::Routine>(collection_prefix, routine_id.0),
+                new_version,
+            });
+            reasons.push(format!("routine {} doesn't exist", routine_id.0));
+        }
+        Operation::PutProgram { program_id, item } => {
+            common::version_put_item::<common::Program>(program_id.0)(&mut items, item, new_version);
+            reasons.push(String::new());
+        }
+        Operation::DeleteProgram { program_id } => {
+            items.push(TransactItem::Delete {
+                id: common::make_key_from_id::<common::Program>(collection_prefix, program_id.0),
+                new_version,
+            });
+            reasons.push(format!("program {} doesn't exist", program_id.0));
+        }
+    }
+
+    Ok(items.into_iter().zip(reasons).collect())
+}
+
+/// Check the exercises and routines of custom types against the types as they
+/// will be when they're written, which could be from an earlier operation in
+/// the batch. The chunks are written one version after the other so the types
+/// read from the store can't change before the batch is applied.
+async fn check_custom_types(
+    store: &impl UserStore,
+    user_id: &str,
+    collection_prefix: &str,
+    operations: &[Operation<'_>],
+) -> Result<(), common::ApiError> {
+    let type_ids = operations.iter()
+        .flat_map(|operation| -> Vec<&str> {
+            match operation {
+                Operation::PutExercise { item, .. } => vec![&item.r#type.0],
+                Operation::PutRoutine { item, .. } => {
+                    item.exercises.0.iter().map(|e| e.r#type.0.as_ref()).collect()
+                }
+                _ => Vec::new(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let stored_items = common::get_custom_types(store, user_id, collection_prefix, &type_ids).await?;
+    let stored_types = stored_items.iter()
+        .map(|(id, item)| (*id, item.as_ref().map(|item| common::CustomExerciseType::from_dynamo_db(id, item))))
+        .collect::<HashMap<_, _>>();
+
+    let mut types = stored_types.iter()
+        .map(|(id, custom)| (*id, custom.as_ref()))
+        .collect::<HashMap<_, _>>();
+
+    for (index, operation) in operations.iter().enumerate() {
+        let result = match operation {
+            Operation::PutExerciseType { exercise_type_id, item } => {
+                types.insert(exercise_type_id.0, Some(item));
+                Ok(())
+            }
+            Operation::DeleteExerciseType { exercise_type_id } => {
+                types.insert(exercise_type_id.0, None);
+                Ok(())
+            }
+            Operation::PutExercise { item, .. } => match types.get(item.r#type.0.as_ref()) {
+                Some(custom) => common::validate_custom_exercise(item, *custom),
+                None => Ok(()),
+            },
+            Operation::PutRoutine { item, .. } => item.exercises.0.iter()
+                .enumerate()
+                .try_for_each(|(i, exercise)| match types.get(exercise.r#type.0.as_ref()) {
+                    Some(custom) => common::validate_custom_targets(exercise, *custom)
+                        .map_err(|e| format!("exercises[{i}]: {e}")),
+                    None => Ok(()),
+                }),
+            _ => Ok(()),
+        };
+
+        result.map_err(|e| common::ApiError::Validation(format!("operation {index}: {e}")))?;
+    }
+
+    Ok(())
+}
+
+/// Update the modified version of steps that were made for a different chunk.
+fn retarget(steps: Vec<Step>, new_version: u64) -> Vec<Step> {
+    steps.into_iter()
+        .map(|(item, reason)| (match item {
+            TransactItem::Put { id, mut item } => {
+                set_modified_version(&mut item, new_version);
+                TransactItem::Put { id, item }
+            }
+            TransactItem::Replace { id, mut item } => {
+                set_modified_version(&mut item, new_version);
+                TransactItem::Replace { id, item }
+            }
+            TransactItem::Delete { id, .. } => TransactItem::Delete { id, new_version },
+            TransactItem::SetOrder { id, order, .. } => TransactItem::SetOrder { id, order, new_version },
+            item @ TransactItem::CheckExists { .. } => item,
+        }, reason))
+        .collect()
+}
+
+/// Combine two operations on the same item into one that has the same effect
+/// as applying them in order. The second operation must not require the item
+/// to exist if the first one deleted it.
+fn coalesce(prev: TransactItem, next: TransactItem, new_version: u64) -> TransactItem {
+    use TransactItem::*;
+
+    match (prev, next) {
+        // Replacing keeps the condition of an earlier operation that required
+        // the item to exist.
+        (Put { .. }, Put { id, item }) => Put { id, item },
+        (_, Put { id, item }) => Replace { id, item },
+
+        (Put { id, .. }, Delete { .. }) => Put { id, item: common::make_deleted_item(new_version) },
+        (Replace { id, .. }, Delete { .. }) => Replace { id, item: common::make_deleted_item(new_version) },
+        (_, next @ Delete { .. }) => next,
+
+        (prev, CheckExists { .. }) => prev,
+
+        (Put { id, mut item }, SetOrder { order, .. }) => {
+            item.insert("Order".into(), AttributeValue::N(order.to_string()));
+            Put { id, item }
+        }
+        (Replace { id, mut item }, SetOrder { order, .. }) => {
+            item.insert("Order".into(), AttributeValue::N(order.to_string()));
+            Replace { id, item }
+        }
+        (_, next @ SetOrder { .. }) => next,
+
+        (_, Replace { .. }) => unreachable!(),
+    }
+}
+
+fn transact_item_id(item: &TransactItem) -> &str {
+    match item {
+        TransactItem::Put { id, .. }
+        | TransactItem::Replace { id, .. }
+        | TransactItem::Delete { id, .. }
+        | TransactItem::CheckExists { id }
+        | TransactItem::SetOrder { id, .. } => id,
+    }
+}
+
+fn needs_existing(item: &TransactItem) -> bool {
+    !matches!(item, TransactItem::Put { .. })
+}
+
+fn is_deleted(item: &TransactItem) -> bool {
+    match item {
+        TransactItem::Put { item, .. } | TransactItem::Replace { item, .. } => {
+            item.contains_key("Deleted")
+        }
+        TransactItem::Delete { .. } => true,
+        _ => false,
+    }
+}
+
+/// Items outside of the collection, such as the records, don't have a modified
+/// version.
+fn set_modified_version(item: &mut common::DynamoDbItem, new_version: u64) {
+    if let Some(modified_version) = item.get_mut("ModifiedVersion") {
+        *modified_version = AttributeValue::N(new_version.to_string());
+    }
+}
+
+fn not_found(index: usize, detail: String) -> common::OperationFailure {
+    common::OperationFailure {
+        index,
+        code: "not-found",
+        detail: Some(detail),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use lambda_http::Body;
+    use serde_json::{Value, json};
+    use crate::store::MemoryStore;
+    use super::*;
+
+    const USER_ID: &str = "user";
+    const WORKOUT_ID: &str = "0f0c3a56-9f1e-4b53-8d2a-6b1f0e3c2a11";
+    const EXERCISE_1: &str = "5b7d2c1e-3a4f-4e6d-9c8b-7a6f5e4d3c22";
+    const EXERCISE_2: &str = "8e9f0a1b-2c3d-4e5f-8a7b-6c5d4e3f2a33";
+
+    fn request(version: u64, operations: Vec<Value>) -> Request {
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let token = format!("x.{}.y", engine.encode(json!({ "sub": USER_ID }).to_string()));
+        let body = json!({ "version": version, "operations": operations });
+
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/user/batch")
+            .header("Authorization", format!("Bearer {token}"))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// Apply a batch and return the version that it wrote.
+    async fn apply(store: &MemoryStore, version: u64, operations: Vec<Value>) -> u64 {
+        match post(store, request(version, operations)).await {
+            Ok(res) => match res.body() {
+                Body::Text(t) => serde_json::from_str::<Value>(t).unwrap()["version"].as_u64().unwrap(),
+                _ => panic!("expected a JSON body"),
+            },
+            Err(common::ApiError::Validation(message)) => panic!("{message}"),
+            Err(_) => panic!("batch failed"),
+        }
+    }
+
+    /// Apply a batch that's expected to fail and return the version, the number
+    /// of operations applied and the failures.
+    async fn apply_failed(
+        store: &MemoryStore,
+        version: u64,
+        operations: Vec<Value>,
+    ) -> (u64, usize, Vec<(usize, &'static str, Option<String>)>) {
+        match post(store, request(version, operations)).await {
+            Err(common::ApiError::BatchFailed { version, applied, failures }) => (
+                version,
+                applied,
+                failures.into_iter().map(|f| (f.index, f.code, f.detail)).collect(),
+            ),
+            _ => panic!("expected the batch to fail"),
+        }
+    }
+
+    fn put_workout(notes: &str) -> Value {
+        json!({ "put_workout": {
+            "workout_id": WORKOUT_ID,
+            "item": { "start_time": "2026-10-01T10:00:00Z", "finish_time": null, "notes": notes },
+        }})
+    }
+
+    fn put_exercise(exercise_id: &str, order: u32) -> Value {
+        json!({ "put_exercise": {
+            "workout_id": WORKOUT_ID,
+            "exercise_id": exercise_id,
+            "item": { "order": order, "type": "biceps-curl", "notes": "", "sets": [] },
+        }})
+    }
+
+    fn measurement_id(day: u32) -> String {
+        let date = common::parse_date("2026-01-01").unwrap() + chrono::Duration::days(day as i64);
+        date.format("%F").to_string()
+    }
+
+    fn put_measurement(day: u32) -> Value {
+        json!({ "put_measurement": {
+            "measurement_id": measurement_id(day),
+            "item": { "notes": "", "measurements": { "weight": 80 } },
+        }})
+    }
+
+    async fn get(store: &MemoryStore, key: &str) -> Option<common::DynamoDbItem> {
+        store.get_item(USER_ID, &format!("{}{key}", common::get_collection_prefix(0))).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn operations_on_the_same_item_are_coalesced() {
+        let store = MemoryStore::new();
+
+        let version = apply(&store, 0, vec![
+            put_workout("first"),
+            put_exercise(EXERCISE_1, 0),
+            put_exercise(EXERCISE_2, 1),
+            put_workout("second"),
+            json!({ "put_workout_order": { "workout_id": WORKOUT_ID, "item": [EXERCISE_2, EXERCISE_1] } }),
+        ]).await;
+        assert_eq!(version, 1);
+
+        let workout = get(&store, &format!("WORKOUT#{WORKOUT_ID}")).await.unwrap();
+        assert_eq!(workout["Notes"].as_s().unwrap(), "second");
+
+        let order = |item: common::DynamoDbItem| item["Order"].as_n().unwrap().to_owned();
+        assert_eq!(order(get(&store, &format!("WORKOUT#{WORKOUT_ID}#{EXERCISE_1}")).await.unwrap()), "1");
+        assert_eq!(order(get(&store, &format!("WORKOUT#{WORKOUT_ID}#{EXERCISE_2}")).await.unwrap()), "0");
+
+        // Deleting the exercise and then putting it again replaces the deleted
+        // item rather than failing.
+
+        let version = apply(&store, 1, vec![
+            json!({ "delete_exercise": { "workout_id": WORKOUT_ID, "exercise_id": EXERCISE_1 } }),
+            put_exercise(EXERCISE_1, 2),
+            json!({ "delete_workout": { "workout_id": WORKOUT_ID } }),
+        ]).await;
+        assert_eq!(version, 2);
+
+        let exercise = get(&store, &format!("WORKOUT#{WORKOUT_ID}#{EXERCISE_1}")).await.unwrap();
+        assert!(!exercise.contains_key("Deleted"));
+        assert!(get(&store, &format!("WORKOUT#{WORKOUT_ID}")).await.unwrap().contains_key("Deleted"));
+    }
+
+    #[tokio::test]
+    async fn operations_on_items_deleted_earlier_fail_before_writing() {
+        let store = MemoryStore::new();
+
+        let failed = apply_failed(&store, 0, vec![
+            put_workout(""),
+            json!({ "delete_workout": { "workout_id": WORKOUT_ID } }),
+            put_exercise(EXERCISE_1, 0),
+        ]).await;
+
+        assert_eq!(failed, (0, 0, vec![(2, "not-found", Some(format!("workout {WORKOUT_ID} doesn't exist")))]));
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn batches_are_split_into_chunks_of_max_transact_items() {
+        let store = MemoryStore::new();
+
+        // One item of each transaction is the VERSION item. Operations on an
+        // item that's already in the chunk don't take up more room.
+
+        let mut operations = (0..MAX_CHUNK_ITEMS as u32).map(put_measurement).collect::<Vec<_>>();
+        operations.push(put_measurement(0));
+
+        assert_eq!(apply(&store, 0, operations.clone()).await, 1);
+
+        operations.push(put_measurement(MAX_CHUNK_ITEMS as u32));
+
+        assert_eq!(apply(&store, 1, operations).await, 3);
+
+        // The items in the second chunk have the version that it wrote.
+
+        let modified_version = |item: common::DynamoDbItem| item["ModifiedVersion"].as_n().unwrap().to_owned();
+        let item = get(&store, &format!("MEASUREMENT#{}", measurement_id(0))).await.unwrap();
+        assert_eq!(modified_version(item), "2");
+
+        let item = get(&store, &format!("MEASUREMENT#{}", measurement_id(MAX_CHUNK_ITEMS as u32))).await.unwrap();
+        assert_eq!(modified_version(item), "3");
+    }
+
+    #[tokio::test]
+    async fn failed_chunk_reports_the_operations_that_were_applied() {
+        let store = MemoryStore::new();
+
+        let mut operations = (0..MAX_CHUNK_ITEMS as u32).map(put_measurement).collect::<Vec<_>>();
+        operations.push(json!({ "delete_workout": { "workout_id": WORKOUT_ID } }));
+
+        let failed = apply_failed(&store, 0, operations).await;
+
+        assert_eq!(failed, (1, MAX_CHUNK_ITEMS, vec![
+            (MAX_CHUNK_ITEMS, "not-found", Some(format!("workout {WORKOUT_ID} doesn't exist"))),
+        ]));
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,20 @@
+use lambda_http::{Request, RequestExt};
+use crate::{common, store::UserStore};
+
+pub async fn delete(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let exercise_type_id = params.first("exerciseTypeId").unwrap();
+
+    common::version_delete::<common::CustomExerciseType>(store, &req, exercise_type_id).await
+}
+
+pub async fn put(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let exercise_type_id = params.first("exerciseTypeId").unwrap();
+
+    common::version_modify(
+        store,
+        &req,
+        common::version_put_item::<common::CustomExerciseType>(exercise_type_id)
+    ).await
+}
@@ -0,0 +1,40 @@
+use lambda_http::{Request, http::StatusCode};
+use crate::{common, store::UserStore};
+
+pub const SETS_FILENAME: &str = "gym-log-sets.csv";
+pub const MEASUREMENTS_FILENAME: &str = "gym-log-measurements.csv";
+
+pub async fn get_sets(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+
+    get_export(store, &req, user_id, common::sets_to_csv, SETS_FILENAME).await
+}
+
+pub async fn get_measurements(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+
+    get_export(store, &req, user_id, common::measurements_to_csv, MEASUREMENTS_FILENAME).await
+}
+
+async fn get_export(
+    store: &impl UserStore,
+    req: &Request,
+    user_id: String,
+    to_csv: fn(&common::User) -> String,
+    filename: &str,
+) -> common::Result {
+    // This reads the collection in the same way as getting a snapshot so the
+    // export is consistent.
+
+    let version = store.read_version(&user_id).await?;
+    let etag = common::version_etag(version);
+
+    if common::if_none_match(req, &etag) {
+        return common::not_modified_response(&etag);
+    }
+
+    let items = common::query_snapshot(store, &user_id, version, false).await?;
+    let user = common::db_to_user(version, false, &items);
+
+    common::with_etag(common::csv_response(StatusCode::OK, to_csv(&user), filename), &etag)
+}
@@ -0,0 +1,23 @@
+use lambda_http::{Request, http::StatusCode};
+use serde::Serialize;
+use crate::{common, feed, store::UserStore};
+
+#[derive(Serialize)]
+struct FeedTokenRes {
+    token: String,
+}
+
+pub async fn post(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+    let token = feed::create_token(store, &user_id).await?;
+
+    common::json_response(StatusCode::OK, FeedTokenRes { token })
+}
+
+pub async fn delete(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+
+    feed::delete_token(store, &user_id).await?;
+
+    common::empty_response(StatusCode::OK)
+}
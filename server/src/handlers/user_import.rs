@@ -0,0 +1,13 @@
+use lambda_http::{Request, RequestExt, http::StatusCode};
+use crate::{common, import, store::UserStore};
+
+pub async fn get(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+    let params = req.path_parameters();
+    let job_id = params.first("jobId").unwrap();
+
+    match import::get_job(store, &user_id, job_id).await? {
+        Some(job) => common::json_response(StatusCode::OK, job),
+        None => Err(common::ApiError::NotFound),
+    }
+}
@@ -1,26 +1,19 @@
-use lambda_http::{Request, RequestExt, http::StatusCode};
-use crate::common;
+use lambda_http::{Request, RequestExt};
+use crate::{common, store::UserStore};
 
-pub async fn delete(req: Request) -> common::Result {
+pub async fn delete(store: &impl UserStore, req: Request) -> common::Result {
     let params = req.path_parameters();
     let measurement_id = params.first("measurementId").unwrap();
 
-    if !common::is_date(measurement_id) {
-        return common::empty_response(StatusCode::NOT_FOUND);
-    }
-
-    common::version_delete::<common::MeasurementSet>(&req, measurement_id).await
+    common::version_delete::<common::MeasurementSet>(store, &req, measurement_id).await
 }
 
-pub async fn put(req: Request) -> common::Result {
+pub async fn put(store: &impl UserStore, req: Request) -> common::Result {
     let params = req.path_parameters();
     let measurement_id = params.first("measurementId").unwrap();
 
-    if let Err(e) = common::validate_date(measurement_id) {
-        return e;
-    }
-
     common::version_modify(
+        store,
         &req,
         common::version_put_item::<common::MeasurementSet>(measurement_id)
     ).await
@@ -0,0 +1,52 @@
+use lambda_http::{Request, RequestExt, http::StatusCode};
+use crate::{common, program, store::UserStore};
+
+pub async fn delete(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let program_id = params.first("programId").unwrap();
+
+    common::version_delete::<common::Program>(store, &req, program_id).await
+}
+
+pub async fn put(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let program_id = params.first("programId").unwrap();
+
+    common::version_modify(
+        store,
+        &req,
+        common::version_put_item::<common::Program>(program_id)
+    ).await
+}
+
+pub async fn get_schedule(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let program_id = params.first("programId").unwrap();
+
+    let user_id = common::get_user_id(&req)?;
+    let (from, to) = common::get_date_range(&req)?;
+
+    // The schedule depends on the routines and on the workouts that are linked
+    // to the program so the whole collection is read.
+
+    let version = store.read_version(&user_id).await?;
+    let items = common::query_snapshot(store, &user_id, version, false).await?;
+    let user = common::db_to_user(version, false, &items);
+
+    let Some(program) = user.programs.iter().find(|p| p.program_id == program_id) else {
+        return Err(common::ApiError::NotFound);
+    };
+
+    let from = from.unwrap_or_else(|| common::parse_date(program.start_date.0).unwrap());
+    let to = to.unwrap_or_else(|| program::end_date(program));
+
+    if from > to {
+        return Err(common::ApiError::Validation("from date is after to date".into()));
+    }
+
+    let today = chrono::NaiveDateTime::from_timestamp_opt(common::now() as i64, 0)
+        .unwrap_or_default()
+        .date();
+
+    common::json_response(StatusCode::OK, program::schedule(program, &user, from, to, today))
+}
@@ -0,0 +1,9 @@
+use lambda_http::{Request, http::StatusCode};
+use crate::{common, records, store::UserStore};
+
+pub async fn get(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+    let records = records::read(store, &user_id).await?;
+
+    common::json_response(StatusCode::OK, records)
+}
@@ -0,0 +1,208 @@
+use std::{borrow::Cow, collections::HashMap, ops::ControlFlow};
+use lambda_http::{Request, RequestExt, http::StatusCode};
+use serde::Serialize;
+use crate::{common::{self, FromDynamoDb, ToDynamoDb}, records, store::{TransactItem, UserStore}};
+
+pub async fn delete(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let routine_id = params.first("routineId").unwrap();
+
+    common::version_delete::<common::Routine>(store, &req, routine_id).await
+}
+
+pub async fn put(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let routine_id = params.first("routineId").unwrap();
+
+    let user_id = common::get_user_id(&req)?;
+    let body = common::parse_request_json::<common::VersionModifyReq<common::Routine>>(&req)?;
+    let collection_prefix = common::get_collection_prefix(
+        common::collection_from_version(body.version)
+    );
+
+    let type_ids = body.item.exercises.0.iter()
+        .map(|e| e.r#type.0.as_ref())
+        .collect::<Vec<_>>();
+    let custom_types = common::get_custom_types(store, &user_id, &collection_prefix, &type_ids).await?;
+
+    for (i, exercise) in body.item.exercises.0.iter().enumerate() {
+        let Some(item) = custom_types.get(exercise.r#type.0.as_ref()) else {
+            continue;
+        };
+
+        let custom = item.as_ref()
+            .map(|item| common::CustomExerciseType::from_dynamo_db(&exercise.r#type.0, item));
+
+        common::validate_custom_targets(exercise, custom.as_ref())
+            .map_err(|e| common::ApiError::Validation(format!("exercises[{i}]: {e}")))?;
+    }
+
+    common::version_apply(
+        store,
+        &req,
+        body.version,
+        |items, new_version| {
+            common::version_put_item::<common::Routine>(routine_id)(items, body.item, new_version)
+        },
+        |_| ControlFlow::Continue(()),
+    ).await
+}
+
+/// Create a new workout from a routine. The request has the same body as
+/// putting a workout. The workout, its exercises and their sets are all given
+/// new IDs and the response has them in the same form as the changes from GET
+/// /user so that the client can apply them, along with the personal records
+/// that the targets of the routine broke.
+pub async fn instantiate(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let routine_id = params.first("routineId").unwrap();
+
+    let user_id = common::get_user_id(&req)?;
+    let body = common::parse_request_json::<common::VersionModifyReq<common::Workout>>(&req)?;
+    let new_version = body.version + 1;
+    let collection_prefix = common::get_collection_prefix(
+        common::collection_from_version(body.version)
+    );
+
+    // The routine and its custom types are read before the write. The write is
+    // conditional on the client's version so they can't have changed.
+
+    let routine_key = common::make_key_from_id::<common::Routine>(&collection_prefix, routine_id);
+    let Some(routine_item) = store.get_item(&user_id, &routine_key).await?
+        .filter(|item| !item.contains_key("Deleted"))
+    else {
+        return Err(common::ApiError::NotFound);
+    };
+
+    let routine = common::Routine::from_dynamo_db(routine_id, &routine_item);
+
+    let type_ids = routine.exercises.0.iter()
+        .map(|e| e.r#type.0.as_ref())
+        .collect::<Vec<_>>();
+    let custom_types = common::get_custom_types(store, &user_id, &collection_prefix, &type_ids).await?;
+
+    let mut exercise_fields = Vec::new();
+
+    for (i, exercise) in routine.exercises.0.iter().enumerate() {
+        let r#type = exercise.r#type.0.as_ref();
+        let fields = match common::get_set_kind(r#type) {
+            Some(kind) => kind.fields().to_vec(),
+            None => match custom_types.get(r#type).and_then(Option::as_ref) {
+                Some(item) => common::CustomExerciseType::from_dynamo_db(r#type, item).fields.0,
+                None => {
+                    return Err(common::ApiError::Validation(
+                        format!("exercises[{i}]: unknown exercise type {type}")
+                    ));
+                }
+            },
+        };
+
+        exercise_fields.push(fields);
+    }
+
+    let workout_id = uuid::Uuid::new_v4().to_string();
+    let exercise_ids = routine.exercises.0.iter()
+        .map(|_| format!("{workout_id}#{}", uuid::Uuid::new_v4()))
+        .collect::<Vec<_>>();
+    let set_ids = routine.exercises.0.iter()
+        .map(|e| e.sets.0.iter().map(|_| uuid::Uuid::new_v4().to_string()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let workout = common::Workout {
+        workout_id: &workout_id,
+        modified_version: new_version,
+        ..body.item
+    };
+
+    let exercises = routine.exercises.0.iter()
+        .zip(exercise_fields.iter())
+        .zip(exercise_ids.iter().zip(set_ids.iter()))
+        .enumerate()
+        .map(|(order, ((exercise, fields), (exercise_id, set_ids)))| common::Exercise {
+            workout_exercise_id: exercise_id,
+            order: order as u32,
+            r#type: common::MaxLenStr(Cow::Borrowed(exercise.r#type.0.as_ref())),
+            notes: common::MaxLenStr(Cow::Borrowed("")),
+            sets: common::MaxLenVec(exercise.sets.0.iter()
+                .zip(set_ids.iter())
+                .map(|(target, set_id)| make_set(set_id, fields, target))
+                .collect()),
+            modified_version: new_version,
+        })
+        .collect::<Vec<_>>();
+
+    // The exercises are new so they can only raise records.
+
+    let types = exercises.iter()
+        .map(|e| e.r#type.0.as_ref())
+        .collect::<Vec<_>>();
+    let stored_records = records::read_types(store, &user_id, &types).await?;
+    let writes = exercises.iter()
+        .map(|e| records::Write::Put(e.workout_exercise_id.to_owned(), e))
+        .collect::<Vec<_>>();
+    let tracked = records::track(store, &user_id, body.version, stored_records, &writes).await?;
+    let record_items = tracked.combined_items();
+
+    common::version_apply(
+        store,
+        &req,
+        body.version,
+        |items, new_version| {
+            for (id, item) in std::iter::once(make_item(&collection_prefix, &workout, new_version))
+                .chain(exercises.iter().map(|e| make_item(&collection_prefix, e, new_version)))
+            {
+                items.push(TransactItem::Put { id, item });
+            }
+
+            items.extend(record_items);
+        },
+        |_| ControlFlow::Continue(()),
+    ).await?;
+
+    common::json_response(StatusCode::OK, InstantiateRes {
+        changes: common::User {
+            version: new_version,
+            workouts: vec![workout],
+            exercises,
+            ..Default::default()
+        },
+        records: tracked.records.broken_since(&tracked.stored),
+    })
+}
+
+#[derive(Serialize)]
+struct InstantiateRes<'a> {
+    #[serde(flatten)]
+    changes: common::User<'a>,
+    /// The personal records that the targets of the routine broke.
+    records: Vec<records::BrokenRecord<'a>>,
+}
+
+/// Make a set with the fields that the exercise type records. The fields that
+/// the target doesn't have a default for are 0.
+fn make_set<'a>(set_id: &'a str, fields: &[&str], target: &common::TargetSet) -> common::Set<'a> {
+    let field = |field, default: Option<u32>| {
+        fields.contains(&field).then(|| default.unwrap_or(0))
+    };
+
+    common::Set {
+        set_id: common::Uuid(set_id),
+        repetitions: field("repetitions", target.repetitions),
+        resistance: field("resistance", target.resistance),
+        speed: field("speed", None),
+        distance: field("distance", None),
+        duration: field("duration", None),
+    }
+}
+
+fn make_item<'a, T: ToDynamoDb<'a>>(
+    collection_prefix: &str,
+    entity: &T,
+    new_version: u64,
+) -> (String, common::DynamoDbItem) {
+    let mut item = HashMap::new();
+
+    entity.insert_dynamo_db(&mut item, Some(new_version));
+
+    (common::make_key_from_entity(collection_prefix, entity), item)
+}
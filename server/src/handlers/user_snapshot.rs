@@ -1,407 +1,222 @@
-use std::collections::HashMap;
-use aws_sdk_dynamodb::{
-    Client,
-    error::SdkError,
-    operation::update_item::UpdateItemError,
-    types::{AttributeValue, Select, WriteRequest, DeleteRequest, ReturnValue, PutRequest},
-};
-use lambda_http::{Request, http::StatusCode};
-use tokio_stream::StreamExt;
-use crate::common;
-
-pub async fn get(req: Request) -> common::Result {
-    let user_id = common::get_user_id(&req);
-    let db = common::get_db_client();
-
-    get_snapshot(db, user_id).await
-}
-
-pub async fn put(req: Request) -> common::Result {
-    let user_id = common::get_user_id(&req);
-    let db = common::get_db_client();
+use std::borrow::Cow;
+use lambda_http::{Request, RequestExt, http::{StatusCode, header::{HeaderValue, LOCATION}}};
+use crate::{common, import, importers, store::{LockOutcome, UserStore}};
+use serde::Deserialize;
+use super::user_export::SETS_FILENAME;
 
-    let user = match common::parse_request_json::<common::User>(&req) {
-        Ok(b) => b,
-        Err(e) => return e,
-    };
+pub async fn get(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
 
-    put_snapshot(db, user_id, user).await
+    get_snapshot(store, &req, user_id).await
 }
 
-async fn get_snapshot(db: &Client, user_id: String) -> common::Result {
-    // We're not using a read lock. Instead, we check the version before and
-    // after the operation. If the version changed, then we have an inconsistent
-    // snapshot and we'll have to try again.
-
-    let get_version = db.get_item()
-        .table_name(common::TABLE_USER)
-        .key("UserId", AttributeValue::S(user_id.clone()))
-        .key("Id", AttributeValue::S("VERSION".into()))
-        .send()
-        .await?;
-
-    let version = get_version.item()
-        .map_or(0, |i| common::as_number(&i["Version"]));
-    let collection = common::collection_from_version(version);
-
-    let items = db.query()
-        .table_name(common::TABLE_USER)
-        .key_condition_expression("UserId = :userId AND begins_with(Id, :collection)")
-        .filter_expression("attribute_not_exists(Deleted)")
-        .expression_attribute_values(":userId", AttributeValue::S(user_id.clone()))
-        .expression_attribute_values(
-            ":collection",
-            AttributeValue::S(common::get_collection_prefix(collection)),
-        )
-        .select(Select::AllAttributes)
-        .into_paginator()
-        .items()
-        .send()
-        .collect::<Result<Vec<_>, _>>()
-        .await?;
-
-    let get_version = db.get_item()
-        .table_name(common::TABLE_USER)
-        .key("UserId", AttributeValue::S(user_id.clone()))
-        .key("Id", AttributeValue::S("VERSION".into()))
-        .send()
-        .await?;
-
-    let new_version = get_version.item()
-        .map_or(0, |i| common::as_number(&i["Version"]));
-
-    if new_version != version {
-        common::retry_later_response(0)
-    } else {
-        common::json_response(
-            StatusCode::OK,
-            common::db_to_user(version, false, &items),
-        )
-    }
-}
+pub async fn put(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
 
-async fn put_snapshot(
-    db: &Client,
-    user_id: String,
-    import_user: common::User<'_>,
-) -> common::Result {
-    // Acquire the lock. Writes aren't allowed while this lock is valid. Reads
-    // are still allowed though. Reads will be on the current collection which
-    // won't change while the lock is valid. If this step fails, nothing
-    // happens.
-
-    const LOCK_DURATION_S: u64 = 60;
-
-    let now = common::now();
-    let now_attr = AttributeValue::N(now.to_string());
-    let lock_expire = now + LOCK_DURATION_S;
-    let lock_expire_attr = AttributeValue::N(lock_expire.to_string());
-
-    // Unfortunately, there doesn't seem to be a way to do a conditional update
-    // and get the item if the condition is true or false. With an UpdateItem,
-    // you can get the item if it's true but not false. With TransactWriteItems,
-    // it's the reverse. In either case, you can't have both. It's a very
-    // frustrating limitation.
-    //
-    // If we do an UpdateItem, then we'll need to do a GetItem if it fails. In
-    // that case, LockedUntil might have changed since we failed to acquire the
-    // lock. That doesn't really matter though. A TransactWriteItems would be
-    // slower. We're optimizing the common case.
-
-    let acquire_lock_result = db.update_item()
-        .table_name(common::TABLE_USER)
-        .key("UserId", AttributeValue::S(user_id.clone()))
-        .key("Id", AttributeValue::S("VERSION".into()))
-        .update_expression("SET LockedUntil = :lockExpire")
-        .condition_expression(
-            "attribute_not_exists(LockedUntil) OR LockedUntil <= :now"
-        )
-        .expression_attribute_values(":lockExpire", lock_expire_attr)
-        .expression_attribute_values(":now", now_attr)
-        .return_values(ReturnValue::AllOld)
-        .send()
-        .await;
-
-    let acquire_lock = match acquire_lock_result {
-        Ok(o) => o,
-        Err(e) => {
-            if let SdkError::ServiceError(service_error) = &e {
-                if let UpdateItemError::ConditionalCheckFailedException(_) = &service_error.err() {
-                    // If the conditional expression is false, then LockedUntil
-                    // is in the future but the item could have changed since
-                    // then so we can't assume anything about it.
-
-                    let get_lock = db.get_item()
-                        .table_name(common::TABLE_USER)
-                        .key("UserId", AttributeValue::S(user_id.clone()))
-                        .key("Id", AttributeValue::S("VERSION".into()))
-                        .send()
-                        .await?;
-
-                    let delay = get_lock.item()
-                        .and_then(|i| i.get("LockedUntil"))
-                        .map(common::as_number::<u64>)
-                        .map_or(0, |until| until.saturating_sub(common::now()));
-
-                    return common::retry_later_response(delay);
-                }
-            }
+    // The payload is validated here so that the client finds out about any
+    // problems with it immediately rather than from the job.
 
-            return Err(e.into());
-        }
-    };
+    let import = common::parse_request_json::<common::User>(&req)?;
 
-    // Get the current collection. We need this to apply the import changes
-    // relative to the current state of the database. If this step fails, the
-    // database will be read-only until the lock expires. Apart from that, there
-    // are no side effects.
-
-    let curr_version = acquire_lock.attributes()
-        .map_or(0, |i| common::as_number(&i["Version"]));
-    let curr_collection = common::collection_from_version(curr_version);
-    let curr_collection_prefix = common::get_collection_prefix(curr_collection);
-    let new_collection = curr_collection + 1;
-    let new_version = common::version_from_collection(new_collection);
-
-    let curr_items = db.query()
-        .table_name(common::TABLE_USER)
-        .key_condition_expression("UserId = :userId AND begins_with(Id, :collection)")
-        .expression_attribute_values(":userId", AttributeValue::S(user_id.clone()))
-        .expression_attribute_values(
-            ":collection",
-            AttributeValue::S(curr_collection_prefix.clone()),
-        )
-        .select(Select::AllAttributes)
-        .into_paginator()
-        .items()
-        .send()
-        .collect::<Result<Vec<_>, _>>()
-        .await?;
-
-    let curr_user = common::db_to_user(curr_version, false, &curr_items);
-
-    // Combine the imported collection with the current collection to determine
-    // the new collection and then write it out in batches.
-
-    common::batch_write(
-        db,
-        common::TABLE_USER,
-        make_import_batch(user_id.clone(), new_version, &curr_user, &import_user),
-    ).await?;
-
-    // Release the lock and switch to the new collection. If this step fails,
-    // the database will be read-only until the lock expires. The new collection
-    // will remain until it is overwritten by the next import attempt.
-
-    db.update_item()
-        .table_name(common::TABLE_USER)
-        .key("UserId", AttributeValue::S(user_id.clone()))
-        .key("Id", AttributeValue::S("VERSION".into()))
-        .update_expression("REMOVE LockedUntil SET Version = :version")
-        .expression_attribute_values(":version", AttributeValue::N(new_version.to_string()))
-        .send()
-        .await?;
-
-    // Clear out the old collection. If this step fails, then the old collection
-    // will remain. There is currently no mechanism to remove the data if this
-    // step fails. It's not doing any harm really. It's just sitting there.
-    // Given a user ID and the current collection ID, it's easy to query for all
-    // of this junk data so perhaps there could be a garbage collection
-    // mechanism?
-
-    common::batch_write(
-        db,
-        common::TABLE_USER,
-        make_delete_batch(user_id, &curr_collection_prefix, &curr_user),
-    ).await?;
-
-    common::empty_response(StatusCode::OK)
+    import_snapshot(store, &req, user_id, &import, req.body(), true).await
 }
 
-fn make_import_batch<'a>(
-    user_id: String,
-    new_version: u64,
-    curr: &common::User<'a>,
-    import: &common::User<'a>,
-) -> Vec<WriteRequest> {
-    let mut requests = Vec::new();
-
-    let new_collection_prefix = common::get_collection_prefix(
-        common::collection_from_version(new_version)
-    );
+pub async fn put_csv(store: &impl UserStore, req: Request) -> common::Result {
+    #[derive(Deserialize)]
+    struct CsvSnapshot<'a> {
+        #[serde(default, borrow)]
+        sets: Cow<'a, str>,
+        #[serde(default, borrow)]
+        measurements: Cow<'a, str>,
+    }
 
-    make_import_batch_for::<common::MeasurementSet>(
-        &mut requests,
-        user_id.clone(),
-        &new_collection_prefix,
-        new_version,
-        import,
-        curr,
-    );
+    let user_id = common::get_user_id(&req)?;
+    let csv = common::parse_request_json::<CsvSnapshot>(&req)?;
 
-    make_import_batch_for::<common::Workout>(
-        &mut requests,
-        user_id.clone(),
-        &new_collection_prefix,
-        new_version,
-        import,
-        curr,
-    );
+    // The CSV is converted to the JSON representation so that the worker
+    // doesn't need to know where the payload came from.
 
-    make_import_batch_for::<common::Exercise>(
-        &mut requests,
-        user_id,
-        &new_collection_prefix,
-        new_version,
-        import,
-        curr,
-    );
+    let payload = common::csv_to_user_json(&csv.sets, &csv.measurements)?.to_string();
+    let import = serde_json::from_str::<common::User>(&payload)
+        .map_err(|e| common::ApiError::Validation(e.to_string()))?;
 
-    requests
+    import_snapshot(store, &req, user_id, &import, payload.as_bytes(), false).await
 }
 
-fn make_import_batch_for<'a, T>(
-    requests: &mut Vec<WriteRequest>,
-    user_id: String,
-    collection_prefix: &str,
-    version: u64,
-    import: &common::User<'a>,
-    curr: &common::User<'a>,
-)
-    where T: common::ToDynamoDb<'a> + common::Equivalent + common::UserField<'a>
-{
-    let mut curr_entities = T::extract_from_user(curr).iter()
-        .map(|e| (e.get_id(), e))
-        .collect::<HashMap<_, _>>();
-    let mut curr_deleted_entities: HashMap<&str, u64> = HashMap::from_iter(
-        T::extract_deleted_from_user(curr).iter().map(|d| (d.id, d.modified_version))
-    );
+pub async fn put_tracker(store: &impl UserStore, req: Request) -> common::Result {
+    #[derive(Deserialize)]
+    struct TrackerSnapshot<'a> {
+        format: importers::Format,
+        #[serde(borrow)]
+        data: Cow<'a, str>,
+        #[serde(flatten, borrow)]
+        options: importers::Options<'a>,
+    }
 
-    for import_entity in T::extract_from_user(import).iter() {
-        curr_deleted_entities.remove(import_entity.get_id());
+    let user_id = common::get_user_id(&req)?;
+    let tracker = common::parse_request_json::<TrackerSnapshot>(&req)?;
 
-        let mut modified_version = Some(version);
+    let payload = importers::convert(tracker.format, &tracker.data, &tracker.options)?.to_string();
+    let import = serde_json::from_str::<common::User>(&payload)
+        .map_err(|e| common::ApiError::Validation(e.to_string()))?;
 
-        let entity = if let Some(curr_entity) = curr_entities.remove(import_entity.get_id()) {
-            if curr_entity.equiv(import_entity) {
-                modified_version = None;
-                curr_entity
-            } else {
-                import_entity
-            }
-        } else {
-            import_entity
-        };
+    import_snapshot(store, &req, user_id, &import, payload.as_bytes(), false).await
+}
 
-        let mut item = HashMap::new();
+/// Import a payload. Formats that can only express some kinds of entity can't
+/// be imported with the replace mode because it would delete every entity of
+/// the other kinds.
+async fn import_snapshot(
+    store: &impl UserStore,
+    req: &Request,
+    user_id: String,
+    import: &common::User<'_>,
+    payload: &[u8],
+    allow_replace: bool,
+) -> common::Result {
+    let query_map = req.query_string_parameters();
+    let dry_run = query_map.first("dry_run").is_some_and(|d| d == "true");
+    let mode = match query_map.first("mode") {
+        Some(m) => match import::Mode::parse(m) {
+            Some(m) => m,
+            None => return Err(common::ApiError::Validation("invalid import mode".into())),
+        },
+        None => import::Mode::default(),
+    };
 
-        item.insert("UserId".into(), AttributeValue::S(user_id.clone()));
-        item.insert("Id".into(), AttributeValue::S(
-            common::make_key_from_entity(collection_prefix, entity)
+    if mode == import::Mode::Replace && !allow_replace {
+        return Err(common::ApiError::Validation(
+            "the replace mode isn't supported for this format".into()
         ));
-        entity.insert_dynamo_db(&mut item, modified_version);
+    }
 
-        requests.push(make_put_request(item));
+    if dry_run {
+        diff_snapshot(store, user_id, mode, import).await
+    } else {
+        put_snapshot(store, user_id, mode, import, payload).await
     }
+}
 
-    for entity in curr_entities.values() {
-        let mut item = HashMap::new();
+async fn get_snapshot(
+    store: &impl UserStore,
+    req: &Request,
+    user_id: String,
+) -> common::Result {
+    // We're not using a read lock. Instead, we check the version before and
+    // after the operation. If the version changed, then we have an inconsistent
+    // snapshot and we'll have to try again.
 
-        item.insert("UserId".into(), AttributeValue::S(user_id.clone()));
-        item.insert("Id".into(), AttributeValue::S(
-            common::make_key_from_entity(collection_prefix, *entity)
-        ));
-        entity.insert_dynamo_db(&mut item, None);
+    let version = store.read_version(&user_id).await?;
+    let etag = common::version_etag(version);
+
+    // If the client already has a snapshot of this version, then we can skip
+    // the query and the second read.
 
-        requests.push(make_put_request(item));
+    if common::if_none_match(req, &etag) {
+        return common::not_modified_response(&etag);
     }
 
-    for (id, modified_version) in curr_deleted_entities.iter() {
-        let mut item = HashMap::new();
+    let items = common::query_snapshot(store, &user_id, version, false).await?;
+    let user = common::db_to_user(version, false, &items);
 
-        item.insert("UserId".into(), AttributeValue::S(user_id.clone()));
-        item.insert("Id".into(), AttributeValue::S(
-            common::make_key_from_id::<T>(collection_prefix, id)
-        ));
-        item.insert("Deleted".into(), AttributeValue::Bool(true));
-        item.insert("ModifiedVersion".into(), AttributeValue::N(
-            modified_version.to_string()
-        ));
+    // Spreadsheets can't do much with the JSON so the sets can be requested as
+    // CSV instead (see user_export).
 
-        requests.push(make_put_request(item));
-    }
-}
+    let result = if common::accepts(req, "text/csv") {
+        common::csv_response(StatusCode::OK, common::sets_to_csv(&user), SETS_FILENAME)
+    } else {
+        common::json_response(StatusCode::OK, user)
+    };
 
-fn make_put_request(item: HashMap<String, AttributeValue>) -> WriteRequest {
-    WriteRequest::builder()
-        .put_request(PutRequest::builder()
-            .set_item(Some(item))
-            .build()
-        )
-        .build()
+    common::with_etag(result, &etag)
 }
 
-fn make_delete_batch(
+async fn diff_snapshot(
+    store: &impl UserStore,
     user_id: String,
-    collection_prefix: &str,
-    user: &common::User,
-) -> Vec<WriteRequest> {
-    let mut requests = Vec::new();
-
-    make_delete_batch_for::<common::MeasurementSet>(
-        &mut requests,
-        user_id.clone(),
-        collection_prefix,
-        user,
-    );
+    mode: import::Mode,
+    import: &common::User<'_>,
+) -> common::Result {
+    // This compares the import to the current collection in the same way as
+    // the worker but doesn't take the lock. As with getting a snapshot, the
+    // version is checked before and after reading the collection instead.
+    // Nothing stops the collection from changing before the real import so
+    // this is only a preview.
 
-    make_delete_batch_for::<common::Workout>(
-        &mut requests,
-        user_id.clone(),
-        collection_prefix,
-        user,
-    );
+    let version = store.read_version(&user_id).await?;
+    let items = common::query_snapshot(store, &user_id, version, true).await?;
+    let curr = common::db_to_user(version, false, &items);
 
-    make_delete_batch_for::<common::Exercise>(
-        &mut requests,
-        user_id.clone(),
-        collection_prefix,
-        user,
-    );
+    import::check_custom_types(mode, &curr, import).map_err(common::ApiError::Validation)?;
 
-    requests
+    common::json_response(StatusCode::OK, import::diff(mode, &curr, import))
 }
 
-fn make_delete_batch_for<'a, T>(
-    requests: &mut Vec<WriteRequest>,
+async fn put_snapshot(
+    store: &impl UserStore,
     user_id: String,
-    collection_prefix: &str,
-    user: &common::User<'a>,
-)
-    where T: common::ToDynamoDb<'a> + common::UserField<'a>
-{
-    for entity in T::extract_from_user(user) {
-        requests.push(make_delete_request(
-            user_id.clone(),
-            common::make_key_from_entity(collection_prefix, entity),
-        ));
-    }
+    mode: import::Mode,
+    import: &common::User<'_>,
+    payload: &[u8],
+) -> common::Result {
+    // Acquire the lock. Writes aren't allowed while this lock is valid. Reads
+    // are still allowed though. Reads will be on the current collection which
+    // won't change while the lock is valid. If this step fails, nothing
+    // happens. The job ID is the token of the lock so that the worker can
+    // extend and release it.
+
+    let job_id = uuid::Uuid::new_v4().to_string();
+    let lock_expire = common::now() + import::LOCK_DURATION_S;
+
+    let curr_version = match store.acquire_import_lock(&user_id, &job_id, lock_expire).await? {
+        LockOutcome::Acquired { version } => version,
+        LockOutcome::Held { until } => {
+            return Err(common::ApiError::Locked {
+                retry_after: until.saturating_sub(common::now()),
+            });
+        }
+    };
 
-    for deleted in T::extract_deleted_from_user(user) {
-        requests.push(make_delete_request(
-            user_id.clone(),
-            common::make_key_from_id::<T>(collection_prefix, deleted.id),
-        ));
-    }
+    // If the job can't be created for any reason, then the lock is released
+    // without changing the version. Otherwise, the worker releases it.
+
+    let job = match create_job(store, &user_id, job_id.clone(), curr_version, mode, import, payload).await {
+        Ok(job) => job,
+        Err(e) => {
+            if let Err(release_error) = store.release_import_lock(&user_id, &job_id, curr_version).await {
+                tracing::error!("Releasing the import lock of {user_id} failed: {release_error}");
+            }
+            return Err(e);
+        }
+    };
+
+    let mut res = common::json_response(StatusCode::ACCEPTED, &job)?;
+    res.headers_mut().insert(
+        LOCATION,
+        HeaderValue::from_str(&format!("/user/import/{}", job.job_id)).unwrap(),
+    );
+
+    Ok(res)
 }
 
-fn make_delete_request(user_id: String, key: String) -> WriteRequest {
-    WriteRequest::builder()
-        .delete_request(
-            DeleteRequest::builder()
-                .key("UserId", AttributeValue::S(user_id))
-                .key("Id", AttributeValue::S(key))
-                .build()
-        )
-        .build()
+async fn create_job(
+    store: &impl UserStore,
+    user_id: &str,
+    job_id: String,
+    curr_version: u64,
+    mode: import::Mode,
+    import: &common::User<'_>,
+    payload: &[u8],
+) -> Result<import::Job, common::ApiError> {
+    // Exercises can refer to custom types in the current collection so they
+    // can only be checked now that the collection can't change.
+
+    let items = store.query_collection(user_id, common::collection_from_version(curr_version), true).await?;
+    let curr = common::db_to_user(curr_version, false, &items);
+
+    import::check_custom_types(mode, &curr, import).map_err(common::ApiError::Validation)?;
+
+    // Hand the payload over to the worker (see import).
+
+    Ok(import::create_job(store, user_id, job_id, curr_version, mode, payload).await?)
 }
@@ -0,0 +1,40 @@
+use lambda_http::{Request, RequestExt, http::StatusCode};
+use crate::{common, stats, store::UserStore};
+
+pub async fn get_exercise(store: &impl UserStore, req: Request) -> common::Result {
+    let params = req.path_parameters();
+    let r#type = params.first("type").unwrap();
+
+    let user_id = common::get_user_id(&req)?;
+    let (from, to) = common::get_date_range(&req)?;
+    let query_map = req.query_string_parameters();
+    let formula = match query_map.first("formula") {
+        Some(f) => stats::Formula::parse(f)
+            .ok_or_else(|| common::ApiError::Validation("invalid formula".into()))?,
+        None => stats::Formula::default(),
+    };
+
+    let version = store.read_version(&user_id).await?;
+    let items = common::query_snapshot(store, &user_id, version, false).await?;
+    let user = common::db_to_user(version, false, &items);
+
+    let Some(fields) = stats::type_fields(&user, r#type) else {
+        return Err(common::ApiError::NotFound);
+    };
+
+    common::json_response(
+        StatusCode::OK,
+        stats::exercise(&user, r#type, &fields, formula, from, to),
+    )
+}
+
+pub async fn get_cardio(store: &impl UserStore, req: Request) -> common::Result {
+    let user_id = common::get_user_id(&req)?;
+    let (from, to) = common::get_date_range(&req)?;
+
+    let version = store.read_version(&user_id).await?;
+    let items = common::query_snapshot(store, &user_id, version, false).await?;
+    let user = common::db_to_user(version, false, &items);
+
+    common::json_response(StatusCode::OK, stats::cardio(&user, from, to))
+}
@@ -1,26 +1,19 @@
-use lambda_http::{Request, RequestExt, http::StatusCode};
-use crate::common;
+use lambda_http::{Request, RequestExt};
+use crate::{common, store::UserStore};
 
-pub async fn delete(req: Request) -> common::Result {
+pub async fn delete(store: &impl UserStore, req: Request) -> common::Result {
     let params = req.path_parameters();
     let workout_id = params.first("workoutId").unwrap();
 
-    if !common::is_uuid(workout_id) {
-        return common::empty_response(StatusCode::NOT_FOUND);
-    }
-
-    common::version_delete::<common::Workout>(&req, workout_id).await
+    common::version_delete::<common::Workout>(store, &req, workout_id).await
 }
 
-pub async fn put(req: Request) -> common::Result {
+pub async fn put(store: &impl UserStore, req: Request) -> common::Result {
     let params = req.path_parameters();
     let workout_id = params.first("workoutId").unwrap();
 
-    if let Err(e) = common::validate_uuid(workout_id) {
-        return e;
-    }
-
     common::version_modify(
+        store,
         &req,
         common::version_put_item::<common::Workout>(workout_id)
     ).await
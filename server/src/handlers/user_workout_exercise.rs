@@ -1,44 +1,49 @@
 use std::ops::ControlFlow;
 use lambda_http::{Request, RequestExt, http::StatusCode};
-use crate::common;
+use serde::Serialize;
+use crate::{common, records, store::{TransactItem, UserStore}};
 
-pub async fn delete(req: Request) -> common::Result {
+pub async fn delete(store: &impl UserStore, req: Request) -> common::Result {
     let params = req.path_parameters();
     let workout_id = params.first("workoutId").unwrap();
     let exercise_id = params.first("exerciseId").unwrap();
 
-    if !common::is_uuid(workout_id) || !common::is_uuid(exercise_id) {
-        return common::empty_response(StatusCode::NOT_FOUND);
-    }
-
-    let client_version = match common::parse_request_json::<common::VersionDeleteReq>(&req) {
-        Ok(b) => b.version,
-        Err(r) => return r,
-    };
+    let user_id = common::get_user_id(&req)?;
+    let client_version = common::parse_request_json::<common::VersionDeleteReq>(&req)?.version;
     let collection_prefix = common::get_collection_prefix(
         common::collection_from_version(client_version)
     );
 
+    let workout_exercise_id = format!("{workout_id}#{exercise_id}");
+    let stored_records = read_records(store, &user_id, &collection_prefix, &workout_exercise_id, None).await?;
+    let mut tracked = records::track(
+        store,
+        &user_id,
+        client_version,
+        stored_records,
+        &[records::Write::Delete(workout_exercise_id)],
+    ).await?;
+    let record_items = tracked.items.pop().unwrap();
+
     common::version_apply(
+        store,
         &req,
         client_version,
-        |mut builder, user_id, new_version| {
-            builder = common::check_exists(
-                builder,
-                user_id.clone(),
-                format!("{collection_prefix}WORKOUT#{workout_id}"),
-            );
-
-            common::version_delete_item(
-                builder,
-                user_id,
-                format!("{collection_prefix}WORKOUT#{workout_id}#{exercise_id}"),
+        |items, new_version| {
+            items.push(TransactItem::CheckExists {
+                id: format!("{collection_prefix}WORKOUT#{workout_id}"),
+            });
+
+            items.push(TransactItem::Delete {
+                id: format!("{collection_prefix}WORKOUT#{workout_id}#{exercise_id}"),
                 new_version,
-            )
+            });
+
+            items.extend(record_items);
         },
-        |reasons| {
-            if reasons.iter().any(|r| r.code() == Some("ConditionalCheckFailed")) {
-                ControlFlow::Break(common::empty_response(StatusCode::NOT_FOUND))
+        |failed| {
+            if failed.iter().any(|f| *f) {
+                ControlFlow::Break(common::ApiError::NotFound)
             } else {
                 ControlFlow::Continue(())
             }
@@ -46,47 +51,82 @@ pub async fn delete(req: Request) -> common::Result {
     ).await
 }
 
-pub async fn put(req: Request) -> common::Result {
+pub async fn put(store: &impl UserStore, req: Request) -> common::Result {
     let params = req.path_parameters();
     let workout_id = params.first("workoutId").unwrap();
     let exercise_id = params.first("exerciseId").unwrap();
+    let workout_exercise_id = format!("{workout_id}#{exercise_id}");
 
-    if let Err(e) = common::validate_uuid(workout_id) {
-        return e;
-    }
-
-    if let Err(e) = common::validate_uuid(exercise_id) {
-        return e;
-    }
-
-    let body = match common::parse_request_json::<common::VersionModifyReq<_>>(&req) {
-        Ok(b) => b,
-        Err(e) => return e,
-    };
+    let user_id = common::get_user_id(&req)?;
+    let body = common::parse_request_json::<common::VersionModifyReq<common::Exercise>>(&req)?;
     let collection_prefix = common::get_collection_prefix(
         common::collection_from_version(body.version)
     );
 
+    common::check_custom_type(store, &user_id, &collection_prefix, &body.item).await?;
+
+    // The records are computed before the write in the same way as the custom
+    // type is checked. The write is conditional on the client's version so the
+    // exercises and records can't have changed.
+
+    let stored_records = read_records(
+        store,
+        &user_id,
+        &collection_prefix,
+        &workout_exercise_id,
+        Some(&body.item.r#type.0),
+    ).await?;
+    let writes = [records::Write::Put(workout_exercise_id.clone(), &body.item)];
+    let mut tracked = records::track(store, &user_id, body.version, stored_records, &writes).await?;
+    let record_items = tracked.items.pop().unwrap();
+
     common::version_apply(
+        store,
         &req,
         body.version,
-        |mut builder, user_id, new_version| {
-            builder = common::check_exists(
-                builder,
-                user_id.clone(),
-                common::make_key_from_id::<common::Workout>(&collection_prefix, workout_id),
-            );
-
-            common::version_put_item::<common::Exercise>(
-                &format!("{workout_id}#{exercise_id}")
-            )(builder, body.item, user_id, new_version)
+        |items, new_version| {
+            items.push(TransactItem::CheckExists {
+                id: common::make_key_from_id::<common::Workout>(&collection_prefix, workout_id),
+            });
+
+            common::version_put_item::<common::Exercise>(&workout_exercise_id)(items, body.item, new_version);
+            items.extend(record_items);
         },
-        |reasons| {
-            if reasons[0].code() == Some("ConditionalCheckFailed") {
-                return ControlFlow::Break(common::empty_response(StatusCode::NOT_FOUND));
+        |failed| {
+            if failed[0] {
+                return ControlFlow::Break(common::ApiError::NotFound);
             }
 
             ControlFlow::Continue(())
         }
-    ).await
+    ).await?;
+
+    common::json_response(StatusCode::OK, PutRes {
+        records: tracked.records.broken_since(&tracked.stored),
+    })
+}
+
+/// Read the records of the types that a write to an exercise affects. These are
+/// the type that it's being given, if any, and the type that it has now.
+async fn read_records(
+    store: &impl UserStore,
+    user_id: &str,
+    collection_prefix: &str,
+    workout_exercise_id: &str,
+    new_type: Option<&str>,
+) -> Result<records::Records, common::ApiError> {
+    let key = common::make_key_from_id::<common::Exercise>(collection_prefix, workout_exercise_id);
+    let item = store.get_item(user_id, &key).await?
+        .filter(|item| !item.contains_key("Deleted"));
+    let types = new_type.into_iter()
+        .chain(item.as_ref().map(|item| item["Type"].as_s().unwrap().as_str()))
+        .collect::<Vec<_>>();
+
+    Ok(records::read_types(store, user_id, &types).await?)
+}
+
+#[derive(Serialize)]
+struct PutRes<'a> {
+    /// The personal records that the exercise broke.
+    records: Vec<records::BrokenRecord<'a>>,
 }
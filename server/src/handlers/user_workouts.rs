@@ -0,0 +1,29 @@
+use lambda_http::{Request, RequestExt, http::StatusCode};
+use crate::{common, feed, store::UserStore};
+
+pub async fn get_ical(store: &impl UserStore, req: Request) -> common::Result {
+    // This is requested by calendar apps rather than the client so it's
+    // authenticated with a feed token instead of the Authorization header (see
+    // feed).
+
+    let token = req.query_string_parameters()
+        .first("token")
+        .map(str::to_owned)
+        .ok_or(common::ApiError::Unauthorized("missing feed token"))?;
+    let user_id = feed::verify_token(store, &token).await?;
+
+    let version = store.read_version(&user_id).await?;
+    let etag = common::version_etag(version);
+
+    if common::if_none_match(&req, &etag) {
+        return common::not_modified_response(&etag);
+    }
+
+    let items = common::query_snapshot(store, &user_id, version, false).await?;
+    let user = common::db_to_user(version, false, &items);
+
+    common::with_etag(common::ical_response(
+        StatusCode::OK,
+        common::workouts_to_ical(&user, common::now()),
+    ), &etag)
+}
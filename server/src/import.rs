@@ -0,0 +1,842 @@
+use std::{collections::HashMap, time::Instant};
+use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue};
+use lambda_http::Error;
+use serde::Serialize;
+use crate::{common::{self, DynamoDbItem}, records, store::{BatchWrite, UserStore}};
+
+// Importing a snapshot can take longer than a single request is allowed to run
+// so imports are done as jobs. The PUT request acquires the import lock, stores
+// the payload alongside a job item and returns immediately. A worker then
+// processes the job in chunks, renewing the lock before each one and recording
+// how many writes have been applied so that it can resume from there if it's
+// interrupted. When the worker runs out of time, it leaves the job running and
+// the next invocation picks it up. The lock is acquired with the job ID as its
+// token so a worker whose lock expired stops rather than extending or
+// releasing a lock that someone else has acquired since.
+//
+// The job and its payload are stored in the user's partition outside of any
+// collection. The payload is split into parts so that each item stays under
+// the DynamoDB item size limit.
+//
+// The writes are computed from the payload and the current collection every
+// time the worker resumes. The current collection can't change while the lock
+// is held so the writes will be the same each time as long as they're in a
+// consistent order.
+
+/// How long the lock is held for before it needs to be renewed. Writes aren't
+/// allowed while the lock is held.
+pub const LOCK_DURATION_S: u64 = 60;
+
+pub const KEY_PREFIX: &str = "IMPORT#";
+
+/// The maximum size of each part of the payload.
+const PART_SIZE: usize = 256 * 1024;
+
+/// The number of writes between each progress update.
+const CHUNK_SIZE: usize = 250;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The job hasn't been picked up by the worker yet.
+    Pending,
+    /// The worker has written some of the new collection.
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "pending" => Self::Pending,
+            "running" => Self::Running,
+            "succeeded" => Self::Succeeded,
+            _ => Self::Failed,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed)
+    }
+}
+
+/// How the imported entities are combined with the current ones.
+#[derive(Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// The import replaces the current collection. Current entities that
+    /// aren't in the import are deleted.
+    Replace,
+    /// Current entities that aren't in the import are kept. If an entity is in
+    /// both, the imported one is used.
+    #[default]
+    MergePreferImport,
+    /// Current entities that aren't in the import are kept. If an entity is in
+    /// both, the current one is used so the import only adds entities.
+    MergeKeepExisting,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Replace => "replace",
+            Self::MergePreferImport => "merge-prefer-import",
+            Self::MergeKeepExisting => "merge-keep-existing",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "replace" => Some(Self::Replace),
+            "merge-prefer-import" => Some(Self::MergePreferImport),
+            "merge-keep-existing" => Some(Self::MergeKeepExisting),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Job {
+    pub job_id: String,
+    pub status: Status,
+    pub mode: Mode,
+    /// The version at the time that the lock was acquired.
+    #[serde(skip)]
+    pub base_version: u64,
+    /// The number of parts that the payload was split into.
+    #[serde(skip)]
+    pub parts: u32,
+    /// The number of writes that have been applied.
+    pub written: u64,
+    /// The total number of writes. This is 0 until the worker starts.
+    pub total: u64,
+    /// The reason that the job failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The version of the user's data after the import succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+}
+
+impl Job {
+    fn from_dynamo_db(job_id: &str, item: &DynamoDbItem) -> Self {
+        Self {
+            job_id: job_id.to_owned(),
+            status: Status::parse(item["Status"].as_s().unwrap()),
+            mode: item.get("Mode")
+                .and_then(|m| Mode::parse(m.as_s().unwrap()))
+                .unwrap_or_default(),
+            base_version: common::as_number(&item["BaseVersion"]),
+            parts: common::as_number(&item["Parts"]),
+            written: common::as_number(&item["Written"]),
+            total: common::as_number(&item["Total"]),
+            error: item.get("Error").map(|e| e.as_s().unwrap().clone()),
+            version: item.get("NewVersion").map(common::as_number),
+        }
+    }
+
+    fn to_dynamo_db(&self) -> DynamoDbItem {
+        let mut item = HashMap::new();
+
+        item.insert("Status".into(), AttributeValue::S(self.status.as_str().into()));
+        item.insert("Mode".into(), AttributeValue::S(self.mode.as_str().into()));
+        item.insert("BaseVersion".into(), AttributeValue::N(self.base_version.to_string()));
+        item.insert("Parts".into(), AttributeValue::N(self.parts.to_string()));
+        item.insert("Written".into(), AttributeValue::N(self.written.to_string()));
+        item.insert("Total".into(), AttributeValue::N(self.total.to_string()));
+        item.insert("UpdatedAt".into(), AttributeValue::N(common::now().to_string()));
+
+        if let Some(error) = &self.error {
+            item.insert("Error".into(), AttributeValue::S(error.clone()));
+        }
+
+        if let Some(version) = self.version {
+            item.insert("NewVersion".into(), AttributeValue::N(version.to_string()));
+        }
+
+        item
+    }
+}
+
+pub fn make_job_key(job_id: &str) -> String {
+    format!("{KEY_PREFIX}{job_id}")
+}
+
+fn make_part_key(job_id: &str, part: u32) -> String {
+    format!("{KEY_PREFIX}{job_id}#{part:04}")
+}
+
+/// Store the payload and create a pending job. The import lock must have been
+/// acquired at the given version with the job ID as its token.
+pub async fn create_job(
+    store: &impl UserStore,
+    user_id: &str,
+    job_id: String,
+    base_version: u64,
+    mode: Mode,
+    payload: &[u8],
+) -> Result<Job, Error> {
+    let parts = payload.chunks(PART_SIZE).collect::<Vec<_>>();
+
+    let job = Job {
+        job_id,
+        status: Status::Pending,
+        mode,
+        base_version,
+        parts: parts.len() as u32,
+        written: 0,
+        total: 0,
+        error: None,
+        version: None,
+    };
+
+    // The job is written last so that the worker never sees a job without its
+    // payload.
+
+    let requests = parts.iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let mut item = HashMap::new();
+            item.insert("Payload".into(), AttributeValue::B(Blob::new(*part)));
+            BatchWrite::Put { id: make_part_key(&job.job_id, i as u32), item }
+        })
+        .collect::<Vec<_>>();
+
+    store.batch_write(user_id, requests).await?;
+    save_job(store, user_id, &job).await?;
+
+    Ok(job)
+}
+
+pub async fn get_job(
+    store: &impl UserStore,
+    user_id: &str,
+    job_id: &str,
+) -> Result<Option<Job>, Error> {
+    Ok(store.get_item(user_id, &make_job_key(job_id)).await?
+        .map(|item| Job::from_dynamo_db(job_id, &item)))
+}
+
+/// Process the job until it finishes or the deadline passes. Returns the status
+/// of the job when it stopped.
+pub async fn process(
+    store: &impl UserStore,
+    user_id: &str,
+    job_id: &str,
+    deadline: Instant,
+) -> Result<Status, Error> {
+    let Some(mut job) = get_job(store, user_id, job_id).await? else {
+        return Err(format!("import job {job_id} doesn't exist").into());
+    };
+
+    if job.status.is_finished() {
+        return Ok(job.status);
+    }
+
+    let curr_collection = common::collection_from_version(job.base_version);
+    let curr_collection_prefix = common::get_collection_prefix(curr_collection);
+    let new_collection = curr_collection + 1;
+    let new_version = common::version_from_collection(new_collection);
+
+    // If the worker was interrupted after releasing the lock but before
+    // updating the job, then the import has already succeeded.
+
+    if common::collection_from_version(store.read_version(user_id).await?) == new_collection {
+        return finish(store, user_id, job, Status::Succeeded, None).await;
+    }
+
+    let payload = read_payload(store, user_id, &job).await?;
+
+    // The payload was validated before the job was created so this shouldn't
+    // fail. If it does, the lock is left to expire rather than being released
+    // because we can't be sure that we still hold it.
+
+    let import_user = match serde_json::from_slice::<common::User>(&payload) {
+        Ok(u) => u,
+        Err(e) => {
+            return finish(store, user_id, job, Status::Failed, Some(e.to_string())).await;
+        }
+    };
+
+    // Get the current collection. We need this to apply the import changes
+    // relative to the current state of the database.
+
+    let curr_items = store.query_collection(user_id, curr_collection, true).await?;
+
+    let curr_user = common::db_to_user(job.base_version, false, &curr_items);
+
+    // Combine the imported collection with the current collection to determine
+    // the new collection and then write it out in chunks, skipping the writes
+    // that were applied by a previous invocation.
+
+    let requests = make_import_batch(new_version, job.mode, &curr_user, &import_user);
+
+    job.status = Status::Running;
+    job.total = requests.len() as u64;
+
+    // An earlier import that failed part way through could have left items in
+    // the new collection. They're deleted before the first chunk is written so
+    // they don't end up in it. If the worker runs out of time while deleting,
+    // then the stream retries and the rest are deleted.
+
+    if job.written == 0 {
+        let new_collection_prefix = common::get_collection_prefix(new_collection);
+        let leftovers = store.query_prefix(user_id, &new_collection_prefix).await?
+            .into_iter()
+            .map(|item| item["Id"].as_s().unwrap().clone())
+            .collect::<Vec<_>>();
+
+        for chunk in leftovers.chunks(CHUNK_SIZE) {
+            if !store.extend_import_lock(user_id, job_id, common::now() + LOCK_DURATION_S).await? {
+                let error = String::from("import lock expired");
+                return finish(store, user_id, job, Status::Failed, Some(error)).await;
+            }
+
+            store.batch_write(
+                user_id,
+                chunk.iter().map(|id| BatchWrite::Delete { id: id.clone() }).collect(),
+            ).await?;
+        }
+    }
+
+    let mut requests = requests.into_iter().skip(job.written as usize).peekable();
+
+    while requests.peek().is_some() {
+        if Instant::now() >= deadline {
+            save_job(store, user_id, &job).await?;
+            return Ok(job.status);
+        }
+
+        // If the lock expired, then another import or a write could have
+        // happened so the new collection can't be trusted. It will be removed
+        // by the garbage collector.
+
+        if !store.extend_import_lock(user_id, job_id, common::now() + LOCK_DURATION_S).await? {
+            let error = String::from("import lock expired");
+            return finish(store, user_id, job, Status::Failed, Some(error)).await;
+        }
+
+        let chunk = requests.by_ref().take(CHUNK_SIZE).collect::<Vec<_>>();
+        job.written += chunk.len() as u64;
+
+        store.batch_write(user_id, chunk).await?;
+        save_job(store, user_id, &job).await?;
+    }
+
+    // The records are computed again from the new collection because the
+    // import may have replaced the exercises that set them (see records). The
+    // records of types that no longer have any are deleted.
+
+    if !store.extend_import_lock(user_id, job_id, common::now() + LOCK_DURATION_S).await? {
+        let error = String::from("import lock expired");
+        return finish(store, user_id, job, Status::Failed, Some(error)).await;
+    }
+
+    let new_items = store.query_collection(user_id, new_collection, false).await?;
+    let new_user = common::db_to_user(new_version, false, &new_items);
+    let new_records = records::Records::compute(&new_user).to_dynamo_db();
+
+    let stale_records = store.query_prefix(user_id, records::KEY_PREFIX).await?
+        .into_iter()
+        .map(|item| item["Id"].as_s().unwrap().clone())
+        .filter(|id| !new_records.iter().any(|(new_id, _)| new_id == id))
+        .map(|id| BatchWrite::Delete { id })
+        .collect::<Vec<_>>();
+
+    store.batch_write(user_id, new_records.into_iter()
+        .map(|(id, item)| BatchWrite::Put { id, item })
+        .chain(stale_records)
+        .collect()).await?;
+
+    // Release the lock and switch to the new collection. If this step fails,
+    // the database will be read-only until the lock expires. The new collection
+    // will remain until it is removed by the garbage collector (see gc).
+
+    if !store.release_import_lock(user_id, job_id, new_version).await? {
+        let error = String::from("import lock expired");
+        return finish(store, user_id, job, Status::Failed, Some(error)).await;
+    }
+
+    // Clear out the old collection. If this step fails, then the old collection
+    // will remain until it is removed by the garbage collector. It's not doing
+    // any harm really. It's just sitting there.
+
+    if let Err(e) = store.batch_write(
+        user_id,
+        make_delete_batch(&curr_collection_prefix, &curr_user),
+    ).await {
+        tracing::warn!("Clearing the old collection of {user_id} failed: {e}");
+    }
+
+    finish(store, user_id, job, Status::Succeeded, None).await
+}
+
+async fn read_payload(store: &impl UserStore, user_id: &str, job: &Job) -> Result<Vec<u8>, Error> {
+    let mut payload = Vec::new();
+
+    for part in 0..job.parts {
+        let Some(item) = store.get_item(user_id, &make_part_key(&job.job_id, part)).await? else {
+            return Err(format!("part {part} of import job {} is missing", job.job_id).into());
+        };
+
+        match item.get("Payload") {
+            Some(AttributeValue::B(b)) => payload.extend_from_slice(b.as_ref()),
+            _ => return Err(format!("part {part} of import job {} is malformed", job.job_id).into()),
+        }
+    }
+
+    Ok(payload)
+}
+
+async fn save_job(store: &impl UserStore, user_id: &str, job: &Job) -> Result<(), Error> {
+    Ok(store.batch_write(user_id, vec![BatchWrite::Put {
+        id: make_job_key(&job.job_id),
+        item: job.to_dynamo_db(),
+    }]).await?)
+}
+
+/// Record the outcome of the job and delete its payload.
+async fn finish(
+    store: &impl UserStore,
+    user_id: &str,
+    mut job: Job,
+    status: Status,
+    error: Option<String>,
+) -> Result<Status, Error> {
+    if status == Status::Succeeded {
+        job.version = Some(common::version_from_collection(
+            common::collection_from_version(job.base_version) + 1
+        ));
+    }
+
+    job.status = status;
+    job.error = error;
+
+    save_job(store, user_id, &job).await?;
+
+    store.batch_write(
+        user_id,
+        (0..job.parts)
+            .map(|part| BatchWrite::Delete { id: make_part_key(&job.job_id, part) })
+            .collect(),
+    ).await?;
+
+    Ok(status)
+}
+
+/// How an import affects the entities of one type.
+pub struct Classification<'b, T> {
+    /// Entities in the import that aren't in the current collection or were
+    /// deleted from it.
+    pub added: Vec<&'b T>,
+    /// Entities in both that aren't equivalent and the imported entity
+    /// replaces the current one.
+    pub changed: Vec<&'b T>,
+    /// Entities in both that are equivalent. The current entity is kept along
+    /// with its modified version.
+    pub unchanged: Vec<&'b T>,
+    /// Entities in the current collection that are kept even though they
+    /// aren't in the import or the import differs from them.
+    pub kept: Vec<&'b T>,
+    /// Entities in the current collection that the import deletes.
+    pub removed: Vec<&'b T>,
+    /// Deleted entities in the current collection that aren't in the import.
+    /// The tombstones are kept so that clients find out about the deletion.
+    /// Tombstones aren't affected by the mode.
+    pub tombstones: Vec<&'b common::Deleted<'b>>,
+}
+
+/// Classify the entities of one type by comparing the import to the current
+/// collection.
+pub fn classify<'b, 'a: 'b, T>(
+    mode: Mode,
+    curr: &'b common::User<'a>,
+    import: &'b common::User<'a>,
+) -> Classification<'b, T>
+    where T: common::Identifiable<'a> + common::Equivalent + common::UserField<'a>
+{
+    let mut curr_entities = T::extract_from_user(curr).iter()
+        .map(|e| (e.get_id(), e))
+        .collect::<HashMap<_, _>>();
+    let mut curr_deleted_entities = T::extract_deleted_from_user(curr).iter()
+        .map(|d| (d.id, d))
+        .collect::<HashMap<_, _>>();
+
+    let mut classification = Classification {
+        added: Vec::new(),
+        changed: Vec::new(),
+        unchanged: Vec::new(),
+        kept: Vec::new(),
+        removed: Vec::new(),
+        tombstones: Vec::new(),
+    };
+
+    for import_entity in T::extract_from_user(import).iter() {
+        curr_deleted_entities.remove(import_entity.get_id());
+
+        match curr_entities.remove(import_entity.get_id()) {
+            Some(curr_entity) if curr_entity.equiv(import_entity) => {
+                classification.unchanged.push(curr_entity);
+            }
+            Some(curr_entity) if mode == Mode::MergeKeepExisting => {
+                classification.kept.push(curr_entity);
+            }
+            Some(_) => classification.changed.push(import_entity),
+            None => classification.added.push(import_entity),
+        }
+    }
+
+    if mode == Mode::Replace {
+        classification.removed.extend(curr_entities.into_values());
+    } else {
+        classification.kept.extend(curr_entities.into_values());
+    }
+    classification.tombstones.extend(curr_deleted_entities.into_values());
+
+    // The entities are collected from hash maps so they need to be sorted to
+    // be in the same order every time.
+
+    classification.kept.sort_by_key(|e| e.get_id());
+    classification.removed.sort_by_key(|e| e.get_id());
+    classification.tombstones.sort_by_key(|d| d.id);
+
+    classification
+}
+
+/// The IDs of the entities in each part of a classification.
+#[derive(Serialize)]
+pub struct EntityDiff<'a> {
+    pub added: Vec<&'a str>,
+    pub changed: Vec<&'a str>,
+    pub unchanged: Vec<&'a str>,
+    pub kept: Vec<&'a str>,
+    pub removed: Vec<&'a str>,
+}
+
+/// What an import would do to the current collection.
+#[derive(Serialize)]
+pub struct Diff<'a> {
+    /// The version that the import was compared to.
+    pub version: u64,
+    pub mode: Mode,
+    pub measurement_sets: EntityDiff<'a>,
+    pub workouts: EntityDiff<'a>,
+    pub exercises: EntityDiff<'a>,
+    pub exercise_types: EntityDiff<'a>,
+    pub routines: EntityDiff<'a>,
+    pub programs: EntityDiff<'a>,
+}
+
+/// Compare an import to the current collection without writing anything.
+pub fn diff<'b, 'a: 'b>(
+    mode: Mode,
+    curr: &'b common::User<'a>,
+    import: &'b common::User<'a>,
+) -> Diff<'a> {
+    Diff {
+        version: curr.version,
+        mode,
+        measurement_sets: diff_for::<common::MeasurementSet>(mode, curr, import),
+        workouts: diff_for::<common::Workout>(mode, curr, import),
+        exercises: diff_for::<common::Exercise>(mode, curr, import),
+        exercise_types: diff_for::<common::CustomExerciseType>(mode, curr, import),
+        routines: diff_for::<common::Routine>(mode, curr, import),
+        programs: diff_for::<common::Program>(mode, curr, import),
+    }
+}
+
+/// Check the imported exercises and routines of custom types against the custom
+/// types that the new collection will have. Current entities that are kept
+/// aren't checked because their type could have been deleted after they were
+/// written.
+pub fn check_custom_types<'b, 'a: 'b>(
+    mode: Mode,
+    curr: &'b common::User<'a>,
+    import: &'b common::User<'a>,
+) -> Result<(), String> {
+    let types = classify::<common::CustomExerciseType>(mode, curr, import);
+    let types = types.added.into_iter()
+        .chain(types.changed)
+        .chain(types.unchanged)
+        .chain(types.kept)
+        .map(|t| (t.exercise_type_id, t))
+        .collect::<HashMap<_, _>>();
+
+    let exercises = classify::<common::Exercise>(mode, curr, import);
+
+    for exercise in exercises.added.into_iter()
+        .chain(exercises.changed)
+        .chain(exercises.unchanged)
+    {
+        let type_id = exercise.r#type.0.as_ref();
+
+        if !common::is_uuid(type_id) {
+            continue;
+        }
+
+        common::validate_custom_exercise(exercise, types.get(type_id).copied())
+            .map_err(|e| format!("exercise {}: {e}", exercise.workout_exercise_id))?;
+    }
+
+    let routines = classify::<common::Routine>(mode, curr, import);
+
+    for routine in routines.added.into_iter()
+        .chain(routines.changed)
+        .chain(routines.unchanged)
+    {
+        for exercise in routine.exercises.0.iter() {
+            let type_id = exercise.r#type.0.as_ref();
+
+            if !common::is_uuid(type_id) {
+                continue;
+            }
+
+            common::validate_custom_targets(exercise, types.get(type_id).copied())
+                .map_err(|e| format!("routine {}: {e}", routine.routine_id))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn diff_for<'b, 'a: 'b, T>(
+    mode: Mode,
+    curr: &'b common::User<'a>,
+    import: &'b common::User<'a>,
+) -> EntityDiff<'a>
+    where T: common::Identifiable<'a> + common::Equivalent + common::UserField<'a>
+{
+    let classification = classify::<T>(mode, curr, import);
+    let ids = |entities: Vec<&T>| entities.into_iter().map(|e| e.get_id()).collect();
+
+    EntityDiff {
+        added: ids(classification.added),
+        changed: ids(classification.changed),
+        unchanged: ids(classification.unchanged),
+        kept: ids(classification.kept),
+        removed: ids(classification.removed),
+    }
+}
+
+fn make_import_batch<'a>(
+    new_version: u64,
+    mode: Mode,
+    curr: &common::User<'a>,
+    import: &common::User<'a>,
+) -> Vec<BatchWrite> {
+    let mut requests = Vec::new();
+
+    let new_collection_prefix = common::get_collection_prefix(
+        common::collection_from_version(new_version)
+    );
+
+    make_import_batch_for::<common::MeasurementSet>(
+        &mut requests,
+        &new_collection_prefix,
+        new_version,
+        classify(mode, curr, import),
+    );
+
+    make_import_batch_for::<common::Workout>(
+        &mut requests,
+        &new_collection_prefix,
+        new_version,
+        classify(mode, curr, import),
+    );
+
+    make_import_batch_for::<common::Exercise>(
+        &mut requests,
+        &new_collection_prefix,
+        new_version,
+        classify(mode, curr, import),
+    );
+
+    make_import_batch_for::<common::CustomExerciseType>(
+        &mut requests,
+        &new_collection_prefix,
+        new_version,
+        classify(mode, curr, import),
+    );
+
+    make_import_batch_for::<common::Routine>(
+        &mut requests,
+        &new_collection_prefix,
+        new_version,
+        classify(mode, curr, import),
+    );
+
+    make_import_batch_for::<common::Program>(
+        &mut requests,
+        &new_collection_prefix,
+        new_version,
+        classify(mode, curr, import),
+    );
+
+    requests
+}
+
+fn make_import_batch_for<'a, T>(
+    requests: &mut Vec<BatchWrite>,
+    collection_prefix: &str,
+    version: u64,
+    classification: Classification<T>,
+)
+    where T: common::ToDynamoDb<'a>
+{
+    // Entities that come from the import get the new version so that clients
+    // will download them. Entities that come from the current collection keep
+    // their modified version.
+
+    let imported = classification.added.iter()
+        .chain(classification.changed.iter())
+        .map(|e| (*e, Some(version)));
+    let current = classification.unchanged.iter()
+        .chain(classification.kept.iter())
+        .map(|e| (*e, None));
+
+    for (entity, modified_version) in imported.chain(current) {
+        let mut item = HashMap::new();
+
+        entity.insert_dynamo_db(&mut item, modified_version);
+
+        requests.push(BatchWrite::Put {
+            id: common::make_key_from_entity(collection_prefix, entity),
+            item,
+        });
+    }
+
+    for entity in classification.removed {
+        requests.push(BatchWrite::Put {
+            id: common::make_key_from_entity(collection_prefix, entity),
+            item: common::make_deleted_item(version),
+        });
+    }
+
+    for deleted in classification.tombstones {
+        requests.push(BatchWrite::Put {
+            id: common::make_key_from_id::<T>(collection_prefix, deleted.id),
+            item: common::make_deleted_item(deleted.modified_version),
+        });
+    }
+}
+
+fn make_delete_batch(
+    collection_prefix: &str,
+    user: &common::User,
+) -> Vec<BatchWrite> {
+    let mut requests = Vec::new();
+
+    make_delete_batch_for::<common::MeasurementSet>(
+        &mut requests,
+        collection_prefix,
+        user,
+    );
+
+    make_delete_batch_for::<common::Workout>(
+        &mut requests,
+        collection_prefix,
+        user,
+    );
+
+    make_delete_batch_for::<common::Exercise>(
+        &mut requests,
+        collection_prefix,
+        user,
+    );
+
+    make_delete_batch_for::<common::CustomExerciseType>(
+        &mut requests,
+        collection_prefix,
+        user,
+    );
+
+    make_delete_batch_for::<common::Routine>(
+        &mut requests,
+        collection_prefix,
+        user,
+    );
+
+    make_delete_batch_for::<common::Program>(
+        &mut requests,
+        collection_prefix,
+        user,
+    );
+
+    requests
+}
+
+fn make_delete_batch_for<'a, T>(
+    requests: &mut Vec<BatchWrite>,
+    collection_prefix: &str,
+    user: &common::User<'a>,
+)
+    where T: common::ToDynamoDb<'a> + common::UserField<'a>
+{
+    for entity in T::extract_from_user(user) {
+        requests.push(BatchWrite::Delete {
+            id: common::make_key_from_entity(collection_prefix, entity),
+        });
+    }
+
+    for deleted in T::extract_deleted_from_user(user) {
+        requests.push(BatchWrite::Delete {
+            id: common::make_key_from_id::<T>(collection_prefix, deleted.id),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::{common::ToDynamoDb, store::{LockOutcome, MemoryStore}};
+    use super::*;
+
+    const USER_ID: &str = "user";
+    const JOB_ID: &str = "job";
+
+    #[tokio::test]
+    async fn leftovers_in_the_new_collection_are_deleted() {
+        let store = MemoryStore::new();
+
+        // The new collection has a workout from an import that failed.
+
+        let mut workout = serde_json::from_str::<common::Workout>(
+            r#"{"start_time":"2026-10-01T10:00:00Z","finish_time":null,"notes":""}"#
+        ).unwrap();
+        workout.workout_id = "9c5e1f3c-3f54-4bb1-9a4e-0cd43d4a7b11";
+
+        let new_prefix = common::get_collection_prefix(1);
+        let mut item = HashMap::new();
+        workout.insert_dynamo_db(&mut item, Some(common::version_from_collection(1)));
+        store.batch_write(USER_ID, vec![BatchWrite::Put {
+            id: common::make_key_from_entity(&new_prefix, &workout),
+            item,
+        }]).await.unwrap();
+
+        let until = common::now() + LOCK_DURATION_S;
+        let outcome = store.acquire_import_lock(USER_ID, JOB_ID, until).await.unwrap();
+        let LockOutcome::Acquired { version } = outcome else {
+            panic!("lock is held");
+        };
+
+        let payload = br#"{"measurement_sets":[],"workouts":[],"exercises":[]}"#;
+        create_job(&store, USER_ID, JOB_ID.into(), version, Mode::MergePreferImport, payload).await.unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let status = process(&store, USER_ID, JOB_ID, deadline).await.unwrap();
+
+        assert!(matches!(status, Status::Succeeded));
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), common::version_from_collection(1));
+        assert!(store.query_collection(USER_ID, 1, true).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,70 @@
+use chrono::NaiveDateTime;
+use crate::common;
+use super::{DistanceUnit, TrackerSet, WeightUnit};
+
+// Hevy exports one row per set with the workout and exercise repeated on each
+// row. The units are part of the column names so the units in the options
+// aren't used.
+
+const FILE: &str = "hevy";
+
+const TIME_FORMAT: &str = "%d %b %Y, %H:%M";
+
+pub(super) fn read(text: &str) -> Result<Vec<TrackerSet>, common::ApiError> {
+    let Some(table) = common::parse_csv(FILE, text, ',')? else {
+        return Ok(Vec::new());
+    };
+
+    super::require_columns(FILE, &table, &["start_time", "exercise_title"])?;
+
+    let (weight_column, weight_unit) = if table.columns.contains_key("weight_lbs") {
+        ("weight_lbs", WeightUnit::Lb)
+    } else {
+        ("weight_kg", WeightUnit::Kg)
+    };
+    let (distance_column, distance_unit) = if table.columns.contains_key("distance_miles") {
+        ("distance_miles", DistanceUnit::Mi)
+    } else {
+        ("distance_km", DistanceUnit::Km)
+    };
+
+    let mut sets = Vec::new();
+
+    for record in table.records.iter() {
+        let row = common::CsvRow { columns: &table.columns, record };
+        let number = |column| super::parse_number(FILE, record.line, column, row.get(column));
+
+        let Ok(start_time) = NaiveDateTime::parse_from_str(row.get("start_time"), TIME_FORMAT) else {
+            return Err(common::csv_error(FILE, record.line, &"invalid start_time"));
+        };
+
+        let finish_time = match row.get("end_time") {
+            "" => None,
+            t => match NaiveDateTime::parse_from_str(t, TIME_FORMAT) {
+                Ok(t) => Some(t),
+                Err(_) => return Err(common::csv_error(FILE, record.line, &"invalid end_time")),
+            },
+        };
+
+        let workout_notes = [row.get("title"), row.get("description")]
+            .into_iter()
+            .filter(|n| !n.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        sets.push(TrackerSet {
+            line: record.line,
+            start_time,
+            finish_time,
+            workout_notes,
+            exercise_name: row.get("exercise_title").to_owned(),
+            exercise_notes: row.get("exercise_notes").to_owned(),
+            weight: number(weight_column)?.map(|w| weight_unit.to_kg(w)),
+            repetitions: number("reps")?,
+            distance: number(distance_column)?.map(|d| distance_unit.to_m(d)),
+            duration: number("duration_seconds")?,
+        });
+    }
+
+    Ok(sets)
+}
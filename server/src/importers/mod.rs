@@ -0,0 +1,443 @@
+use std::{borrow::Cow, collections::HashMap};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use crate::common;
+
+// Converts the exports of other workout trackers into the JSON representation
+// of a user so that they can be imported as a snapshot. Each tracker has its
+// own module that reads its export into a list of sets in common units. The
+// sets are then turned into a table with the columns of the sets export and
+// converted in the same way as a CSV import (see common::csv) so that the IDs
+// are derived in the same way and importing the same export again doesn't
+// create duplicates.
+//
+// The other trackers name their exercises rather than having a fixed set of
+// types so the names are mapped to types using the table below, which can be
+// extended or overridden by the request. A name can be mapped to null to skip
+// it. If any name isn't mapped, nothing is imported and the names are reported
+// so that the client can ask the user how to map them.
+
+mod hevy;
+mod strong;
+
+/// Common names of exercises in other trackers. Names are compared ignoring
+/// case. The built-in types are machines except for the dumbbell wrist curl so
+/// only names with the same equipment are mapped. Resistances on different
+/// equipment aren't comparable and would mix up the records and statistics of
+/// a type, so the other names are left for the user to map.
+const DEFAULT_EXERCISES: [(&str, &str); 20] = [
+    ("Bicep Curl (Machine)", "biceps-curl"),
+    ("Chest Fly (Machine)", "pectoral-fly"),
+    ("Chest Press (Machine)", "chest-press"),
+    ("Cycling (Indoor)", "upright-bike"),
+    ("Elliptical Machine", "elliptical-cross-trainer"),
+    ("Elliptical Trainer", "elliptical-cross-trainer"),
+    ("Lat Pulldown (Machine)", "fixed-pulldown"),
+    ("Leg Extension (Machine)", "leg-extension"),
+    ("Lying Leg Curl (Machine)", "leg-curl"),
+    ("Recumbent Bike", "recumbent-bike"),
+    ("Running (Treadmill)", "treadmill"),
+    ("Seated Leg Curl (Machine)", "seated-leg-curl"),
+    ("Seated Row (Machine)", "seated-row"),
+    ("Shoulder Press (Machine)", "shoulder-press"),
+    ("Shoulder Press (Machine Plates)", "shoulder-press"),
+    ("Spinning", "upright-bike"),
+    ("Standing Calf Raise (Machine)", "standing-calf"),
+    ("Treadmill", "treadmill"),
+    ("Triceps Extension (Machine)", "triceps-extension"),
+    ("Wrist Curl (Dumbbell)", "dumbbell-wrist-curl"),
+];
+
+const KG_PER_LB: f64 = 0.45359237;
+const M_PER_MI: f64 = 1609.344;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Strong,
+    Hevy,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightUnit {
+    #[default]
+    Kg,
+    Lb,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceUnit {
+    #[default]
+    Km,
+    Mi,
+}
+
+#[derive(Deserialize)]
+pub struct Options<'a> {
+    /// Exercise names mapped to exercise types, or to null to skip them. These
+    /// take precedence over the default table.
+    #[serde(default, borrow)]
+    pub exercises: HashMap<Cow<'a, str>, Option<Cow<'a, str>>>,
+    /// The unit of weights in exports that don't specify one.
+    #[serde(default)]
+    pub weight_unit: WeightUnit,
+    /// The unit of distances in exports that don't specify one.
+    #[serde(default)]
+    pub distance_unit: DistanceUnit,
+    /// The offset of the times in the export from UTC in minutes. The exports
+    /// use local time without a time zone.
+    #[serde(default)]
+    pub utc_offset: i64,
+}
+
+/// A set read from an export, in kilograms, meters and seconds.
+struct TrackerSet {
+    line: usize,
+    start_time: NaiveDateTime,
+    finish_time: Option<NaiveDateTime>,
+    workout_notes: String,
+    exercise_name: String,
+    exercise_notes: String,
+    weight: Option<f64>,
+    repetitions: Option<f64>,
+    distance: Option<f64>,
+    duration: Option<f64>,
+}
+
+/// Convert an export into the JSON representation of a user.
+pub fn convert(format: Format, text: &str, options: &Options) -> Result<Value, common::ApiError> {
+    let (file, sets) = match format {
+        Format::Strong => ("strong", strong::read(text, options)?),
+        Format::Hevy => ("hevy", hevy::read(text)?),
+    };
+
+    let table = to_table(file, sets, options)?;
+    let (workouts, exercises) = common::table_to_workouts(file, &table)?;
+
+    Ok(json!({
+        "measurement_sets": [],
+        "workouts": workouts,
+        "exercises": exercises,
+    }))
+}
+
+fn to_table(
+    file: &str,
+    sets: Vec<TrackerSet>,
+    options: &Options,
+) -> Result<common::CsvTable, common::ApiError> {
+    let columns = common::SET_COLUMNS.iter()
+        .enumerate()
+        .map(|(i, c)| (c.to_string(), i))
+        .collect::<HashMap<_, _>>();
+
+    let mut records = Vec::new();
+    let mut unmapped = Vec::<common::UnmappedExercise>::new();
+
+    // The exports don't number the exercises within a workout. A new exercise
+    // starts whenever the exercise name changes.
+
+    let mut exercise_orders = HashMap::<NaiveDateTime, (&str, u32)>::new();
+
+    for set in sets.iter() {
+        let Some(r#type) = map_exercise(&set.exercise_name, options) else {
+            match unmapped.iter_mut().find(|u| u.name == set.exercise_name) {
+                Some(u) => u.rows += 1,
+                None => unmapped.push(common::UnmappedExercise {
+                    name: set.exercise_name.clone(),
+                    line: set.line,
+                    rows: 1,
+                }),
+            }
+            continue;
+        };
+
+        let Some(r#type) = r#type else {
+            continue;
+        };
+
+        let order = match exercise_orders.get_mut(&set.start_time) {
+            Some((name, order)) => {
+                if *name != set.exercise_name {
+                    *name = &set.exercise_name;
+                    *order += 1;
+                }
+                *order
+            }
+            None => {
+                exercise_orders.insert(set.start_time, (&set.exercise_name, 0));
+                0
+            }
+        };
+
+        let error = |column| common::csv_error(file, set.line, &format!("invalid {column}"));
+
+        // The other trackers record whatever fields they like so the fields are
+        // fitted to the exercise type. Trackers don't usually record the
+        // resistance of cardio machines or of body weight exercises so it
+        // defaults to 0. The speed of a fixed speed machine is worked out from
+        // the distance and duration. Unknown types are left alone and fail
+        // validation.
+
+        let mut values = [set.repetitions, set.weight, None, set.distance, set.duration];
+
+        if let Some(kind) = common::get_set_kind(r#type) {
+            let fields = kind.fields();
+
+            if kind == common::SetKind::Fixed {
+                if let (Some(distance), Some(duration)) = (set.distance, set.duration) {
+                    if duration > 0.0 {
+                        values[2] = Some(distance / duration * 3.6);
+                    }
+                }
+            }
+
+            for (field, value) in common::SET_FIELDS.iter().zip(values.iter_mut()) {
+                if !fields.contains(field) {
+                    *value = None;
+                } else if *field == "resistance" && value.is_none() {
+                    *value = Some(0.0);
+                }
+            }
+        }
+
+        let [repetitions, resistance, speed, distance, duration] = values;
+
+        let fields = [
+            String::new(),
+            format_time(set.start_time, options),
+            set.finish_time.map(|t| format_time(t, options)).unwrap_or_default(),
+            set.workout_notes.clone(),
+            String::new(),
+            order.to_string(),
+            r#type.to_owned(),
+            set.exercise_notes.clone(),
+            String::new(),
+            String::new(),
+            round(repetitions).ok_or_else(|| error("repetitions"))?,
+            round(resistance).ok_or_else(|| error("weight"))?,
+            round(speed).ok_or_else(|| error("speed"))?,
+            round(distance).ok_or_else(|| error("distance"))?,
+            round(duration).ok_or_else(|| error("duration"))?,
+        ];
+
+        records.push(common::CsvRecord { line: set.line, fields: fields.into() });
+    }
+
+    if !unmapped.is_empty() {
+        return Err(common::ApiError::UnmappedExercises(unmapped));
+    }
+
+    Ok(common::CsvTable { columns, records })
+}
+
+/// Look up the exercise type of an exercise name. Returns `None` if the name
+/// isn't mapped and `Some(None)` if the name is mapped to null.
+fn map_exercise<'b>(name: &str, options: &'b Options) -> Option<Option<&'b str>> {
+    let custom = options.exercises.get(name).or_else(|| {
+        options.exercises.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, t)| t)
+    });
+
+    if let Some(r#type) = custom {
+        return Some(r#type.as_deref());
+    }
+
+    DEFAULT_EXERCISES.iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, t)| Some(*t))
+}
+
+fn format_time(time: NaiveDateTime, options: &Options) -> String {
+    (time - chrono::Duration::minutes(options.utc_offset)).format("%FT%TZ").to_string()
+}
+
+/// Round a value to fit in a u32 field. Returns `None` if it doesn't fit.
+fn round(value: Option<f64>) -> Option<String> {
+    match value {
+        None => Some(String::new()),
+        Some(v) if (0.0..=u32::MAX as f64).contains(&v) => Some((v.round() as u32).to_string()),
+        Some(_) => None,
+    }
+}
+
+/// Parse a number that may use a comma as the decimal separator. Empty fields
+/// are `None`.
+fn parse_number(file: &str, line: usize, column: &str, value: &str) -> Result<Option<f64>, common::ApiError> {
+    match value.trim() {
+        "" => Ok(None),
+        v => match v.replace(',', ".").parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Some(n)),
+            _ => Err(common::csv_error(file, line, &format!("invalid {column}"))),
+        },
+    }
+}
+
+fn require_columns(
+    file: &str,
+    table: &common::CsvTable,
+    columns: &[&str],
+) -> Result<(), common::ApiError> {
+    match columns.iter().find(|c| !table.columns.contains_key(**c)) {
+        Some(column) => Err(common::csv_error(file, 1, &format!("missing {column} column"))),
+        None => Ok(()),
+    }
+}
+
+impl WeightUnit {
+    fn to_kg(self, weight: f64) -> f64 {
+        match self {
+            Self::Kg => weight,
+            Self::Lb => weight * KG_PER_LB,
+        }
+    }
+}
+
+impl DistanceUnit {
+    fn to_m(self, distance: f64) -> f64 {
+        match self {
+            Self::Km => distance * 1000.0,
+            Self::Mi => distance * M_PER_MI,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(weight_unit: WeightUnit, distance_unit: DistanceUnit) -> Options<'static> {
+        Options { exercises: HashMap::new(), weight_unit, distance_unit, utc_offset: 0 }
+    }
+
+    fn convert_ok(format: Format, text: &str, options: &Options) -> Value {
+        match convert(format, text, options) {
+            Ok(user) => user,
+            Err(common::ApiError::Validation(message)) => panic!("{message}"),
+            Err(_) => panic!("conversion failed"),
+        }
+    }
+
+    fn unmapped(format: Format, text: &str, options: &Options) -> Vec<(String, usize, usize)> {
+        match convert(format, text, options) {
+            Err(common::ApiError::UnmappedExercises(unmapped)) => unmapped.into_iter()
+                .map(|u| (u.name, u.line, u.rows))
+                .collect(),
+            _ => panic!("expected unmapped exercises"),
+        }
+    }
+
+    fn sets(user: &Value, exercise: usize) -> &Vec<Value> {
+        user["exercises"][exercise]["sets"].as_array().unwrap()
+    }
+
+    const STRONG: &str = "\
+Date;Workout Name;Duration;Exercise Name;Set Order;Weight;Reps;Distance;Seconds;Notes;Workout Notes;RPE
+2026-10-01 18:30:00;Evening;1h 5m;Bicep Curl (Machine);1;50;10;0;0;;;
+2026-10-01 18:30:00;Evening;1h 5m;Bicep Curl (Machine);Rest Timer;0;0;0;90;;;
+2026-10-01 18:30:00;Evening;1h 5m;Bicep Curl (Machine);2;52,5;8;0;0;;;
+2026-10-01 18:30:00;Evening;1h 5m;Running (Treadmill);1;0;0;1,5;600;Easy;;
+";
+
+    #[test]
+    fn strong_units_come_from_the_options() {
+        let mut imperial = options(WeightUnit::Lb, DistanceUnit::Mi);
+        imperial.utc_offset = 60;
+
+        let user = convert_ok(Format::Strong, STRONG, &imperial);
+
+        let workout = &user["workouts"][0];
+        assert_eq!(workout["start_time"], "2026-10-01T17:30:00Z");
+        assert_eq!(workout["finish_time"], "2026-10-01T18:35:00Z");
+        assert_eq!(workout["notes"], "Evening");
+
+        // 50 lb and 52.5 lb, with the rest timer left out.
+
+        let curls = sets(&user, 0);
+        assert_eq!(user["exercises"][0]["type"], "biceps-curl");
+        assert_eq!(curls.len(), 2);
+        assert_eq!((&curls[0]["repetitions"], &curls[0]["resistance"]), (&10.into(), &23.into()));
+        assert_eq!((&curls[1]["repetitions"], &curls[1]["resistance"]), (&8.into(), &24.into()));
+
+        // 1.5 mi in 10 minutes, which is 2414 m at 14.5 km/h. The treadmill
+        // doesn't record repetitions and the resistance defaults to 0.
+
+        let runs = sets(&user, 1);
+        assert_eq!(user["exercises"][1]["type"], "treadmill");
+        assert_eq!(user["exercises"][1]["order"], 1);
+        assert_eq!(user["exercises"][1]["notes"], "Easy");
+        assert_eq!(runs[0], json!({
+            "set_id": runs[0]["set_id"],
+            "resistance": 0,
+            "speed": 14,
+            "distance": 2414,
+            "duration": 600,
+        }));
+
+        let user = convert_ok(Format::Strong, STRONG, &options(WeightUnit::Kg, DistanceUnit::Km));
+        assert_eq!(sets(&user, 0)[1]["resistance"], 53);
+        assert_eq!(sets(&user, 1)[0]["distance"], 1500);
+    }
+
+    #[test]
+    fn hevy_units_come_from_the_columns() {
+        let hevy = "\
+title,start_time,end_time,description,exercise_title,exercise_notes,set_index,set_type,weight_lbs,reps,distance_miles,duration_seconds,rpe
+Push,\"1 Oct 2026, 18:30\",\"1 Oct 2026, 19:15\",,Chest Press (Machine),,0,normal,100,10,,,
+Push,\"1 Oct 2026, 18:30\",\"1 Oct 2026, 19:15\",,Treadmill,,0,normal,,,2,1200,
+";
+
+        // The options are ignored because the columns name the units.
+
+        let user = convert_ok(Format::Hevy, hevy, &options(WeightUnit::Kg, DistanceUnit::Km));
+
+        assert_eq!(user["workouts"][0]["finish_time"], "2026-10-01T19:15:00Z");
+        assert_eq!(sets(&user, 0)[0]["resistance"], 45);
+        assert_eq!(sets(&user, 1)[0]["distance"], 3219);
+        assert_eq!(sets(&user, 1)[0]["speed"], 10);
+
+        let user = convert_ok(
+            Format::Hevy,
+            &hevy.replace("weight_lbs", "weight_kg").replace("distance_miles", "distance_km"),
+            &options(WeightUnit::Lb, DistanceUnit::Mi),
+        );
+
+        assert_eq!(sets(&user, 0)[0]["resistance"], 100);
+        assert_eq!(sets(&user, 1)[0]["distance"], 2000);
+    }
+
+    #[test]
+    fn unmapped_names_are_reported() {
+        let strong = STRONG.to_owned()
+            + "2026-10-01 18:30:00;Evening;1h 5m;Wrist Curl (Barbell);1;20;12;0;0;;;\n"
+            + "2026-10-01 18:30:00;Evening;1h 5m;Wrist Curl (Barbell);2;20;10;0;0;;;\n"
+            + "2026-10-01 18:30:00;Evening;1h 5m;Wrist Curl (Barbell);3;20;8;0;0;;;\n"
+            + "2026-10-01 18:30:00;Evening;1h 5m;Plank;1;0;0;0;60;;;\n";
+
+        let mut options = options(WeightUnit::Kg, DistanceUnit::Km);
+
+        assert_eq!(unmapped(Format::Strong, &strong, &options), [
+            ("Wrist Curl (Barbell)".to_owned(), 6, 3),
+            ("Plank".to_owned(), 9, 1),
+        ]);
+
+        // Names mapped by the request are compared ignoring case and take
+        // precedence over the defaults. Names mapped to null are left out.
+
+        options.exercises.insert("WRIST CURL (BARBELL)".into(), Some("dumbbell-wrist-curl".into()));
+        options.exercises.insert("Plank".into(), None);
+        options.exercises.insert("Running (Treadmill)".into(), None);
+
+        let user = convert_ok(Format::Strong, &strong, &options);
+        let types = user["exercises"].as_array().unwrap().iter()
+            .map(|e| e["type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(types, ["biceps-curl", "dumbbell-wrist-curl"]);
+        assert_eq!(sets(&user, 1).len(), 3);
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::NaiveDateTime;
+use crate::common;
+use super::{Options, TrackerSet};
+
+// Strong exports one row per set with the workout and exercise repeated on each
+// row. The weight and distance are in whatever units the user had selected so
+// they come from the options. Depending on the locale, the export may be
+// separated with semicolons and use commas as decimal separators. Fields that
+// don't apply to an exercise are exported as 0 rather than left empty, except
+// for the weight which can legitimately be 0.
+
+const FILE: &str = "strong";
+
+pub(super) fn read(text: &str, options: &Options) -> Result<Vec<TrackerSet>, common::ApiError> {
+    let first_line = text.lines().next().unwrap_or_default();
+    let delimiter = if first_line.contains(';') && !first_line.contains(',') { ';' } else { ',' };
+
+    let Some(table) = common::parse_csv(FILE, text, delimiter)? else {
+        return Ok(Vec::new());
+    };
+
+    super::require_columns(FILE, &table, &["Date", "Exercise Name"])?;
+
+    let mut sets = Vec::new();
+
+    for record in table.records.iter() {
+        let row = common::CsvRow { columns: &table.columns, record };
+        let number = |column| super::parse_number(FILE, record.line, column, row.get(column));
+
+        // Rest timers are exported as their own rows.
+
+        if row.get("Set Order") == "Rest Timer" {
+            continue;
+        }
+
+        let Ok(start_time) = NaiveDateTime::parse_from_str(row.get("Date"), "%F %T") else {
+            return Err(common::csv_error(FILE, record.line, &"invalid Date"));
+        };
+
+        let finish_time = parse_duration(row.get("Duration")).map(|d| start_time + d);
+
+        let workout_notes = [row.get("Workout Name"), row.get("Workout Notes")]
+            .into_iter()
+            .filter(|n| !n.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        sets.push(TrackerSet {
+            line: record.line,
+            start_time,
+            finish_time,
+            workout_notes,
+            exercise_name: row.get("Exercise Name").to_owned(),
+            exercise_notes: row.get("Notes").to_owned(),
+            weight: number("Weight")?.map(|w| options.weight_unit.to_kg(w)),
+            repetitions: number("Reps")?.filter(|r| *r != 0.0),
+            distance: number("Distance")?
+                .filter(|d| *d != 0.0)
+                .map(|d| options.distance_unit.to_m(d)),
+            duration: number("Seconds")?.filter(|s| *s != 0.0),
+        });
+    }
+
+    Ok(sets)
+}
+
+/// Parse a duration like "1h 5m". Returns `None` if there isn't a duration.
+fn parse_duration(duration: &str) -> Option<chrono::Duration> {
+    let mut total = chrono::Duration::zero();
+
+    for part in duration.split_whitespace() {
+        let unit_len = part.chars().last()?.len_utf8();
+        let (value, unit) = part.split_at(part.len() - unit_len);
+        let value = value.parse::<i64>().ok()?;
+
+        total = total + match unit {
+            "h" => chrono::Duration::hours(value),
+            "m" => chrono::Duration::minutes(value),
+            "s" => chrono::Duration::seconds(value),
+            _ => return None,
+        };
+    }
+
+    Some(total).filter(|d| !d.is_zero())
+}
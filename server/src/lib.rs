@@ -0,0 +1,294 @@
+pub mod auth;
+pub mod common;
+pub mod feed;
+pub mod gc;
+pub mod handlers;
+pub mod import;
+pub mod importers;
+pub mod program;
+pub mod records;
+pub mod router;
+pub mod stats;
+pub mod store;
+
+use lambda_http::{Body, Error, Request, RequestExt, Response};
+
+pub async fn function_handler(
+    store: &impl store::UserStore,
+    verifier: Option<&auth::Verifier>,
+    req: Request,
+) -> Result<Response<Body>, Error> {
+    Ok(handle_request(store, verifier, req).await.unwrap_or_else(common::ApiError::into_response))
+}
+
+async fn handle_request(
+    store: &impl store::UserStore,
+    verifier: Option<&auth::Verifier>,
+    req: Request,
+) -> common::Result {
+    use handlers::*;
+    use router::Endpoint;
+
+    let path = match req.raw_http_path() {
+        p if p.is_empty() => req.uri().path().to_owned(),
+        p => p,
+    };
+
+    let (endpoint, params) = match router::route(req.method(), &path) {
+        router::Routed::Found(e, p) => (e, p),
+        router::Routed::MethodNotAllowed(allowed) => {
+            return Err(common::ApiError::MethodNotAllowed(allowed));
+        }
+        router::Routed::NotFound => return Err(common::ApiError::NotFound),
+    };
+
+    if let (Some(verifier), false) = (verifier, endpoint.is_public()) {
+        verifier.verify(&req).await?;
+    }
+
+    let req = req.with_path_parameters(params);
+
+    match endpoint {
+        Endpoint::UserGet => user::get(store, req).await,
+        Endpoint::UserBatchPost => user_batch::post(store, req).await,
+        Endpoint::UserExerciseTypeDelete => user_exercise_type::delete(store, req).await,
+        Endpoint::UserExerciseTypePut => user_exercise_type::put(store, req).await,
+        Endpoint::UserExportGet => user_export::get_sets(store, req).await,
+        Endpoint::UserExportMeasurementsGet => user_export::get_measurements(store, req).await,
+        Endpoint::UserFeedTokenDelete => user_feed_token::delete(store, req).await,
+        Endpoint::UserFeedTokenPost => user_feed_token::post(store, req).await,
+        Endpoint::UserImportGet => user_import::get(store, req).await,
+        Endpoint::UserSnapshotGet => user_snapshot::get(store, req).await,
+        Endpoint::UserSnapshotPut => user_snapshot::put(store, req).await,
+        Endpoint::UserSnapshotCsvPut => user_snapshot::put_csv(store, req).await,
+        Endpoint::UserSnapshotTrackerPut => user_snapshot::put_tracker(store, req).await,
+        Endpoint::UserStatsCardioGet => user_stats::get_cardio(store, req).await,
+        Endpoint::UserStatsExerciseGet => user_stats::get_exercise(store, req).await,
+        Endpoint::UserMeasurementDelete => user_measurement::delete(store, req).await,
+        Endpoint::UserMeasurementPut => user_measurement::put(store, req).await,
+        Endpoint::UserProgramDelete => user_program::delete(store, req).await,
+        Endpoint::UserProgramPut => user_program::put(store, req).await,
+        Endpoint::UserProgramScheduleGet => user_program::get_schedule(store, req).await,
+        Endpoint::UserRecordsGet => user_records::get(store, req).await,
+        Endpoint::UserRoutineDelete => user_routine::delete(store, req).await,
+        Endpoint::UserRoutinePut => user_routine::put(store, req).await,
+        Endpoint::UserRoutineInstantiatePost => user_routine::instantiate(store, req).await,
+        Endpoint::UserWorkoutDelete => user_workout::delete(store, req).await,
+        Endpoint::UserWorkoutPut => user_workout::put(store, req).await,
+        Endpoint::UserWorkoutExerciseDelete => user_workout_exercise::delete(store, req).await,
+        Endpoint::UserWorkoutExercisePut => user_workout_exercise::put(store, req).await,
+        Endpoint::UserWorkoutOrderPut => user_workout_order::put(store, req).await,
+        Endpoint::UserWorkoutsIcsGet => user_workouts::get_ical(store, req).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use base64::Engine;
+    use lambda_http::{aws_lambda_events::query_map::QueryMap, http::{Method, StatusCode, header::LOCATION}};
+    use serde_json::{Value, json};
+    use super::*;
+
+    // These run requests through the same handler as the Lambda against the
+    // memory store so that the sync and import protocols are tested end to end.
+
+    const USER_ID: &str = "user";
+    const WORKOUT_A: &str = "0f0c3a56-9f1e-4b53-8d2a-6b1f0e3c2a11";
+    const WORKOUT_B: &str = "5b7d2c1e-3a4f-4e6d-9c8b-7a6f5e4d3c22";
+    const CUSTOM_TYPE: &str = "8e9f0a1b-2c3d-4e5f-8a7b-6c5d4e3f2a33";
+
+    struct Res {
+        status: StatusCode,
+        location: Option<String>,
+        body: Value,
+    }
+
+    async fn send(store: &store::MemoryStore, method: Method, uri: &str, body: Option<Value>) -> Res {
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let token = format!("x.{}.y", engine.encode(json!({ "sub": USER_ID }).to_string()));
+        let query = uri.split_once('?').map_or("", |(_, q)| q).parse::<QueryMap>().unwrap();
+
+        let req = lambda_http::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {token}"))
+            .body(body.map_or(Body::Empty, |b| Body::from(b.to_string())))
+            .unwrap()
+            .with_query_string_parameters(query);
+
+        let res = function_handler(store, None, req).await.unwrap();
+
+        Res {
+            status: res.status(),
+            location: res.headers().get(LOCATION).map(|l| l.to_str().unwrap().to_owned()),
+            body: match res.body() {
+                Body::Empty => Value::Null,
+                Body::Text(t) => serde_json::from_str(t).unwrap(),
+                Body::Binary(b) => serde_json::from_slice(b).unwrap(),
+            },
+        }
+    }
+
+    fn workout(version: u64) -> Value {
+        json!({
+            "version": version,
+            "item": { "start_time": "2026-10-01T10:00:00Z", "finish_time": null, "notes": "" },
+        })
+    }
+
+    fn workout_ids(changes: &Value) -> Vec<&str> {
+        changes["workouts"].as_array().unwrap().iter()
+            .map(|w| w["workout_id"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn changes_since_a_version() {
+        let store = store::MemoryStore::new();
+
+        let res = send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(0))).await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        let res = send(&store, Method::GET, "/user?since=0", None).await;
+        assert_eq!(res.body["version"], 1);
+        assert_eq!(workout_ids(&res.body), [WORKOUT_A]);
+
+        let res = send(&store, Method::GET, "/user?since=1", None).await;
+        assert_eq!(res.body["version"], 1);
+        assert!(workout_ids(&res.body).is_empty());
+
+        let res = send(&store, Method::DELETE, &format!("/user/workout/{WORKOUT_A}"), Some(json!({ "version": 1 }))).await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        let res = send(&store, Method::GET, "/user?since=1", None).await;
+        assert_eq!(res.body["version"], 2);
+        assert_eq!(res.body["deleted_workouts"], json!([WORKOUT_A]));
+    }
+
+    #[tokio::test]
+    async fn stale_write_conflicts_with_the_changes() {
+        let store = store::MemoryStore::new();
+
+        send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(0))).await;
+
+        let uri = format!("/user/workout/{WORKOUT_B}?changes=true");
+        let res = send(&store, Method::PUT, &uri, Some(workout(0))).await;
+
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        assert_eq!(res.body["code"], "version-conflict");
+        assert_eq!(res.body["version"], 1);
+        assert_eq!(workout_ids(&res.body["changes"]), [WORKOUT_A]);
+
+        let res = send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_B}"), Some(workout(0))).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+        assert!(res.body.get("changes").is_none());
+    }
+
+    #[tokio::test]
+    async fn imported_snapshot_becomes_visible_when_the_job_succeeds() {
+        let store = store::MemoryStore::new();
+
+        send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(0))).await;
+
+        let snapshot = json!({
+            "measurement_sets": [],
+            "workouts": [
+                { "workout_id": WORKOUT_B, "start_time": "2026-10-02T10:00:00Z", "finish_time": null, "notes": "" },
+            ],
+            "exercises": [],
+        });
+        let res = send(&store, Method::PUT, "/user/snapshot?mode=replace", Some(snapshot)).await;
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        assert_eq!(res.body["status"], "pending");
+
+        let location = res.location.unwrap();
+        let job_id = location.strip_prefix("/user/import/").unwrap();
+
+        // Writes are rejected while the import holds the lock but reads still
+        // see the current collection.
+
+        let res = send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(1))).await;
+        assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.body["code"], "locked");
+
+        let res = send(&store, Method::GET, "/user", None).await;
+        assert_eq!(workout_ids(&res.body), [WORKOUT_A]);
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let status = import::process(&store, USER_ID, job_id, deadline).await.unwrap();
+        assert!(matches!(status, import::Status::Succeeded));
+
+        let res = send(&store, Method::GET, &location, None).await;
+        assert_eq!(res.body["status"], "succeeded");
+
+        let version = common::version_from_collection(1);
+        let res = send(&store, Method::GET, "/user", None).await;
+        assert_eq!(res.body["version"], version);
+        assert_eq!(workout_ids(&res.body), [WORKOUT_B]);
+
+        // Clients with a version from before the import conflict and then
+        // start again from the new version.
+
+        let res = send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(1))).await;
+        assert_eq!(res.status, StatusCode::CONFLICT);
+
+        let res = send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(version))).await;
+        assert_eq!(res.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn sets_must_match_the_exercise_type() {
+        let store = store::MemoryStore::new();
+
+        send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(0))).await;
+
+        let exercise = |version: u64, r#type: &str, mut set: Value| {
+            set["set_id"] = WORKOUT_B.into();
+            json!({
+                "version": version,
+                "item": { "order": 0, "type": r#type, "notes": "", "sets": [set] },
+            })
+        };
+        let uri = format!("/user/workout/{WORKOUT_A}/exercise/{WORKOUT_B}");
+
+        let res = send(&store, Method::PUT, &uri, Some(exercise(1, "treadmill", json!({ "repetitions": 10 })))).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.body["code"], "validation");
+
+        // Exercises of a custom type are checked against the stored type.
+
+        let custom_type = json!({ "version": 1, "item": { "name": "Plank", "group": "core", "fields": ["duration"] } });
+        let res = send(&store, Method::PUT, &format!("/user/exercise-type/{CUSTOM_TYPE}"), Some(custom_type)).await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        let res = send(&store, Method::PUT, &uri, Some(exercise(2, CUSTOM_TYPE, json!({ "repetitions": 10 })))).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let res = send(&store, Method::PUT, &uri, Some(exercise(2, CUSTOM_TYPE, json!({ "duration": 60 })))).await;
+        assert_eq!(res.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejected_snapshot_releases_the_lock() {
+        let store = store::MemoryStore::new();
+
+        // The custom type of the exercise isn't in the snapshot or the current
+        // collection, which is only checked after taking the lock.
+
+        let snapshot = json!({
+            "measurement_sets": [],
+            "workouts": [
+                { "workout_id": WORKOUT_B, "start_time": "2026-10-02T10:00:00Z", "finish_time": null, "notes": "" },
+            ],
+            "exercises": [
+                { "workout_exercise_id": format!("{WORKOUT_B}#{WORKOUT_A}"), "order": 0, "type": CUSTOM_TYPE, "notes": "", "sets": [] },
+            ],
+        });
+        let res = send(&store, Method::PUT, "/user/snapshot", Some(snapshot)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let res = send(&store, Method::PUT, &format!("/user/workout/{WORKOUT_A}"), Some(workout(0))).await;
+        assert_eq!(res.status, StatusCode::OK);
+    }
+}
@@ -1,28 +1,5 @@
-mod common;
-mod handlers;
-
-use lambda_http::{Body, Error, Request, RequestExt, Response, request::RequestContext, http::StatusCode};
-
-async fn function_handler(req: Request) -> Result<Response<Body>, Error> {
-    use handlers::*;
-
-    let RequestContext::ApiGatewayV2(req_ctx) = req.request_context();
-
-    match req_ctx.route_key.as_deref() {
-        Some("GET /user") => user::get(req).await,
-        Some("GET /user/snapshot") => user_snapshot::get(req).await,
-        Some("PUT /user/snapshot") => user_snapshot::put(req).await,
-        Some("DELETE /user/measurement/{measurementId}") => user_measurement::delete(req).await,
-        Some("PUT /user/measurement/{measurementId}") => user_measurement::put(req).await,
-        Some("DELETE /user/workout/{workoutId}") => user_workout::delete(req).await,
-        Some("PUT /user/workout/{workoutId}") => user_workout::put(req).await,
-        Some("DELETE /user/workout/{workoutId}/exercise/{exerciseId}") => user_workout_exercise::delete(req).await,
-        Some("PUT /user/workout/{workoutId}/exercise/{exerciseId}") => user_workout_exercise::put(req).await,
-        Some("PUT /user/workout/{workoutId}/order") => user_workout_order::put(req).await,
-
-        Some(_) | None => common::empty_response(StatusCode::NOT_FOUND)
-    }
-}
+use gym_log::{auth, function_handler, store};
+use lambda_http::Error;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -34,7 +11,19 @@ async fn main() -> Result<(), Error> {
         .without_time()
         .init();
 
-    common::init_db_client().await;
+    // The in-memory store is useful when running the function locally with
+    // cargo lambda watch. Everything is lost when the process exits.
+
+    match std::env::var("GYM_LOG_STORE").as_deref() {
+        Ok("memory") => run(store::MemoryStore::new()).await,
+        _ => run(store::DynamoStore::from_env().await).await,
+    }
+}
+
+async fn run(store: impl store::UserStore) -> Result<(), Error> {
+    let verifier = auth::Verifier::from_env().await?;
 
-    lambda_http::run(lambda_http::service_fn(function_handler)).await
+    lambda_http::run(lambda_http::service_fn(|req| {
+        function_handler(&store, verifier.as_ref(), req)
+    })).await
 }
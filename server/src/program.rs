@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use chrono::{Duration, NaiveDate};
+use serde::Serialize;
+use crate::common;
+
+// A program repeats the same sessions every week for a number of weeks. Each
+// session performs a routine and the targets of the routine are increased by
+// the progression of the session for every week since the start. The planned
+// sessions are computed from the program rather than stored so that editing
+// the program or its routines changes the schedule.
+//
+// A workout is linked to a planned session by its planned_session, which
+// identifies the session by the program, the date and the index of the session
+// within the program. A session is completed if a workout is linked to it and
+// missed if its date has passed without one. Linked workouts don't have to be
+// on the planned date. Routines that were deleted are left out of the plan but
+// the sessions still count towards adherence. The link isn't checked when the
+// workout is written, so a workout may refer to a program or session that
+// doesn't exist. Such links are ignored.
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Completed,
+    Missed,
+    Upcoming,
+}
+
+#[derive(Serialize)]
+pub struct Schedule<'a> {
+    pub program_id: &'a str,
+    pub from: String,
+    pub to: String,
+    pub sessions: Vec<Session<'a>>,
+    pub adherence: Adherence,
+}
+
+#[derive(Serialize)]
+pub struct Session<'a> {
+    pub date: String,
+    /// The week of the program that the session is in, starting from 0.
+    pub week: u32,
+    /// Index of the session within the sessions of the program.
+    pub session: u32,
+    pub routine_id: &'a str,
+    /// The name of the routine or `None` if it was deleted.
+    pub routine_name: Option<&'a str>,
+    pub exercises: Vec<PlannedExercise<'a>>,
+    pub status: Status,
+    /// The workouts that are linked to the session.
+    pub workout_ids: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+pub struct PlannedExercise<'a> {
+    pub r#type: &'a str,
+    pub sets: Vec<PlannedSet>,
+}
+
+#[derive(Serialize)]
+pub struct PlannedSet {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repetitions: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resistance: Option<f64>,
+}
+
+/// How many of the sessions that were due have been completed. Sessions are due
+/// once they're completed or missed so a session today that hasn't been done
+/// yet doesn't count against adherence.
+#[derive(Serialize)]
+pub struct Adherence {
+    /// The number of sessions in the range that were completed or missed.
+    pub due: u32,
+    /// The number of sessions in the range that were completed.
+    pub completed: u32,
+    /// The fraction of due sessions that were completed or `None` if none
+    /// were due.
+    pub rate: Option<f64>,
+}
+
+/// The date of the last day of the program.
+pub fn end_date(program: &common::Program) -> NaiveDate {
+    common::parse_date(program.start_date.0).unwrap() + Duration::days(7 * program.weeks as i64 - 1)
+}
+
+/// Compute the planned sessions of a program between two dates, inclusive.
+pub fn schedule<'a>(
+    program: &'a common::Program<'a>,
+    user: &'a common::User<'a>,
+    from: NaiveDate,
+    to: NaiveDate,
+    today: NaiveDate,
+) -> Schedule<'a> {
+    let start_date = common::parse_date(program.start_date.0).unwrap();
+
+    let routines = user.routines.iter()
+        .map(|r| (r.routine_id, r))
+        .collect::<HashMap<_, _>>();
+
+    let mut workouts = HashMap::<(&str, u32), Vec<&str>>::new();
+
+    for workout in user.workouts.iter() {
+        if let Some(planned) = &workout.planned_session {
+            if planned.program_id.0 == program.program_id {
+                workouts.entry((planned.date.0, planned.session))
+                    .or_default()
+                    .push(workout.workout_id);
+            }
+        }
+    }
+
+    let mut sessions = Vec::new();
+
+    for week in 0..program.weeks {
+        for (index, session) in program.sessions.0.iter().enumerate() {
+            let date = start_date + Duration::days(7 * week as i64 + session.day as i64);
+
+            if date < from || date > to {
+                continue;
+            }
+
+            let date_str = date.format("%F").to_string();
+            let mut workout_ids = workouts.get(&(date_str.as_str(), index as u32))
+                .cloned()
+                .unwrap_or_default();
+            workout_ids.sort();
+
+            let status = if !workout_ids.is_empty() {
+                Status::Completed
+            } else if date < today {
+                Status::Missed
+            } else {
+                Status::Upcoming
+            };
+
+            let routine = routines.get(session.routine_id.0);
+
+            sessions.push(Session {
+                date: date_str,
+                week,
+                session: index as u32,
+                routine_id: session.routine_id.0,
+                routine_name: routine.map(|r| r.name.0.as_ref()),
+                exercises: routine.map(|r| plan_exercises(r, session, week)).unwrap_or_default(),
+                status,
+                workout_ids,
+            });
+        }
+    }
+
+    sessions.sort_by(|a, b| (&a.date, a.session).cmp(&(&b.date, b.session)));
+
+    let completed = sessions.iter().filter(|s| s.status == Status::Completed).count() as u32;
+    let missed = sessions.iter().filter(|s| s.status == Status::Missed).count() as u32;
+    let due = completed + missed;
+
+    Schedule {
+        program_id: program.program_id,
+        from: from.format("%F").to_string(),
+        to: to.format("%F").to_string(),
+        sessions,
+        adherence: Adherence {
+            due,
+            completed,
+            rate: (due > 0).then(|| completed as f64 / due as f64),
+        },
+    }
+}
+
+/// Apply the progression of a session to the targets of its routine. Targets
+/// can't go below 0.
+fn plan_exercises<'a>(
+    routine: &'a common::Routine<'a>,
+    session: &common::ProgramSession,
+    week: u32,
+) -> Vec<PlannedExercise<'a>> {
+    routine.exercises.0.iter()
+        .map(|exercise| PlannedExercise {
+            r#type: exercise.r#type.0.as_ref(),
+            sets: exercise.sets.0.iter()
+                .map(|set| PlannedSet {
+                    repetitions: set.repetitions.map(|r| {
+                        (r as i64 + session.repetitions_per_week as i64 * week as i64)
+                            .clamp(0, u32::MAX as i64) as u32
+                    }),
+                    resistance: set.resistance.map(|r| {
+                        (r as f64 + session.resistance_per_week * week as f64).max(0.0)
+                    }),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use super::*;
+
+    const PROGRAM_ID: &str = "0f0c3a56-9f1e-4b53-8d2a-6b1f0e3c2a11";
+    const OTHER_PROGRAM_ID: &str = "5b7d2c1e-3a4f-4e6d-9c8b-7a6f5e4d3c22";
+    const ROUTINE_ID: &str = "8e9f0a1b-2c3d-4e5f-8a7b-6c5d4e3f2a33";
+    const DELETED_ROUTINE_ID: &str = "3c4d5e6f-7a8b-4c9d-8e0f-1a2b3c4d5e44";
+
+    fn program_json(weeks: u32, sessions: Value) -> Value {
+        json!({
+            "program_id": PROGRAM_ID,
+            "name": "Strength",
+            "start_date": "2026-10-05",
+            "weeks": weeks,
+            "sessions": sessions,
+        })
+    }
+
+    fn program_error(program: Value) -> Option<String> {
+        serde_json::from_str::<common::Program>(&program.to_string()).err()
+            .map(|e| e.to_string().split(" at line ").next().unwrap().to_owned())
+    }
+
+    fn workout(workout_id: &str, program_id: &str, date: &str, session: u32) -> Value {
+        json!({
+            "workout_id": workout_id,
+            "start_time": format!("{date}T18:00:00Z"),
+            "finish_time": null,
+            "notes": "",
+            "planned_session": { "program_id": program_id, "date": date, "session": session },
+        })
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        common::parse_date(s).unwrap()
+    }
+
+    #[test]
+    fn schedule_and_adherence() {
+        let json = json!({
+            "measurement_sets": [],
+            "workouts": [
+                workout("6d7e8f90-1a2b-4c3d-9e4f-5a6b7c8d9e55", PROGRAM_ID, "2026-10-05", 0),
+                workout("7e8f901a-2b3c-4d4e-8f5a-6b7c8d9e0f66", OTHER_PROGRAM_ID, "2026-10-12", 0),
+            ],
+            "exercises": [],
+            "routines": [{
+                "routine_id": ROUTINE_ID,
+                "name": "Arms",
+                "exercises": [{
+                    "type": "biceps-curl",
+                    "sets": [{ "repetitions": 10, "resistance": 40 }, { "repetitions": 10 }],
+                }],
+            }],
+            "programs": [program_json(3, json!([
+                { "routine_id": ROUTINE_ID, "day": 0, "repetitions_per_week": -6, "resistance_per_week": -25 },
+                { "routine_id": DELETED_ROUTINE_ID, "day": 3 },
+            ]))],
+        }).to_string();
+        let user = serde_json::from_str::<common::User>(&json).unwrap();
+        let program = &user.programs[0];
+
+        assert_eq!(end_date(program), date("2026-10-25"));
+
+        let planned = schedule(program, &user, date("2026-10-01"), date("2026-10-31"), date("2026-10-15"));
+        let sessions = planned.sessions.iter()
+            .map(|s| (s.date.as_str(), s.week, s.session, s.routine_name, json!(s.status)))
+            .collect::<Vec<_>>();
+
+        // The workout linked to another program doesn't complete a session and
+        // a session today isn't missed yet.
+
+        assert_eq!(sessions, [
+            ("2026-10-05", 0, 0, Some("Arms"), json!("completed")),
+            ("2026-10-08", 0, 1, None, json!("missed")),
+            ("2026-10-12", 1, 0, Some("Arms"), json!("missed")),
+            ("2026-10-15", 1, 1, None, json!("upcoming")),
+            ("2026-10-19", 2, 0, Some("Arms"), json!("upcoming")),
+            ("2026-10-22", 2, 1, None, json!("upcoming")),
+        ]);
+        assert_eq!(planned.sessions[0].workout_ids, ["6d7e8f90-1a2b-4c3d-9e4f-5a6b7c8d9e55"]);
+        assert!(planned.sessions[1].exercises.is_empty());
+        assert_eq!((planned.adherence.due, planned.adherence.completed), (3, 1));
+        assert_eq!(planned.adherence.rate, Some(1.0 / 3.0));
+
+        // The targets decrease every week until they reach 0. Targets that the
+        // routine doesn't set stay unset.
+
+        let targets = |i: usize| planned.sessions[i].exercises[0].sets.iter()
+            .map(|s| (s.repetitions, s.resistance))
+            .collect::<Vec<_>>();
+
+        assert_eq!(targets(0), [(Some(10), Some(40.0)), (Some(10), None)]);
+        assert_eq!(targets(2), [(Some(4), Some(15.0)), (Some(4), None)]);
+        assert_eq!(targets(4), [(Some(0), Some(0.0)), (Some(0), None)]);
+
+        let planned = schedule(program, &user, date("2026-10-08"), date("2026-10-15"), date("2026-10-15"));
+        assert_eq!(planned.sessions.len(), 3);
+        assert_eq!((planned.adherence.due, planned.adherence.completed), (2, 0));
+    }
+
+    #[test]
+    fn repetitions_progression_is_bounded() {
+        let json = json!({
+            "routine_id": ROUTINE_ID,
+            "name": "Arms",
+            "exercises": [{ "type": "biceps-curl", "sets": [{ "repetitions": 10 }] }],
+        }).to_string();
+        let routine = serde_json::from_str::<common::Routine>(&json).unwrap();
+
+        let json = json!({ "routine_id": ROUTINE_ID, "day": 0, "repetitions_per_week": i32::MAX }).to_string();
+        let session = serde_json::from_str::<common::ProgramSession>(&json).unwrap();
+
+        let planned = plan_exercises(&routine, &session, common::MAX_WEEKS - 1);
+        assert_eq!(planned[0].sets[0].repetitions, Some(u32::MAX));
+    }
+
+    #[test]
+    fn programs_are_bounded() {
+        let session = |resistance_per_week: f64| json!([
+            { "routine_id": ROUTINE_ID, "day": 6, "resistance_per_week": resistance_per_week },
+        ]);
+
+        assert_eq!(program_error(program_json(1, session(2.5))), None);
+        assert_eq!(program_error(program_json(common::MAX_WEEKS, session(-1000.0))), None);
+        assert_eq!(program_error(program_json(1, session(0.125))), None);
+
+        let weeks_error = Some(format!("weeks must be from 1 to {}", common::MAX_WEEKS));
+        assert_eq!(program_error(program_json(0, session(0.0))), weeks_error);
+        assert_eq!(program_error(program_json(common::MAX_WEEKS + 1, session(0.0))), weeks_error);
+
+        let day = json!([{ "routine_id": ROUTINE_ID, "day": 7 }]);
+        assert_eq!(program_error(program_json(1, day)), Some("sessions[0].day must be from 0 to 6".into()));
+
+        let resistance_error = Some(
+            "sessions[0].resistance_per_week must be from -1000 to 1000 with no more than 3 decimal places".to_owned(),
+        );
+        assert_eq!(program_error(program_json(1, session(1000.5))), resistance_error);
+        assert_eq!(program_error(program_json(1, session(-1e300))), resistance_error);
+        assert_eq!(program_error(program_json(1, session(0.0001))), resistance_error);
+    }
+}
@@ -0,0 +1,760 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_http::Error;
+use serde::Serialize;
+use crate::{common::{self, DynamoDbItem}, stats, store::{TransactItem, UserStore}};
+
+// Personal records are derived from the sets of exercises and kept per
+// exercise type. Sets with repetitions and resistance count towards the
+// strength records and sets with a distance count towards the distance
+// records. The pace is the duration per kilometre so it needs a duration as
+// well.
+//
+// The records of each exercise type are stored in an item outside of any
+// collection. Writing an exercise updates the records of the types that it
+// affects in the same transaction as the exercise. Importing a snapshot
+// computes the records of every type from scratch, so the records only depend
+// on the exercises and not on how they were written. Editing or deleting the
+// exercise that set a record can lower it. The records that were improved by a
+// write are returned so the client can celebrate them.
+//
+// Most writes only raise records so the records of a type are updated from the
+// stored records and the written exercise alone. The other exercises only need
+// to be read when the exercise that holds a record no longer reaches it, as the
+// next best is unknown. Ties go to the exercise with the lowest ID and then to
+// its first set either way.
+//
+// Only the heaviest resistances have a repetitions record so that the item of
+// a type stays well under the item size limit.
+
+pub const KEY_PREFIX: &str = "RECORDS#";
+
+/// The maximum number of resistances that have a repetitions record.
+const MAX_REPETITION_RECORDS: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// The heaviest resistance of a set with any number of repetitions.
+    BestResistance,
+    /// The most repetitions at a particular resistance.
+    BestRepetitions,
+    /// The best one-rep max estimated with the Epley formula.
+    BestOneRepMax,
+    /// The longest distance in meters.
+    LongestDistance,
+    /// The lowest duration per kilometre in seconds.
+    FastestPace,
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Record {
+    pub value: f64,
+    /// The ID of the exercise with the set that set the record, which is the
+    /// workout ID and exercise ID separated by a `#`.
+    pub exercise_id: String,
+    pub set_id: String,
+}
+
+#[derive(Clone, Default, PartialEq, Serialize)]
+pub struct TypeRecords {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_resistance: Option<Record>,
+    /// The records for each resistance.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub best_repetitions: BTreeMap<u32, Record>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_one_rep_max: Option<Record>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longest_distance: Option<Record>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fastest_pace: Option<Record>,
+}
+
+/// The records of each exercise type.
+#[derive(Clone, Default, Serialize)]
+pub struct Records(pub BTreeMap<String, TypeRecords>);
+
+/// A record that was improved by a write.
+#[derive(Serialize)]
+pub struct BrokenRecord<'a> {
+    pub r#type: &'a str,
+    pub record: Kind,
+    /// The resistance that the record is for if it's a repetitions record.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resistance: Option<u32>,
+    pub value: f64,
+    /// The value of the record before it was broken or `None` if there wasn't
+    /// a record.
+    pub previous: Option<f64>,
+    pub exercise_id: &'a str,
+    pub set_id: &'a str,
+}
+
+pub fn make_key(r#type: &str) -> String {
+    format!("{KEY_PREFIX}{type}")
+}
+
+impl TypeRecords {
+    /// Compute the records from the exercises of a type. The exercises are
+    /// visited in order of ID so that ties always go to the same set.
+    fn compute<'a>(exercises: impl Iterator<Item = (&'a str, &'a common::Exercise<'a>)>) -> Self {
+        let mut exercises = exercises.collect::<Vec<_>>();
+        exercises.sort_by_key(|(id, _)| *id);
+
+        let mut records = Self::default();
+
+        for (exercise_id, exercise) in exercises {
+            records.add_exercise(exercise_id, exercise);
+        }
+
+        while records.best_repetitions.len() > MAX_REPETITION_RECORDS {
+            records.best_repetitions.pop_first();
+        }
+
+        records
+    }
+
+    fn add_exercise(&mut self, exercise_id: &str, exercise: &common::Exercise) {
+        for set in exercise.sets.0.iter() {
+            let raise = |record: &mut Option<Record>, value: f64, higher: bool| {
+                let better = record.as_ref().is_none_or(|r| {
+                    if higher { value > r.value } else { value < r.value }
+                });
+
+                if better {
+                    *record = Some(Record {
+                        value,
+                        exercise_id: exercise_id.to_owned(),
+                        set_id: set.set_id.0.to_owned(),
+                    });
+                }
+            };
+
+            if let (Some(repetitions), Some(resistance)) = (set.repetitions, set.resistance) {
+                if let Some(one_rep_max) = stats::Formula::Epley.estimate(resistance, repetitions) {
+                    raise(&mut self.best_resistance, resistance as f64, true);
+                    raise(&mut self.best_one_rep_max, one_rep_max, true);
+
+                    let mut best = self.best_repetitions.remove(&resistance);
+                    raise(&mut best, repetitions as f64, true);
+                    self.best_repetitions.extend(best.map(|b| (resistance, b)));
+                }
+            }
+
+            if let Some(distance) = set.distance.filter(|d| *d > 0) {
+                raise(&mut self.longest_distance, distance as f64, true);
+
+                if let Some(duration) = set.duration.filter(|d| *d > 0) {
+                    raise(&mut self.fastest_pace, duration as f64 * 1000.0 / distance as f64, false);
+                }
+            }
+        }
+    }
+
+    /// Merge the records of an exercise into the records of its type without
+    /// the other exercises. Returns `None` if the exercise held a record that
+    /// it no longer reaches.
+    fn merge(&self, exercise_id: &str, exercise: &common::Exercise) -> Option<Self> {
+        let candidate = Self::compute(std::iter::once((exercise_id, exercise)));
+        let mut merged = self.clone();
+
+        let merge = |kind, record: &mut Option<Record>, candidate: Option<&Record>| {
+            let held = record.as_ref().is_some_and(|r| r.exercise_id == exercise_id);
+
+            match (record.as_ref(), candidate) {
+                (Some(r), Some(c)) => {
+                    let replace = if held {
+                        if is_better(kind, r.value, c.value) {
+                            return None;
+                        }
+                        true
+                    } else {
+                        is_better(kind, c.value, r.value)
+                            || (c.value == r.value && exercise_id < r.exercise_id.as_str())
+                    };
+
+                    if replace {
+                        *record = Some(c.clone());
+                    }
+                }
+                (Some(_), None) if held => return None,
+                (None, Some(c)) => *record = Some(c.clone()),
+                _ => {}
+            }
+
+            Some(())
+        };
+
+        merge(Kind::BestResistance, &mut merged.best_resistance, candidate.best_resistance.as_ref())?;
+        merge(Kind::BestOneRepMax, &mut merged.best_one_rep_max, candidate.best_one_rep_max.as_ref())?;
+        merge(Kind::LongestDistance, &mut merged.longest_distance, candidate.longest_distance.as_ref())?;
+        merge(Kind::FastestPace, &mut merged.fastest_pace, candidate.fastest_pace.as_ref())?;
+
+        let resistances = self.best_repetitions.keys()
+            .chain(candidate.best_repetitions.keys())
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        for resistance in resistances {
+            let mut record = merged.best_repetitions.remove(&resistance);
+            merge(Kind::BestRepetitions, &mut record, candidate.best_repetitions.get(&resistance))?;
+            merged.best_repetitions.extend(record.map(|r| (resistance, r)));
+        }
+
+        while merged.best_repetitions.len() > MAX_REPETITION_RECORDS {
+            merged.best_repetitions.pop_first();
+        }
+
+        Some(merged)
+    }
+
+    /// Whether any of the records were set by an exercise.
+    fn is_held_by(&self, exercise_id: &str) -> bool {
+        [
+            &self.best_resistance,
+            &self.best_one_rep_max,
+            &self.longest_distance,
+            &self.fastest_pace,
+        ].into_iter()
+            .flatten()
+            .chain(self.best_repetitions.values())
+            .any(|r| r.exercise_id == exercise_id)
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn from_dynamo_db(item: &DynamoDbItem) -> Self {
+        let record = |attribute: &AttributeValue| {
+            let map = attribute.as_m().unwrap();
+            Record {
+                value: common::as_number(&map["Value"]),
+                exercise_id: map["ExerciseId"].as_s().unwrap().clone(),
+                set_id: map["SetId"].as_s().unwrap().clone(),
+            }
+        };
+
+        Self {
+            best_resistance: item.get("BestResistance").map(record),
+            best_repetitions: item.get("BestRepetitions")
+                .map(|b| b.as_m().unwrap().iter()
+                    .map(|(resistance, r)| (resistance.parse().unwrap(), record(r)))
+                    .collect())
+                .unwrap_or_default(),
+            best_one_rep_max: item.get("BestOneRepMax").map(record),
+            longest_distance: item.get("LongestDistance").map(record),
+            fastest_pace: item.get("FastestPace").map(record),
+        }
+    }
+
+    fn to_dynamo_db(&self) -> DynamoDbItem {
+        let record = |record: &Record| {
+            let mut map = HashMap::new();
+            map.insert("Value".into(), AttributeValue::N(record.value.to_string()));
+            map.insert("ExerciseId".into(), AttributeValue::S(record.exercise_id.clone()));
+            map.insert("SetId".into(), AttributeValue::S(record.set_id.clone()));
+            AttributeValue::M(map)
+        };
+
+        let mut item = HashMap::new();
+
+        for (name, r) in [
+            ("BestResistance", &self.best_resistance),
+            ("BestOneRepMax", &self.best_one_rep_max),
+            ("LongestDistance", &self.longest_distance),
+            ("FastestPace", &self.fastest_pace),
+        ] {
+            if let Some(r) = r {
+                item.insert(name.into(), record(r));
+            }
+        }
+
+        if !self.best_repetitions.is_empty() {
+            item.insert("BestRepetitions".into(), AttributeValue::M(
+                self.best_repetitions.iter()
+                    .map(|(resistance, r)| (resistance.to_string(), record(r)))
+                    .collect()
+            ));
+        }
+
+        item
+    }
+}
+
+impl Records {
+    /// Compute the records from all of the exercises of a user.
+    pub fn compute(user: &common::User) -> Self {
+        let mut by_type = BTreeMap::<&str, Vec<&common::Exercise>>::new();
+
+        for exercise in user.exercises.iter() {
+            by_type.entry(exercise.r#type.0.as_ref()).or_default().push(exercise);
+        }
+
+        Self(by_type.into_iter()
+            .map(|(r#type, exercises)| (
+                r#type.to_owned(),
+                TypeRecords::compute(exercises.into_iter().map(|e| (e.workout_exercise_id, e))),
+            ))
+            .filter(|(_, records)| !records.is_empty())
+            .collect())
+    }
+
+    /// The records that are better in this than in an earlier version of the
+    /// records.
+    pub fn broken_since<'b>(&'b self, earlier: &Self) -> Vec<BrokenRecord<'b>> {
+        let empty = TypeRecords::default();
+        let mut broken = Vec::new();
+
+        for (r#type, records) in self.0.iter() {
+            let earlier = earlier.0.get(r#type).unwrap_or(&empty);
+
+            let mut compare = |kind, resistance, record: Option<&'b Record>, previous: Option<&Record>| {
+                let Some(record) = record else {
+                    return;
+                };
+
+                if !previous.is_none_or(|p| is_better(kind, record.value, p.value)) {
+                    return;
+                }
+
+                broken.push(BrokenRecord {
+                    r#type,
+                    record: kind,
+                    resistance,
+                    value: record.value,
+                    previous: previous.map(|p| p.value),
+                    exercise_id: &record.exercise_id,
+                    set_id: &record.set_id,
+                });
+            };
+
+            compare(Kind::BestResistance, None, records.best_resistance.as_ref(), earlier.best_resistance.as_ref());
+
+            for (resistance, record) in records.best_repetitions.iter() {
+                compare(
+                    Kind::BestRepetitions,
+                    Some(*resistance),
+                    Some(record),
+                    earlier.best_repetitions.get(resistance),
+                );
+            }
+
+            compare(Kind::BestOneRepMax, None, records.best_one_rep_max.as_ref(), earlier.best_one_rep_max.as_ref());
+            compare(Kind::LongestDistance, None, records.longest_distance.as_ref(), earlier.longest_distance.as_ref());
+            compare(Kind::FastestPace, None, records.fastest_pace.as_ref(), earlier.fastest_pace.as_ref());
+        }
+
+        broken
+    }
+
+    /// Make the items of every type in the records.
+    pub fn to_dynamo_db(&self) -> Vec<(String, DynamoDbItem)> {
+        self.0.iter()
+            .map(|(r#type, records)| (make_key(r#type), records.to_dynamo_db()))
+            .collect()
+    }
+}
+
+/// Whether the value of a record is better than another value.
+fn is_better(kind: Kind, value: f64, other: f64) -> bool {
+    match kind {
+        Kind::FastestPace => value < other,
+        _ => value > other,
+    }
+}
+
+/// A write to an exercise. The ID is the workout ID and exercise ID separated
+/// by a `#`.
+pub enum Write<'a> {
+    Put(String, &'a common::Exercise<'a>),
+    Delete(String),
+}
+
+/// The records that a sequence of writes to exercises results in.
+pub struct Tracked {
+    /// The record writes to add to the transaction of each write.
+    pub items: Vec<Vec<TransactItem>>,
+    /// The records of the types that were written to, as they are after the
+    /// writes. Types without records are empty.
+    pub records: Records,
+    /// The records that were stored before the writes.
+    pub stored: Records,
+}
+
+impl Tracked {
+    /// The record writes of all of the writes for when they're applied in a
+    /// single transaction.
+    pub fn combined_items(&self) -> Vec<TransactItem> {
+        self.records.0.iter()
+            .filter(|(r#type, records)| match self.stored.0.get(*r#type) {
+                Some(stored) => stored != *records,
+                None => !records.is_empty(),
+            })
+            .map(|(r#type, records)| TransactItem::Put {
+                id: make_key(r#type),
+                item: records.to_dynamo_db(),
+            })
+            .collect()
+    }
+}
+
+/// Computes the records of the types that are affected by a sequence of writes
+/// to the exercises of a collection.
+struct Tracker<'a> {
+    /// The exercises of the collection by ID as they are after the writes so
+    /// far, if the collection was read.
+    exercises: Option<BTreeMap<String, &'a common::Exercise<'a>>>,
+    /// The records of every type that has been written to, as they are after
+    /// the writes so far.
+    records: Records,
+    /// The records that were stored before the writes.
+    stored: Records,
+}
+
+impl<'a> Tracker<'a> {
+    fn new(user: Option<&'a common::User<'a>>, stored: Records) -> Self {
+        Self {
+            exercises: user.map(|user| user.exercises.iter()
+                .map(|e| (e.workout_exercise_id.to_owned(), e))
+                .collect()),
+            records: Records::default(),
+            stored,
+        }
+    }
+
+    /// Make the record writes of each write. Returns `None` if the exercises
+    /// of the collection are needed.
+    fn apply(&mut self, writes: &'a [Write<'a>]) -> Option<Vec<Vec<TransactItem>>> {
+        writes.iter()
+            .map(|write| match write {
+                Write::Put(id, exercise) => self.put_exercise(id, exercise),
+                Write::Delete(id) => self.delete_exercise(id),
+            })
+            .collect()
+    }
+
+    fn put_exercise(&mut self, exercise_id: &str, exercise: &'a common::Exercise<'a>) -> Option<Vec<TransactItem>> {
+        let r#type = exercise.r#type.0.as_ref();
+
+        let Some(exercises) = &mut self.exercises else {
+            // Holding a record of another type means that the type changed.
+            if self.held_types(exercise_id).iter().any(|t| t != r#type) {
+                return None;
+            }
+
+            let merged = self.current(r#type).merge(exercise_id, exercise)?;
+            return Some(self.set(r#type, merged));
+        };
+
+        let previous = exercises.insert(exercise_id.to_owned(), exercise);
+
+        let mut types = BTreeSet::from([r#type]);
+        types.extend(previous.map(|e| e.r#type.0.as_ref()));
+
+        Some(self.update(types))
+    }
+
+    fn delete_exercise(&mut self, exercise_id: &str) -> Option<Vec<TransactItem>> {
+        let Some(exercises) = &mut self.exercises else {
+            return self.held_types(exercise_id).is_empty().then(Vec::new);
+        };
+
+        match exercises.remove(exercise_id) {
+            Some(exercise) => Some(self.update(BTreeSet::from([exercise.r#type.0.as_ref()]))),
+            None => Some(Vec::new()),
+        }
+    }
+
+    /// The types with a record that was set by an exercise.
+    fn held_types(&self, exercise_id: &str) -> Vec<String> {
+        self.stored.0.keys()
+            .chain(self.records.0.keys())
+            .filter(|t| self.current(t).is_held_by(exercise_id))
+            .cloned()
+            .collect()
+    }
+
+    /// The records of a type as they are after the writes so far.
+    fn current(&self, r#type: &str) -> TypeRecords {
+        self.records.0.get(r#type)
+            .or_else(|| self.stored.0.get(r#type))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Compute the records of some types from the exercises.
+    fn update(&mut self, types: BTreeSet<&str>) -> Vec<TransactItem> {
+        let exercises = self.exercises.as_ref().unwrap();
+        let computed = types.into_iter()
+            .map(|r#type| (r#type, TypeRecords::compute(exercises.iter()
+                .filter(|(_, e)| e.r#type.0 == r#type)
+                .map(|(id, e)| (id.as_str(), *e)))))
+            .collect::<Vec<_>>();
+
+        computed.into_iter()
+            .flat_map(|(r#type, records)| self.set(r#type, records))
+            .collect()
+    }
+
+    /// Set the records of a type and make the write if they changed.
+    fn set(&mut self, r#type: &str, records: TypeRecords) -> Vec<TransactItem> {
+        let mut items = Vec::new();
+
+        if self.current(r#type) != records {
+            items.push(TransactItem::Put {
+                id: make_key(r#type),
+                item: records.to_dynamo_db(),
+            });
+        }
+
+        self.records.0.insert(r#type.to_owned(), records);
+
+        items
+    }
+}
+
+/// Compute the record writes of a sequence of writes to the exercises of the
+/// collection at a version. The stored records must include every type that an
+/// exercise has before or after the writes. If the version isn't current then
+/// the writes will fail, so nothing is computed.
+pub async fn track(
+    store: &impl UserStore,
+    user_id: &str,
+    version: u64,
+    stored: Records,
+    writes: &[Write<'_>],
+) -> Result<Tracked, Error> {
+    let mut tracker = Tracker::new(None, stored);
+
+    if let Some(items) = tracker.apply(writes) {
+        return Ok(Tracked { items, records: tracker.records, stored: tracker.stored });
+    }
+
+    let stored = tracker.stored;
+
+    if store.read_version(user_id).await? != version {
+        return Ok(Tracked {
+            items: writes.iter().map(|_| Vec::new()).collect(),
+            records: Records::default(),
+            stored,
+        });
+    }
+
+    let collection = store.query_collection(
+        user_id,
+        common::collection_from_version(version),
+        false,
+    ).await?;
+    let user = common::db_to_user(version, false, &collection);
+    let mut tracker = Tracker::new(Some(&user), stored);
+    let items = tracker.apply(writes).unwrap();
+
+    Ok(Tracked { items, records: tracker.records, stored: tracker.stored })
+}
+
+/// Read the records of a user. Users that haven't set any records have none.
+pub async fn read(store: &impl UserStore, user_id: &str) -> Result<Records, Error> {
+    Ok(Records(store.query_prefix(user_id, KEY_PREFIX).await?
+        .iter()
+        .map(|item| {
+            let id = item["Id"].as_s().unwrap();
+            (id[KEY_PREFIX.len()..].to_owned(), TypeRecords::from_dynamo_db(item))
+        })
+        .filter(|(_, records)| !records.is_empty())
+        .collect()))
+}
+
+/// Read the records of some types. Types without records are left out.
+pub async fn read_types(store: &impl UserStore, user_id: &str, types: &[&str]) -> Result<Records, Error> {
+    let types = types.iter().copied().collect::<BTreeSet<_>>();
+    let mut records = Records::default();
+
+    for r#type in types {
+        if let Some(item) = store.get_item(user_id, &make_key(r#type)).await? {
+            let type_records = TypeRecords::from_dynamo_db(&item);
+
+            if !type_records.is_empty() {
+                records.0.insert(r#type.to_owned(), type_records);
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use super::*;
+
+    const SET_IDS: [&str; 3] = ["s0", "s1", "s2"];
+
+    /// Make an exercise with a set for each repetitions and resistance pair.
+    fn exercise<'a>(id: &'a str, r#type: &'a str, sets: &[(u32, u32)]) -> common::Exercise<'a> {
+        common::Exercise {
+            workout_exercise_id: id,
+            order: 0,
+            r#type: common::MaxLenStr(Cow::Borrowed(r#type)),
+            notes: common::MaxLenStr(Cow::Borrowed("")),
+            sets: common::MaxLenVec(sets.iter()
+                .zip(SET_IDS)
+                .map(|((repetitions, resistance), set_id)| common::Set {
+                    set_id: common::Uuid(set_id),
+                    repetitions: Some(*repetitions),
+                    resistance: Some(*resistance),
+                    speed: None,
+                    distance: None,
+                    duration: None,
+                })
+                .collect()),
+            modified_version: 0,
+        }
+    }
+
+    fn user<'a>(exercises: Vec<common::Exercise<'a>>) -> common::User<'a> {
+        common::User { exercises, ..Default::default() }
+    }
+
+    /// Apply the writes with and without the exercises and check that they
+    /// agree whenever the exercises aren't needed. Returns whether they were.
+    fn check(before: &common::User, writes: &[Write]) -> bool {
+        let stored = Records::compute(before);
+
+        let mut full = Tracker::new(Some(before), stored.clone());
+        let full_items = full.apply(writes).unwrap();
+
+        let mut incremental = Tracker::new(None, stored);
+        let Some(incremental_items) = incremental.apply(writes) else {
+            return true;
+        };
+
+        for r#type in full.records.0.keys().chain(incremental.records.0.keys()) {
+            assert!(full.current(r#type) == incremental.current(r#type), "{type}");
+        }
+
+        assert_eq!(
+            full_items.iter().map(Vec::len).collect::<Vec<_>>(),
+            incremental_items.iter().map(Vec::len).collect::<Vec<_>>(),
+        );
+        false
+    }
+
+    #[test]
+    fn raising_a_record_only_needs_the_exercise() {
+        let before = user(vec![exercise("a", "chest-press", &[(5, 100)])]);
+        let b = exercise("b", "chest-press", &[(5, 110), (8, 100)]);
+
+        assert!(!check(&before, &[Write::Put("b".into(), &b)]));
+    }
+
+    #[test]
+    fn lowering_a_record_needs_the_exercises() {
+        let before = user(vec![
+            exercise("a", "chest-press", &[(5, 100)]),
+            exercise("b", "chest-press", &[(4, 100)]),
+        ]);
+        let a = exercise("a", "chest-press", &[(5, 70)]);
+
+        assert!(check(&before, &[Write::Put("a".into(), &a)]));
+        assert!(check(&before, &[Write::Delete("a".into())]));
+        assert!(!check(&before, &[Write::Delete("b".into())]));
+    }
+
+    #[test]
+    fn changing_the_type_of_a_holder_needs_the_exercises() {
+        let before = user(vec![exercise("a", "chest-press", &[(5, 100)])]);
+        let a = exercise("a", "shoulder-press", &[(5, 100)]);
+
+        assert!(check(&before, &[Write::Put("a".into(), &a)]));
+    }
+
+    #[test]
+    fn ties_go_to_the_lowest_id() {
+        let before = user(vec![exercise("b", "chest-press", &[(5, 100)])]);
+        let a = exercise("a", "chest-press", &[(5, 100)]);
+        let c = exercise("c", "chest-press", &[(5, 100)]);
+
+        assert!(!check(&before, &[Write::Put("a".into(), &a), Write::Put("c".into(), &c)]));
+
+        let writes = [Write::Put("a".into(), &a)];
+        let mut tracker = Tracker::new(None, Records::compute(&before));
+        tracker.apply(&writes).unwrap();
+        let records = &tracker.records.0["chest-press"];
+
+        assert_eq!(records.best_resistance.as_ref().unwrap().exercise_id, "a");
+    }
+
+    #[test]
+    fn unchanged_records_arent_written() {
+        let before = user(vec![exercise("a", "chest-press", &[(5, 100)])]);
+        let b = exercise("b", "chest-press", &[(4, 100)]);
+        let writes = [Write::Put("b".into(), &b)];
+        let mut tracker = Tracker::new(None, Records::compute(&before));
+
+        assert!(tracker.apply(&writes).unwrap()[0].is_empty());
+    }
+
+    #[test]
+    fn only_the_heaviest_resistances_have_repetitions_records() {
+        let exercises = (0..=MAX_REPETITION_RECORDS as u32)
+            .map(|r| exercise("a", "chest-press", &[(5, r + 1)]))
+            .collect::<Vec<_>>();
+        let records = TypeRecords::compute(exercises.iter().map(|e| (e.workout_exercise_id, e)));
+
+        assert_eq!(records.best_repetitions.len(), MAX_REPETITION_RECORDS);
+        assert_eq!(records.best_repetitions.first_key_value().unwrap().0, &2);
+    }
+
+    #[test]
+    fn incremental_updates_agree_with_computing_from_scratch() {
+        // A small linear congruential generator keeps the test deterministic.
+        let mut seed = 1u64;
+        let mut next = |n: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) % n) as u32
+        };
+
+        const IDS: [&str; 4] = ["a", "b", "c", "d"];
+        const TYPES: [&str; 2] = ["chest-press", "shoulder-press"];
+
+        let mut random_exercise = |id: Option<&'static str>| {
+            let id = id.unwrap_or_else(|| IDS[next(4) as usize]);
+            let r#type = TYPES[next(2) as usize];
+            let sets = (0..1 + next(3))
+                .map(|_| (1 + next(4), 10 * (1 + next(4))))
+                .collect::<Vec<_>>();
+            (exercise(id, r#type, &sets), next(4) == 0)
+        };
+
+        let mut incremental = 0;
+
+        for _ in 0..2000 {
+            let before = user(IDS.iter()
+                .map(|id| random_exercise(Some(id)))
+                .filter(|(_, skip)| !skip)
+                .map(|(e, _)| e)
+                .collect());
+
+            let count = before.exercises.len() % 3 + 1;
+            let puts = (0..count)
+                .map(|_| random_exercise(None))
+                .collect::<Vec<_>>();
+            let writes = puts.iter()
+                .map(|(e, delete)| match delete {
+                    true => Write::Delete(e.workout_exercise_id.into()),
+                    false => Write::Put(e.workout_exercise_id.into(), e),
+                })
+                .collect::<Vec<_>>();
+
+            if !check(&before, &writes) {
+                incremental += 1;
+            }
+        }
+
+        // Enough of the sequences should be applied without the exercises for
+        // that path to be covered.
+        assert!(incremental > 200);
+    }
+}
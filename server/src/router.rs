@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use lambda_http::http::Method;
+use crate::common;
+
+// Routing is done here rather than relying on the route key from API Gateway
+// so that the function can be invoked by anything that produces an HTTP
+// request. Path parameters are written as `{name:type}` in the templates. The
+// type of each parameter is checked while matching so a path with a malformed
+// parameter doesn't match the route at all and the handlers can assume that
+// their path parameters are valid.
+
+#[derive(Clone, Copy)]
+pub enum Endpoint {
+    UserGet,
+    UserBatchPost,
+    UserExerciseTypeDelete,
+    UserExerciseTypePut,
+    UserExportGet,
+    UserExportMeasurementsGet,
+    UserFeedTokenDelete,
+    UserFeedTokenPost,
+    UserImportGet,
+    UserSnapshotGet,
+    UserSnapshotPut,
+    UserSnapshotCsvPut,
+    UserSnapshotTrackerPut,
+    UserStatsCardioGet,
+    UserStatsExerciseGet,
+    UserMeasurementDelete,
+    UserMeasurementPut,
+    UserProgramDelete,
+    UserProgramPut,
+    UserProgramScheduleGet,
+    UserRecordsGet,
+    UserRoutineDelete,
+    UserRoutinePut,
+    UserRoutineInstantiatePost,
+    UserWorkoutDelete,
+    UserWorkoutPut,
+    UserWorkoutExerciseDelete,
+    UserWorkoutExercisePut,
+    UserWorkoutOrderPut,
+    UserWorkoutsIcsGet,
+}
+
+impl Endpoint {
+    /// Whether the endpoint does its own authentication rather than requiring
+    /// a JWT.
+    pub fn is_public(self) -> bool {
+        matches!(self, Self::UserWorkoutsIcsGet)
+    }
+}
+
+const ROUTES: [(Method, &str, Endpoint); 30] = [
+    (Method::GET, "/user", Endpoint::UserGet),
+    (Method::POST, "/user/batch", Endpoint::UserBatchPost),
+    (Method::DELETE, "/user/exercise-type/{exerciseTypeId:uuid}", Endpoint::UserExerciseTypeDelete),
+    (Method::PUT, "/user/exercise-type/{exerciseTypeId:uuid}", Endpoint::UserExerciseTypePut),
+    (Method::GET, "/user/export.csv", Endpoint::UserExportGet),
+    (Method::GET, "/user/export/measurements.csv", Endpoint::UserExportMeasurementsGet),
+    (Method::DELETE, "/user/feed-token", Endpoint::UserFeedTokenDelete),
+    (Method::POST, "/user/feed-token", Endpoint::UserFeedTokenPost),
+    (Method::GET, "/user/import/{jobId:uuid}", Endpoint::UserImportGet),
+    (Method::GET, "/user/snapshot", Endpoint::UserSnapshotGet),
+    (Method::PUT, "/user/snapshot", Endpoint::UserSnapshotPut),
+    (Method::PUT, "/user/snapshot/csv", Endpoint::UserSnapshotCsvPut),
+    (Method::PUT, "/user/snapshot/tracker", Endpoint::UserSnapshotTrackerPut),
+    (Method::GET, "/user/stats/cardio", Endpoint::UserStatsCardioGet),
+    (Method::GET, "/user/stats/exercise/{type:exercise-type}", Endpoint::UserStatsExerciseGet),
+    (Method::DELETE, "/user/measurement/{measurementId:date}", Endpoint::UserMeasurementDelete),
+    (Method::PUT, "/user/measurement/{measurementId:date}", Endpoint::UserMeasurementPut),
+    (Method::DELETE, "/user/program/{programId:uuid}", Endpoint::UserProgramDelete),
+    (Method::PUT, "/user/program/{programId:uuid}", Endpoint::UserProgramPut),
+    (Method::GET, "/user/program/{programId:uuid}/schedule", Endpoint::UserProgramScheduleGet),
+    (Method::GET, "/user/records", Endpoint::UserRecordsGet),
+    (Method::DELETE, "/user/routine/{routineId:uuid}", Endpoint::UserRoutineDelete),
+    (Method::PUT, "/user/routine/{routineId:uuid}", Endpoint::UserRoutinePut),
+    (Method::POST, "/user/routine/{routineId:uuid}/instantiate", Endpoint::UserRoutineInstantiatePost),
+    (Method::DELETE, "/user/workout/{workoutId:uuid}", Endpoint::UserWorkoutDelete),
+    (Method::PUT, "/user/workout/{workoutId:uuid}", Endpoint::UserWorkoutPut),
+    (Method::DELETE, "/user/workout/{workoutId:uuid}/exercise/{exerciseId:uuid}", Endpoint::UserWorkoutExerciseDelete),
+    (Method::PUT, "/user/workout/{workoutId:uuid}/exercise/{exerciseId:uuid}", Endpoint::UserWorkoutExercisePut),
+    (Method::PUT, "/user/workout/{workoutId:uuid}/order", Endpoint::UserWorkoutOrderPut),
+    (Method::GET, "/user/workouts.ics", Endpoint::UserWorkoutsIcsGet),
+];
+
+pub enum Routed {
+    /// The method and path matched a route.
+    Found(Endpoint, HashMap<String, String>),
+    /// The path matched at least one route but the method didn't. These are
+    /// the methods that are allowed for the path.
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+pub fn route(method: &Method, path: &str) -> Routed {
+    let mut allowed = Vec::new();
+
+    for (route_method, template, endpoint) in ROUTES.iter() {
+        if let Some(params) = match_path(template, path) {
+            if route_method == method {
+                return Routed::Found(*endpoint, params);
+            }
+            allowed.push(route_method.clone());
+        }
+    }
+
+    if allowed.is_empty() {
+        Routed::NotFound
+    } else {
+        Routed::MethodNotAllowed(allowed)
+    }
+}
+
+fn match_path(template: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut template_segments = template.split('/');
+    let mut path_segments = path.split('/');
+
+    loop {
+        match (template_segments.next(), path_segments.next()) {
+            (None, None) => return Some(params),
+            (Some(t), Some(p)) => {
+                if let Some(param) = t.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                    let (name, param_type) = param.split_once(':').unwrap();
+                    let valid = match param_type {
+                        "date" => common::is_date(p),
+                        "exercise-type" => common::get_set_kind(p).is_some() || common::is_uuid(p),
+                        "uuid" => common::is_uuid(p),
+                        _ => unreachable!(),
+                    };
+
+                    if !valid {
+                        return None;
+                    }
+
+                    params.insert(name.to_owned(), p.to_owned());
+                } else if t != p {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lambda_http::http::{StatusCode, header::ALLOW};
+    use super::*;
+
+    const WORKOUT_ID: &str = "0f0c3a56-9f1e-4b53-8d2a-6b1f0e3c2a11";
+    const EXERCISE_ID: &str = "5b7d2c1e-3a4f-4e6d-9c8b-7a6f5e4d3c22";
+
+    fn params(method: Method, path: &str) -> Option<HashMap<String, String>> {
+        match route(&method, path) {
+            Routed::Found(_, params) => Some(params),
+            _ => None,
+        }
+    }
+
+    fn is_not_found(method: Method, path: &str) -> bool {
+        matches!(route(&method, path), Routed::NotFound)
+    }
+
+    fn params_for_type(r#type: &str) -> Option<HashMap<String, String>> {
+        params(Method::GET, &format!("/user/stats/exercise/{type}"))
+    }
+
+    #[test]
+    fn parameters_are_extracted() {
+        let path = format!("/user/workout/{WORKOUT_ID}/exercise/{EXERCISE_ID}");
+        let params = params(Method::PUT, &path).unwrap();
+
+        assert_eq!(params.len(), 2);
+        assert_eq!(params["workoutId"], WORKOUT_ID);
+        assert_eq!(params["exerciseId"], EXERCISE_ID);
+
+        assert!(params_for_type("biceps-curl").is_some());
+        assert!(params_for_type(WORKOUT_ID).is_some());
+    }
+
+    #[test]
+    fn malformed_parameters_dont_match() {
+        assert!(is_not_found(Method::PUT, "/user/workout/not-a-uuid"));
+        assert!(is_not_found(Method::PUT, "/user/measurement/2026-13-01"));
+        assert!(is_not_found(Method::GET, "/user/stats/exercise/rowing-machine"));
+        assert!(is_not_found(Method::GET, &format!("/user/workout/{WORKOUT_ID}/")));
+        assert!(is_not_found(Method::GET, "/user/unknown"));
+        assert!(is_not_found(Method::GET, "/"));
+    }
+
+    #[test]
+    fn other_methods_are_not_allowed() {
+        let path = format!("/user/workout/{WORKOUT_ID}");
+
+        let Routed::MethodNotAllowed(allowed) = route(&Method::PATCH, &path) else {
+            panic!("expected the method not to be allowed");
+        };
+
+        assert_eq!(allowed, [Method::DELETE, Method::PUT]);
+
+        // Paths that only fail to match because of a malformed parameter are
+        // not found rather than not allowed.
+
+        assert!(is_not_found(Method::PATCH, "/user/workout/not-a-uuid"));
+
+        let res = common::ApiError::MethodNotAllowed(allowed).into_response();
+
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "DELETE, PUT");
+    }
+}
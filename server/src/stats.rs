@@ -0,0 +1,560 @@
+use std::collections::BTreeMap;
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::Serialize;
+use crate::common;
+
+// Statistics are computed from the exercises of a user when they're requested
+// rather than being stored. Exercises belong to the date that their workout
+// started on so workouts without a start time are left out.
+//
+// The exercise statistics total the sets of one exercise type for each
+// workout. Which totals are given depends on the fields that the type records.
+// Every type has sets but only types that record repetitions have repetitions
+// and only types that record both repetitions and resistance have tonnage and
+// an estimated one-rep max. The one-rep max of a workout is the best estimate
+// from any of its sets.
+//
+// The cardio statistics total the sets of every type that records a duration
+// along with a distance or a speed, which are the machines, by week and by
+// month. Weeks start on Monday. The client calculates the distance of a fixed
+// set from its speed and duration but the user can override it, so the
+// distance is only calculated here if the set doesn't have one. The pace and
+// speed are averaged over the sets that have both a distance and a duration.
+//
+// Set fields are u32 so the totals are u64 and saturate rather than overflow
+// on absurd values, which the API doesn't otherwise limit.
+
+/// The formulas for estimating the weight that could be lifted for a single
+/// repetition from a set of multiple repetitions.
+#[derive(Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Formula {
+    /// `resistance × (1 + repetitions / 30)`
+    #[default]
+    Epley,
+    /// `resistance × 36 / (37 - repetitions)`, which is undefined from 37
+    /// repetitions.
+    Brzycki,
+    /// `resistance × repetitions ^ 0.1`
+    Lombardi,
+}
+
+impl Formula {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "epley" => Some(Self::Epley),
+            "brzycki" => Some(Self::Brzycki),
+            "lombardi" => Some(Self::Lombardi),
+            _ => None,
+        }
+    }
+
+    /// Estimate the one-rep max of a set. A single repetition is its own
+    /// one-rep max with every formula. Returns `None` if the formula isn't
+    /// defined for the number of repetitions.
+    pub fn estimate(self, resistance: u32, repetitions: u32) -> Option<f64> {
+        let resistance = resistance as f64;
+
+        if repetitions == 0 {
+            return None;
+        }
+
+        if repetitions == 1 {
+            return Some(resistance);
+        }
+
+        match self {
+            Self::Epley => Some(resistance * (1.0 + repetitions as f64 / 30.0)),
+            Self::Brzycki => (repetitions < 37)
+                .then(|| resistance * 36.0 / (37.0 - repetitions as f64)),
+            Self::Lombardi => Some(resistance * (repetitions as f64).powf(0.1)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ExerciseStats<'a> {
+    pub r#type: &'a str,
+    pub formula: Formula,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// The totals of each workout with the type in order of date.
+    pub workouts: Vec<WorkoutTotals<'a>>,
+    pub trend: Trend,
+}
+
+#[derive(Serialize)]
+pub struct WorkoutTotals<'a> {
+    pub workout_id: &'a str,
+    /// The date that the workout started on.
+    pub date: &'a str,
+    pub sets: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repetitions: Option<u64>,
+    /// The sum of repetitions × resistance over the sets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tonnage: Option<u64>,
+    /// The best estimated one-rep max of the sets or `None` if none of them
+    /// could be estimated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_rep_max: Option<f64>,
+}
+
+/// The change per week of the totals fitted with least squares over the
+/// workouts. Each is `None` if fewer than two dates have a value.
+#[derive(Serialize)]
+pub struct Trend {
+    pub tonnage_per_week: Option<f64>,
+    pub one_rep_max_per_week: Option<f64>,
+}
+
+/// The units of the cardio statistics.
+#[derive(Serialize)]
+pub struct Units {
+    pub distance: &'static str,
+    pub duration: &'static str,
+    pub pace: &'static str,
+    pub speed: &'static str,
+}
+
+const UNITS: Units = Units {
+    distance: "m",
+    duration: "s",
+    pace: "s/km",
+    speed: "km/h",
+};
+
+#[derive(Serialize)]
+pub struct CardioStats<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    pub units: Units,
+    pub total: Totals,
+    /// The totals of each exercise type.
+    pub machines: BTreeMap<&'a str, Totals>,
+    pub weeks: Vec<Period<'a>>,
+    pub months: Vec<Period<'a>>,
+}
+
+#[derive(Default, Serialize)]
+pub struct Period<'a> {
+    /// The first day of the period.
+    pub start: String,
+    #[serde(flatten)]
+    pub totals: Totals,
+    pub machines: BTreeMap<&'a str, Totals>,
+}
+
+#[derive(Default, Serialize)]
+pub struct Totals {
+    pub sets: u32,
+    pub distance: u64,
+    pub duration: u64,
+    /// The average duration per kilometre or `None` if no set had both a
+    /// distance and a duration.
+    pub pace: Option<f64>,
+    /// The average speed or `None` if no set had both a distance and a
+    /// duration.
+    pub speed: Option<f64>,
+    #[serde(skip)]
+    paced_distance: u64,
+    #[serde(skip)]
+    paced_duration: u64,
+}
+
+impl Totals {
+    fn add(&mut self, distance: u64, duration: u64) {
+        self.sets = self.sets.saturating_add(1);
+        self.distance = self.distance.saturating_add(distance);
+        self.duration = self.duration.saturating_add(duration);
+
+        if distance > 0 && duration > 0 {
+            self.paced_distance = self.paced_distance.saturating_add(distance);
+            self.paced_duration = self.paced_duration.saturating_add(duration);
+        }
+    }
+
+    /// Compute the averages once all of the sets have been added.
+    fn finish(&mut self) {
+        if self.paced_distance > 0 {
+            self.pace = Some(self.paced_duration as f64 * 1000.0 / self.paced_distance as f64);
+            self.speed = Some(self.paced_distance as f64 * 3.6 / self.paced_duration as f64);
+        }
+    }
+}
+
+impl Period<'_> {
+    fn finish(&mut self) {
+        self.totals.finish();
+        self.machines.values_mut().for_each(Totals::finish);
+    }
+}
+
+/// Get the set fields that an exercise type records or `None` if the type is a
+/// custom type that doesn't exist.
+pub fn type_fields<'a>(user: &'a common::User<'a>, r#type: &str) -> Option<Vec<&'a str>> {
+    match common::get_set_kind(r#type) {
+        Some(kind) => Some(kind.fields().to_vec()),
+        None => user.exercise_types.iter()
+            .find(|t| t.exercise_type_id == r#type)
+            .map(|t| t.fields.0.clone()),
+    }
+}
+
+/// Get the date that a workout started on.
+pub fn workout_date<'a>(workout: &common::Workout<'a>) -> Option<&'a str> {
+    workout.start_time.and_then(|t| t.get(..10))
+}
+
+/// Compute the statistics of an exercise type between two dates, inclusive.
+/// The fields are the set fields that the type records.
+pub fn exercise<'a>(
+    user: &'a common::User<'a>,
+    r#type: &'a str,
+    fields: &[&str],
+    formula: Formula,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> ExerciseStats<'a> {
+    let has_repetitions = fields.contains(&"repetitions");
+    let has_resistance = has_repetitions && fields.contains(&"resistance");
+
+    let dates = workout_dates(user, from, to);
+    let mut totals = BTreeMap::<&str, WorkoutTotals>::new();
+
+    for exercise in user.exercises.iter().filter(|e| e.r#type.0 == r#type) {
+        let workout_id = exercise.workout_exercise_id.split_once('#').unwrap().0;
+        let Some((date, _)) = dates.get(workout_id) else {
+            continue;
+        };
+
+        let workout = totals.entry(workout_id).or_insert_with(|| WorkoutTotals {
+            workout_id,
+            date,
+            sets: 0,
+            repetitions: has_repetitions.then_some(0),
+            tonnage: has_resistance.then_some(0),
+            one_rep_max: None,
+        });
+
+        for set in exercise.sets.0.iter() {
+            workout.sets = workout.sets.saturating_add(1);
+
+            let repetitions = set.repetitions.unwrap_or(0);
+            let resistance = set.resistance.unwrap_or(0);
+
+            if let Some(r) = &mut workout.repetitions {
+                *r = r.saturating_add(repetitions as u64);
+            }
+
+            if let Some(t) = &mut workout.tonnage {
+                *t = t.saturating_add(repetitions as u64 * resistance as u64);
+
+                if let Some(estimate) = formula.estimate(resistance, repetitions) {
+                    workout.one_rep_max = Some(workout.one_rep_max.map_or(estimate, |m| m.max(estimate)));
+                }
+            }
+        }
+    }
+
+    let mut workouts = totals.into_values().collect::<Vec<_>>();
+    workouts.sort_by(|a, b| (a.date, a.workout_id).cmp(&(b.date, b.workout_id)));
+
+    let trend = Trend {
+        tonnage_per_week: slope_per_week(workouts.iter()
+            .filter_map(|w| Some((w.date, w.tonnage? as f64)))),
+        one_rep_max_per_week: slope_per_week(workouts.iter()
+            .filter_map(|w| Some((w.date, w.one_rep_max?)))),
+    };
+
+    ExerciseStats {
+        r#type,
+        formula,
+        from: from.map(|d| d.format("%F").to_string()),
+        to: to.map(|d| d.format("%F").to_string()),
+        workouts,
+        trend,
+    }
+}
+
+/// Compute the cardio statistics between two dates, inclusive.
+pub fn cardio<'a>(
+    user: &'a common::User<'a>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> CardioStats<'a> {
+    let dates = workout_dates(user, from, to);
+
+    let mut total = Totals::default();
+    let mut machines = BTreeMap::<&str, Totals>::new();
+    let mut weeks = BTreeMap::<NaiveDate, Period>::new();
+    let mut months = BTreeMap::<NaiveDate, Period>::new();
+    let mut is_cardio = BTreeMap::<&str, bool>::new();
+
+    for exercise in user.exercises.iter() {
+        let r#type = exercise.r#type.0.as_ref();
+        let workout_id = exercise.workout_exercise_id.split_once('#').unwrap().0;
+        let Some((_, date)) = dates.get(workout_id) else {
+            continue;
+        };
+
+        let cardio = *is_cardio.entry(r#type).or_insert_with(|| {
+            type_fields(user, r#type).is_some_and(|fields| {
+                fields.contains(&"duration")
+                    && (fields.contains(&"distance") || fields.contains(&"speed"))
+            })
+        });
+
+        if !cardio {
+            continue;
+        }
+
+        let week = *date - Duration::days(date.weekday().num_days_from_monday() as i64);
+        let month = date.with_day(1).unwrap();
+
+        for set in exercise.sets.0.iter() {
+            let duration = set.duration.unwrap_or(0) as u64;
+            let distance = match set.distance.filter(|d| *d > 0) {
+                Some(distance) => distance as u64,
+                None => (set.speed.unwrap_or(0) as u128 * duration as u128 * 1000 / 3600) as u64,
+            };
+
+            total.add(distance, duration);
+            machines.entry(r#type).or_default().add(distance, duration);
+
+            for (periods, start) in [(&mut weeks, week), (&mut months, month)] {
+                let period = periods.entry(start).or_insert_with(|| Period {
+                    start: start.format("%F").to_string(),
+                    ..Default::default()
+                });
+                period.totals.add(distance, duration);
+                period.machines.entry(r#type).or_default().add(distance, duration);
+            }
+        }
+    }
+
+    total.finish();
+    machines.values_mut().for_each(Totals::finish);
+    weeks.values_mut().for_each(Period::finish);
+    months.values_mut().for_each(Period::finish);
+
+    CardioStats {
+        from: from.map(|d| d.format("%F").to_string()),
+        to: to.map(|d| d.format("%F").to_string()),
+        units: UNITS,
+        total,
+        machines,
+        weeks: weeks.into_values().collect(),
+        months: months.into_values().collect(),
+    }
+}
+
+/// Get the start date of each workout that started between two dates,
+/// inclusive, by workout ID.
+fn workout_dates<'a>(
+    user: &'a common::User<'a>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> BTreeMap<&'a str, (&'a str, NaiveDate)> {
+    user.workouts.iter()
+        .filter_map(|w| {
+            let date_str = workout_date(w)?;
+            Some((w.workout_id, (date_str, common::parse_date(date_str)?)))
+        })
+        .filter(|(_, (_, date))| from.is_none_or(|f| *date >= f) && to.is_none_or(|t| *date <= t))
+        .collect()
+}
+
+/// Fit a line to values by date and return its slope per week.
+fn slope_per_week<'a>(points: impl Iterator<Item = (&'a str, f64)>) -> Option<f64> {
+    let points = points
+        .filter_map(|(date, value)| {
+            let date = common::parse_date(date)?;
+            Some((date.num_days_from_ce() as f64, value))
+        })
+        .collect::<Vec<_>>();
+
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+
+    let covariance = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>();
+    let variance = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum::<f64>();
+
+    // There's no variance if there are fewer than two distinct dates.
+
+    (variance > 0.0).then(|| covariance / variance * 7.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use super::*;
+
+    const THURSDAY: &str = "0f0c3a56-9f1e-4b53-8d2a-6b1f0e3c2a11";
+    const MONDAY: &str = "5b7d2c1e-3a4f-4e6d-9c8b-7a6f5e4d3c22";
+    const NOT_STARTED: &str = "8e9f0a1b-2c3d-4e5f-8a7b-6c5d4e3f2a33";
+
+    fn user_json(exercises: &[(&str, &str, Vec<Value>)]) -> String {
+        let workouts = [
+            (THURSDAY, Some("2026-10-01T18:00:00Z")),
+            (MONDAY, Some("2026-10-05T18:00:00Z")),
+            (NOT_STARTED, None),
+        ];
+
+        let exercises = exercises.iter().enumerate()
+            .map(|(i, (workout_id, r#type, sets))| json!({
+                "workout_exercise_id": format!("{workout_id}#{}", uuid::Uuid::new_v4()),
+                "order": i,
+                "type": r#type,
+                "notes": "",
+                "sets": sets.iter()
+                    .map(|s| {
+                        let mut set = s.clone();
+                        set["set_id"] = uuid::Uuid::new_v4().to_string().into();
+                        set
+                    })
+                    .collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>();
+
+        json!({
+            "measurement_sets": [],
+            "workouts": workouts.map(|(workout_id, start_time)| json!({
+                "workout_id": workout_id,
+                "start_time": start_time,
+                "finish_time": null,
+                "notes": "",
+            })),
+            "exercises": exercises,
+        }).to_string()
+    }
+
+    fn curl(repetitions: u32, resistance: u32) -> Value {
+        json!({ "repetitions": repetitions, "resistance": resistance })
+    }
+
+    fn date(s: &str) -> Option<NaiveDate> {
+        common::parse_date(s)
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        assert!(actual.is_some_and(|a| (a - expected).abs() < 1e-9), "{actual:?} != {expected}");
+    }
+
+    #[test]
+    fn one_rep_max_formulas() {
+        assert_close(Formula::Epley.estimate(100, 10), 133.33333333333334);
+        assert_close(Formula::Brzycki.estimate(100, 10), 133.33333333333334);
+        assert_close(Formula::Lombardi.estimate(100, 10), 125.89254117941673);
+
+        for formula in [Formula::Epley, Formula::Brzycki, Formula::Lombardi] {
+            assert_eq!(formula.estimate(100, 1), Some(100.0));
+            assert_eq!(formula.estimate(100, 0), None);
+        }
+
+        assert_close(Formula::Brzycki.estimate(100, 36), 3600.0);
+        assert_eq!(Formula::Brzycki.estimate(100, 37), None);
+        assert_eq!(Formula::Brzycki.estimate(100, u32::MAX), None);
+    }
+
+    #[test]
+    fn exercise_totals_and_trend() {
+        let json = user_json(&[
+            (THURSDAY, "biceps-curl", vec![curl(10, 40), curl(8, 50)]),
+            (THURSDAY, "chest-press", vec![curl(10, 100)]),
+            (MONDAY, "biceps-curl", vec![curl(5, 60), curl(0, 80)]),
+            (NOT_STARTED, "biceps-curl", vec![curl(10, 100)]),
+        ]);
+        let user = serde_json::from_str::<common::User>(&json).unwrap();
+        let fields = ["repetitions", "resistance"];
+
+        let stats = exercise(&user, "biceps-curl", &fields, Formula::Epley, None, None);
+        let totals = stats.workouts.iter()
+            .map(|w| (w.workout_id, w.date, w.sets, w.repetitions, w.tonnage))
+            .collect::<Vec<_>>();
+
+        assert_eq!(totals, [
+            (THURSDAY, "2026-10-01", 2, Some(18), Some(800)),
+            (MONDAY, "2026-10-05", 2, Some(5), Some(300)),
+        ]);
+
+        // The best of 40 × (1 + 10/30) and 50 × (1 + 8/30), then 60 × (1 + 5/30)
+        // because a set without repetitions has no estimate.
+
+        assert_close(stats.workouts[0].one_rep_max, 63.333333333333336);
+        assert_close(stats.workouts[1].one_rep_max, 70.0);
+        assert_close(stats.trend.tonnage_per_week, -875.0);
+        assert_close(stats.trend.one_rep_max_per_week, (70.0 - 63.333333333333336) / 4.0 * 7.0);
+
+        let stats = exercise(&user, "biceps-curl", &fields, Formula::Epley, date("2026-10-02"), None);
+        assert_eq!(stats.workouts.len(), 1);
+        assert_eq!(stats.workouts[0].workout_id, MONDAY);
+        assert_eq!(stats.trend.tonnage_per_week, None);
+
+        // Types that don't record resistance only have sets and repetitions.
+
+        let stats = exercise(&user, "biceps-curl", &["repetitions"], Formula::Epley, None, None);
+        assert_eq!(stats.workouts[0].repetitions, Some(18));
+        assert_eq!(stats.workouts[0].tonnage, None);
+        assert_eq!(stats.workouts[0].one_rep_max, None);
+    }
+
+    #[test]
+    fn exercise_totals_saturate() {
+        let json = user_json(&[
+            (THURSDAY, "biceps-curl", vec![curl(u32::MAX, u32::MAX), curl(u32::MAX, u32::MAX)]),
+        ]);
+        let user = serde_json::from_str::<common::User>(&json).unwrap();
+
+        let stats = exercise(&user, "biceps-curl", &["repetitions", "resistance"], Formula::Epley, None, None);
+
+        assert_eq!(stats.workouts[0].repetitions, Some(2 * u32::MAX as u64));
+        assert_eq!(stats.workouts[0].tonnage, Some(u64::MAX));
+    }
+
+    #[test]
+    fn cardio_distance_falls_back_to_speed_and_duration() {
+        let json = user_json(&[
+            (THURSDAY, "treadmill", vec![
+                // 12 km/h for 10 minutes without a distance is 2 km.
+                json!({ "resistance": 0, "speed": 12, "distance": 0, "duration": 600 }),
+                // A distance without a duration isn't paced.
+                json!({ "resistance": 0, "speed": 12, "distance": 1500, "duration": 0 }),
+            ]),
+            (THURSDAY, "biceps-curl", vec![curl(10, 40)]),
+            (MONDAY, "upright-bike", vec![
+                json!({ "resistance": 5, "distance": 5000, "duration": 900 }),
+            ]),
+            (NOT_STARTED, "upright-bike", vec![
+                json!({ "resistance": 5, "distance": 5000, "duration": 900 }),
+            ]),
+        ]);
+        let user = serde_json::from_str::<common::User>(&json).unwrap();
+
+        let stats = cardio(&user, None, None);
+
+        assert_eq!((stats.total.sets, stats.total.distance, stats.total.duration), (3, 8500, 1500));
+        assert_close(stats.total.pace, 1500.0 * 1000.0 / 7000.0);
+        assert_close(stats.total.speed, 7000.0 * 3.6 / 1500.0);
+        assert_eq!(stats.machines.keys().copied().collect::<Vec<_>>(), ["treadmill", "upright-bike"]);
+        assert_eq!(stats.machines["treadmill"].distance, 3500);
+        assert_close(stats.machines["treadmill"].speed, 12.0);
+
+        // Weeks start on Monday.
+
+        let weeks = stats.weeks.iter().map(|w| (w.start.as_str(), w.totals.distance)).collect::<Vec<_>>();
+        assert_eq!(weeks, [("2026-09-28", 3500), ("2026-10-05", 5000)]);
+
+        let months = stats.months.iter().map(|m| (m.start.as_str(), m.totals.sets)).collect::<Vec<_>>();
+        assert_eq!(months, [("2026-10-01", 3)]);
+
+        let stats = cardio(&user, None, date("2026-10-04"));
+        assert_eq!(stats.total.distance, 3500);
+        assert!(!stats.machines.contains_key("upright-bike"));
+    }
+}
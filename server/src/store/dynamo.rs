@@ -0,0 +1,494 @@
+use aws_sdk_dynamodb::{
+    Client,
+    error::SdkError,
+    operation::{
+        transact_write_items::TransactWriteItemsError,
+        update_item::UpdateItemError,
+    },
+    types::{
+        AttributeValue,
+        CancellationReason,
+        ConditionCheck,
+        DeleteRequest,
+        Put,
+        PutRequest,
+        ReturnValue,
+        ReturnValuesOnConditionCheckFailure,
+        Select,
+        TransactWriteItem,
+        Update,
+        WriteRequest,
+    },
+};
+use tokio_stream::StreamExt;
+use crate::common::{self, DynamoDbItem};
+use super::{BatchWrite, Error, LockOutcome, TransactItem, UserStore, WriteOutcome};
+
+pub const TABLE_USER: &str = "gym-log.User";
+pub const INDEX_MODIFIED_VERSION: &str = "LSI-ModifiedVersion";
+
+pub struct DynamoStore {
+    client: Client,
+}
+
+impl DynamoStore {
+    pub async fn from_env() -> Self {
+        let config = aws_config::load_from_env().await;
+        Self { client: Client::new(&config) }
+    }
+
+    async fn get_version_item(&self, user_id: &str) -> Result<Option<DynamoDbItem>, Error> {
+        self.get_item(user_id, "VERSION").await
+    }
+}
+
+impl<E, R> From<SdkError<E, R>> for Error
+    where SdkError<E, R>: std::error::Error + Send + Sync + 'static
+{
+    fn from(e: SdkError<E, R>) -> Self {
+        Self::new(e)
+    }
+}
+
+impl UserStore for DynamoStore {
+    async fn read_version(&self, user_id: &str) -> Result<u64, Error> {
+        Ok(self.get_version_item(user_id).await?
+            .map_or(0, |i| common::as_number(&i["Version"])))
+    }
+
+    async fn get_item(&self, user_id: &str, id: &str) -> Result<Option<DynamoDbItem>, Error> {
+        let get_item = self.client.get_item()
+            .table_name(TABLE_USER)
+            .key("UserId", AttributeValue::S(user_id.into()))
+            .key("Id", AttributeValue::S(id.into()))
+            .send()
+            .await?;
+
+        Ok(get_item.item().cloned())
+    }
+
+    async fn query_changed_since(
+        &self,
+        user_id: &str,
+        version: u64,
+    ) -> Result<Vec<DynamoDbItem>, Error> {
+        // There's an LSI on the ModifiedVersion but the index only includes the
+        // keys as to avoid slowing down writes too much.
+
+        Ok(self.client.query()
+            .table_name(TABLE_USER)
+            .index_name(INDEX_MODIFIED_VERSION)
+            .key_condition_expression("UserId = :userId AND ModifiedVersion > :clientVersion")
+            .expression_attribute_values(":userId", AttributeValue::S(user_id.into()))
+            .expression_attribute_values(
+                ":clientVersion",
+                AttributeValue::N(version.to_string()),
+            )
+            .select(Select::AllAttributes)
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?)
+    }
+
+    async fn query_collection(
+        &self,
+        user_id: &str,
+        collection: u32,
+        include_deleted: bool,
+    ) -> Result<Vec<DynamoDbItem>, Error> {
+        let mut query = self.client.query()
+            .table_name(TABLE_USER)
+            .key_condition_expression("UserId = :userId AND begins_with(Id, :collection)")
+            .expression_attribute_values(":userId", AttributeValue::S(user_id.into()))
+            .expression_attribute_values(
+                ":collection",
+                AttributeValue::S(common::get_collection_prefix(collection)),
+            )
+            .select(Select::AllAttributes);
+
+        if !include_deleted {
+            query = query.filter_expression("attribute_not_exists(Deleted)");
+        }
+
+        Ok(query
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?)
+    }
+
+    async fn query_prefix(&self, user_id: &str, prefix: &str) -> Result<Vec<DynamoDbItem>, Error> {
+        Ok(self.client.query()
+            .table_name(TABLE_USER)
+            .key_condition_expression("UserId = :userId AND begins_with(Id, :prefix)")
+            .expression_attribute_values(":userId", AttributeValue::S(user_id.into()))
+            .expression_attribute_values(":prefix", AttributeValue::S(prefix.into()))
+            .select(Select::AllAttributes)
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?)
+    }
+
+    async fn query_ids(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        let items = self.client.query()
+            .table_name(TABLE_USER)
+            .key_condition_expression("UserId = :userId")
+            .expression_attribute_values(":userId", AttributeValue::S(user_id.into()))
+            .projection_expression("Id")
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+
+        Ok(items.into_iter()
+            .filter_map(|mut item| match item.remove("Id") {
+                Some(AttributeValue::S(id)) if id != "VERSION" => Some(id),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn scan_user_ids(&self) -> Result<Vec<String>, Error> {
+        let items = self.client.scan()
+            .table_name(TABLE_USER)
+            .filter_expression("Id = :version")
+            .expression_attribute_values(":version", AttributeValue::S("VERSION".into()))
+            .projection_expression("UserId")
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+
+        Ok(items.into_iter()
+            .filter_map(|mut item| match item.remove("UserId") {
+                Some(AttributeValue::S(user_id)) => Some(user_id),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn versioned_write(
+        &self,
+        user_id: &str,
+        client_version: u64,
+        items: Vec<TransactItem>,
+    ) -> Result<WriteOutcome, Error> {
+        let new_version = client_version + 1;
+        let now = common::now();
+
+        let mut builder = self.client.transact_write_items()
+            .transact_items(TransactWriteItem::builder()
+                .update(Update::builder()
+                    .table_name(TABLE_USER)
+                    .key("UserId", AttributeValue::S(user_id.into()))
+                    .key("Id", AttributeValue::S("VERSION".into()))
+                    .expression_attribute_values(":clientVersion", AttributeValue::N(client_version.to_string()))
+                    .expression_attribute_values(":newVersion", AttributeValue::N(new_version.to_string()))
+                    .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+                    .condition_expression(
+                        "(attribute_not_exists(Version) OR Version = :clientVersion) \
+                        AND (attribute_not_exists(LockedUntil) OR LockedUntil <= :now)"
+                    )
+                    .update_expression("SET Version = :newVersion")
+                    .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+                    .build())
+                .build());
+
+        for item in items {
+            builder = builder.transact_items(make_transact_item(user_id, item));
+        }
+
+        let e = match builder.send().await {
+            Ok(_) => return Ok(WriteOutcome::Written),
+            Err(e) => e,
+        };
+
+        let Some(reasons) = transact_write_cancellation_reasons(&e) else {
+            return Err(e.into());
+        };
+
+        if is_condition_failed(&reasons[0]) {
+            if let Some(item) = reasons[0].item() {
+                let old_version: u64 = item.get("Version").map_or(0, common::as_number);
+                if old_version != client_version {
+                    return Ok(WriteOutcome::Conflict { version: old_version });
+                }
+
+                let locked_until: u64 = item.get("LockedUntil").map_or(0, common::as_number);
+                if locked_until > now {
+                    return Ok(WriteOutcome::Locked { until: locked_until });
+                }
+            }
+        }
+
+        let failed = reasons[1..].iter()
+            .map(is_condition_failed)
+            .collect::<Vec<_>>();
+
+        if failed.iter().any(|f| *f) {
+            Ok(WriteOutcome::Canceled(failed))
+        } else {
+            Err(e.into())
+        }
+    }
+
+    async fn acquire_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        until: u64,
+    ) -> Result<LockOutcome, Error> {
+        // Unfortunately, there doesn't seem to be a way to do a conditional
+        // update and get the item if the condition is true or false. With an
+        // UpdateItem, you can get the item if it's true but not false. With
+        // TransactWriteItems, it's the reverse. In either case, you can't have
+        // both. It's a very frustrating limitation.
+        //
+        // If we do an UpdateItem, then we'll need to do a GetItem if it fails.
+        // In that case, LockedUntil might have changed since we failed to
+        // acquire the lock. That doesn't really matter though. A
+        // TransactWriteItems would be slower. We're optimizing the common case.
+
+        let acquire_lock_result = self.client.update_item()
+            .table_name(TABLE_USER)
+            .key("UserId", AttributeValue::S(user_id.into()))
+            .key("Id", AttributeValue::S("VERSION".into()))
+            .update_expression("SET LockedUntil = :lockExpire, LockToken = :token")
+            .condition_expression(
+                "attribute_not_exists(LockedUntil) OR LockedUntil <= :now"
+            )
+            .expression_attribute_values(":lockExpire", AttributeValue::N(until.to_string()))
+            .expression_attribute_values(":token", AttributeValue::S(token.into()))
+            .expression_attribute_values(":now", AttributeValue::N(common::now().to_string()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await;
+
+        match acquire_lock_result {
+            Ok(o) => Ok(LockOutcome::Acquired {
+                version: o.attributes()
+                    .and_then(|i| i.get("Version"))
+                    .map_or(0, common::as_number),
+            }),
+            Err(e) => {
+                if let SdkError::ServiceError(service_error) = &e {
+                    if let UpdateItemError::ConditionalCheckFailedException(_) = &service_error.err() {
+                        // If the conditional expression is false, then
+                        // LockedUntil is in the future but the item could have
+                        // changed since then so we can't assume anything about
+                        // it.
+
+                        let until = self.get_version_item(user_id).await?
+                            .and_then(|i| i.get("LockedUntil").map(common::as_number))
+                            .unwrap_or(0);
+
+                        return Ok(LockOutcome::Held { until });
+                    }
+                }
+
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn extend_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        until: u64,
+    ) -> Result<bool, Error> {
+        let extend_lock_result = self.client.update_item()
+            .table_name(TABLE_USER)
+            .key("UserId", AttributeValue::S(user_id.into()))
+            .key("Id", AttributeValue::S("VERSION".into()))
+            .update_expression("SET LockedUntil = :lockExpire")
+            .condition_expression("LockToken = :token AND LockedUntil > :now")
+            .expression_attribute_values(":lockExpire", AttributeValue::N(until.to_string()))
+            .expression_attribute_values(":token", AttributeValue::S(token.into()))
+            .expression_attribute_values(":now", AttributeValue::N(common::now().to_string()))
+            .send()
+            .await;
+
+        is_lock_update_applied(extend_lock_result)
+    }
+
+    async fn release_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        new_version: u64,
+    ) -> Result<bool, Error> {
+        let release_lock_result = self.client.update_item()
+            .table_name(TABLE_USER)
+            .key("UserId", AttributeValue::S(user_id.into()))
+            .key("Id", AttributeValue::S("VERSION".into()))
+            .update_expression("REMOVE LockedUntil, LockToken SET Version = :version")
+            .condition_expression("LockToken = :token AND LockedUntil > :now")
+            .expression_attribute_values(":version", AttributeValue::N(new_version.to_string()))
+            .expression_attribute_values(":token", AttributeValue::S(token.into()))
+            .expression_attribute_values(":now", AttributeValue::N(common::now().to_string()))
+            .send()
+            .await;
+
+        is_lock_update_applied(release_lock_result)
+    }
+
+    async fn batch_write(
+        &self,
+        user_id: &str,
+        requests: Vec<BatchWrite>,
+    ) -> Result<(), Error> {
+        const MAX_BATCH_SIZE: usize = 25;
+
+        let requests = requests.into_iter()
+            .map(|r| make_write_request(user_id, r))
+            .collect::<Vec<_>>();
+        let mut unprocessed = Vec::new();
+        let mut processed = 0;
+
+        while !unprocessed.is_empty() || processed < requests.len() {
+            let mut batch;
+            let remaining = requests.len() - processed;
+
+            if !unprocessed.is_empty() {
+                batch = unprocessed;
+                let processing = remaining.min(MAX_BATCH_SIZE - batch.len());
+                batch.extend_from_slice(&requests[processed..processed + processing]);
+                processed += processing;
+            } else {
+                let processing = remaining.min(MAX_BATCH_SIZE);
+                batch = Vec::from(&requests[processed..processed + processing]);
+                processed += processing;
+            }
+
+            let batch_write = self.client.batch_write_item()
+                .request_items(TABLE_USER, batch)
+                .send()
+                .await?;
+
+            unprocessed = batch_write.unprocessed_items()
+                .and_then(|map| map.get(TABLE_USER))
+                .cloned()
+                .unwrap_or_default();
+        }
+
+        Ok(())
+    }
+}
+
+fn make_transact_item(user_id: &str, item: TransactItem) -> TransactWriteItem {
+    let builder = TransactWriteItem::builder();
+
+    match item {
+        TransactItem::Put { id, mut item } => {
+            item.insert("UserId".into(), AttributeValue::S(user_id.into()));
+            item.insert("Id".into(), AttributeValue::S(id));
+
+            builder.put(Put::builder()
+                .table_name(TABLE_USER)
+                .set_item(Some(item))
+                .build())
+        }
+        TransactItem::Replace { id, mut item } => {
+            item.insert("UserId".into(), AttributeValue::S(user_id.into()));
+            item.insert("Id".into(), AttributeValue::S(id));
+
+            builder.put(Put::builder()
+                .table_name(TABLE_USER)
+                .set_item(Some(item))
+                .condition_expression("attribute_exists(UserId) AND attribute_not_exists(Deleted)")
+                .build())
+        }
+        TransactItem::Delete { id, new_version } => {
+            builder.update(Update::builder()
+                .table_name(TABLE_USER)
+                .key("UserId", AttributeValue::S(user_id.into()))
+                .key("Id", AttributeValue::S(id))
+                .expression_attribute_values(":newVersion", AttributeValue::N(new_version.to_string()))
+                .expression_attribute_values(":deleted", AttributeValue::Bool(true))
+                .condition_expression("attribute_exists(UserId) AND attribute_not_exists(Deleted)")
+                .update_expression("SET ModifiedVersion = :newVersion, Deleted = :deleted")
+                .build())
+        }
+        TransactItem::CheckExists { id } => {
+            builder.condition_check(ConditionCheck::builder()
+                .table_name(TABLE_USER)
+                .key("UserId", AttributeValue::S(user_id.into()))
+                .key("Id", AttributeValue::S(id))
+                .condition_expression("attribute_exists(UserId) AND attribute_not_exists(Deleted)")
+                .build())
+        }
+        TransactItem::SetOrder { id, order, new_version } => {
+            builder.update(Update::builder()
+                .table_name(TABLE_USER)
+                .key("UserId", AttributeValue::S(user_id.into()))
+                .key("Id", AttributeValue::S(id))
+                .expression_attribute_names("#order", "Order")
+                .expression_attribute_values(":order", AttributeValue::N(order.to_string()))
+                .expression_attribute_values(":newVersion", AttributeValue::N(new_version.to_string()))
+                .condition_expression("attribute_exists(UserId) AND attribute_not_exists(Deleted)")
+                .update_expression("SET #order = :order, ModifiedVersion = :newVersion")
+                .build())
+        }
+    }.build()
+}
+
+fn make_write_request(user_id: &str, request: BatchWrite) -> WriteRequest {
+    match request {
+        BatchWrite::Put { id, mut item } => {
+            item.insert("UserId".into(), AttributeValue::S(user_id.into()));
+            item.insert("Id".into(), AttributeValue::S(id));
+
+            WriteRequest::builder()
+                .put_request(PutRequest::builder()
+                    .set_item(Some(item))
+                    .build())
+                .build()
+        }
+        BatchWrite::Delete { id } => {
+            WriteRequest::builder()
+                .delete_request(DeleteRequest::builder()
+                    .key("UserId", AttributeValue::S(user_id.into()))
+                    .key("Id", AttributeValue::S(id))
+                    .build())
+                .build()
+        }
+    }
+}
+
+fn transact_write_cancellation_reasons(
+    error: &SdkError<TransactWriteItemsError>,
+) -> Option<&[CancellationReason]> {
+    if let SdkError::ServiceError(service_error) = error {
+        if let TransactWriteItemsError::TransactionCanceledException(cancel) = &service_error.err() {
+            return cancel.cancellation_reasons();
+        }
+    }
+    None
+}
+
+fn is_condition_failed(reason: &CancellationReason) -> bool {
+    reason.code() == Some("ConditionalCheckFailed")
+}
+
+/// Whether a conditional update of the lock was applied. A failed condition
+/// means that the lock isn't held with the token.
+fn is_lock_update_applied<O>(
+    result: Result<O, SdkError<UpdateItemError, impl std::fmt::Debug + Send + Sync + 'static>>,
+) -> Result<bool, Error> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(SdkError::ServiceError(service_error))
+            if matches!(service_error.err(), UpdateItemError::ConditionalCheckFailedException(_)) => {
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,573 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::Mutex};
+use aws_sdk_dynamodb::types::AttributeValue;
+use crate::common::{self, DynamoDbItem};
+use super::{BatchWrite, Error, LockOutcome, MAX_TRANSACT_ITEMS, TransactItem, UserStore, WriteOutcome};
+
+// An in-memory store that emulates the behaviour of DynamoDB closely enough
+// that the sync and import protocols can be run without a network. Items are
+// stored exactly as DynamoDB would store them (including the UserId and Id
+// attributes) and are returned in the same order that DynamoDB would return
+// them. The limits on transactions and batches are also enforced so that
+// something that works here will also work against the real table.
+
+const MAX_BATCH_SIZE: usize = 25;
+
+type Partition = BTreeMap<String, DynamoDbItem>;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    users: Mutex<HashMap<String, Partition>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_partition<R>(&self, user_id: &str, f: impl FnOnce(&mut Partition) -> R) -> R {
+        let mut users = self.users.lock().unwrap();
+        f(users.entry(user_id.to_owned()).or_default())
+    }
+}
+
+impl UserStore for MemoryStore {
+    async fn read_version(&self, user_id: &str) -> Result<u64, Error> {
+        Ok(self.with_partition(user_id, |partition| {
+            get_number(partition.get("VERSION"), "Version")
+        }))
+    }
+
+    async fn get_item(&self, user_id: &str, id: &str) -> Result<Option<DynamoDbItem>, Error> {
+        Ok(self.with_partition(user_id, |partition| partition.get(id).cloned()))
+    }
+
+    async fn query_changed_since(
+        &self,
+        user_id: &str,
+        version: u64,
+    ) -> Result<Vec<DynamoDbItem>, Error> {
+        // Items without a ModifiedVersion (the VERSION item) aren't in the
+        // index. The index is sorted by ModifiedVersion and then by the primary
+        // key.
+
+        Ok(self.with_partition(user_id, |partition| {
+            let mut items = partition.values()
+                .filter(|item| item.contains_key("ModifiedVersion"))
+                .filter(|item| get_number(Some(item), "ModifiedVersion") > version)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            items.sort_by_key(|item| get_number(Some(item), "ModifiedVersion"));
+            items
+        }))
+    }
+
+    async fn query_collection(
+        &self,
+        user_id: &str,
+        collection: u32,
+        include_deleted: bool,
+    ) -> Result<Vec<DynamoDbItem>, Error> {
+        let prefix = common::get_collection_prefix(collection);
+
+        Ok(self.with_partition(user_id, |partition| {
+            partition.range(prefix.clone()..)
+                .take_while(|(id, _)| id.starts_with(&prefix))
+                .filter(|(_, item)| include_deleted || !item.contains_key("Deleted"))
+                .map(|(_, item)| item.clone())
+                .collect()
+        }))
+    }
+
+    async fn query_prefix(&self, user_id: &str, prefix: &str) -> Result<Vec<DynamoDbItem>, Error> {
+        Ok(self.with_partition(user_id, |partition| {
+            partition.range(prefix.to_owned()..)
+                .take_while(|(id, _)| id.starts_with(prefix))
+                .map(|(_, item)| item.clone())
+                .collect()
+        }))
+    }
+
+    async fn query_ids(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        Ok(self.with_partition(user_id, |partition| {
+            partition.keys()
+                .filter(|id| *id != "VERSION")
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn scan_user_ids(&self) -> Result<Vec<String>, Error> {
+        Ok(self.users.lock().unwrap().iter()
+            .filter(|(_, partition)| partition.contains_key("VERSION"))
+            .map(|(user_id, _)| user_id.clone())
+            .collect())
+    }
+
+    async fn versioned_write(
+        &self,
+        user_id: &str,
+        client_version: u64,
+        items: Vec<TransactItem>,
+    ) -> Result<WriteOutcome, Error> {
+        if items.len() + 1 > MAX_TRANSACT_ITEMS {
+            return Err(Error::new(format!(
+                "transaction has {} items but the limit is {MAX_TRANSACT_ITEMS}",
+                items.len() + 1,
+            )));
+        }
+
+        let mut ids = HashSet::new();
+        for item in items.iter() {
+            if !ids.insert(transact_item_id(item)) {
+                return Err(Error::new("transaction contains multiple operations on one item"));
+            }
+        }
+
+        let now = common::now();
+
+        self.with_partition(user_id, |partition| {
+            let version_item = partition.get("VERSION");
+            let has_version = version_item.is_some_and(|i| i.contains_key("Version"));
+            let old_version = get_number(version_item, "Version");
+            let locked_until = get_number(version_item, "LockedUntil");
+
+            if has_version && old_version != client_version {
+                return Ok(WriteOutcome::Conflict { version: old_version });
+            }
+
+            if locked_until > now {
+                return Ok(WriteOutcome::Locked { until: locked_until });
+            }
+
+            let failed = items.iter()
+                .map(|item| match item {
+                    TransactItem::Put { .. } => false,
+                    TransactItem::Replace { id, .. }
+                    | TransactItem::Delete { id, .. }
+                    | TransactItem::CheckExists { id }
+                    | TransactItem::SetOrder { id, .. } => !exists(partition, id),
+                })
+                .collect::<Vec<_>>();
+
+            if failed.iter().any(|f| *f) {
+                return Ok(WriteOutcome::Canceled(failed));
+            }
+
+            set_number(version_item_mut(partition, user_id), "Version", client_version + 1);
+
+            for item in items {
+                match item {
+                    TransactItem::Put { id, mut item }
+                    | TransactItem::Replace { id, mut item } => {
+                        insert_keys(&mut item, user_id, &id);
+                        partition.insert(id, item);
+                    }
+                    TransactItem::Delete { id, new_version } => {
+                        let item = partition.get_mut(&id).unwrap();
+                        set_number(item, "ModifiedVersion", new_version);
+                        item.insert("Deleted".into(), AttributeValue::Bool(true));
+                    }
+                    TransactItem::CheckExists { .. } => {}
+                    TransactItem::SetOrder { id, order, new_version } => {
+                        let item = partition.get_mut(&id).unwrap();
+                        set_number(item, "Order", order as u64);
+                        set_number(item, "ModifiedVersion", new_version);
+                    }
+                }
+            }
+
+            Ok(WriteOutcome::Written)
+        })
+    }
+
+    async fn acquire_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        until: u64,
+    ) -> Result<LockOutcome, Error> {
+        let now = common::now();
+
+        Ok(self.with_partition(user_id, |partition| {
+            let locked_until = get_number(partition.get("VERSION"), "LockedUntil");
+
+            if locked_until > now {
+                return LockOutcome::Held { until: locked_until };
+            }
+
+            let version = get_number(partition.get("VERSION"), "Version");
+            let item = version_item_mut(partition, user_id);
+            set_number(item, "LockedUntil", until);
+            item.insert("LockToken".into(), AttributeValue::S(token.into()));
+
+            LockOutcome::Acquired { version }
+        }))
+    }
+
+    async fn extend_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        until: u64,
+    ) -> Result<bool, Error> {
+        let now = common::now();
+
+        Ok(self.with_partition(user_id, |partition| {
+            if !holds_lock(partition.get("VERSION"), token, now) {
+                return false;
+            }
+
+            set_number(version_item_mut(partition, user_id), "LockedUntil", until);
+
+            true
+        }))
+    }
+
+    async fn release_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        new_version: u64,
+    ) -> Result<bool, Error> {
+        let now = common::now();
+
+        Ok(self.with_partition(user_id, |partition| {
+            if !holds_lock(partition.get("VERSION"), token, now) {
+                return false;
+            }
+
+            let item = version_item_mut(partition, user_id);
+            item.remove("LockedUntil");
+            item.remove("LockToken");
+            set_number(item, "Version", new_version);
+
+            true
+        }))
+    }
+
+    async fn batch_write(
+        &self,
+        user_id: &str,
+        requests: Vec<BatchWrite>,
+    ) -> Result<(), Error> {
+        // This follows the same algorithm as the DynamoDB store. Unprocessed
+        // requests from the previous batch are retried at the front of the
+        // next batch.
+
+        let mut requests = requests.into_iter().peekable();
+        let mut unprocessed = Vec::new();
+
+        while !unprocessed.is_empty() || requests.peek().is_some() {
+            let mut batch = std::mem::take(&mut unprocessed);
+
+            while batch.len() < MAX_BATCH_SIZE {
+                match requests.next() {
+                    Some(r) => batch.push(r),
+                    None => break,
+                }
+            }
+
+            let mut ids = HashSet::new();
+            for request in batch.iter() {
+                let id = match request {
+                    BatchWrite::Put { id, .. } | BatchWrite::Delete { id } => id,
+                };
+                if !ids.insert(id.clone()) {
+                    return Err(Error::new("batch contains multiple operations on one item"));
+                }
+            }
+
+            // DynamoDB may leave some requests unprocessed if the table is
+            // being throttled. We always leave the last request in a full batch
+            // unprocessed so that the retry path is exercised.
+            if batch.len() == MAX_BATCH_SIZE {
+                unprocessed = batch.split_off(MAX_BATCH_SIZE - 1);
+            }
+
+            self.with_partition(user_id, |partition| {
+                for request in batch {
+                    match request {
+                        BatchWrite::Put { id, mut item } => {
+                            insert_keys(&mut item, user_id, &id);
+                            partition.insert(id, item);
+                        }
+                        BatchWrite::Delete { id } => {
+                            partition.remove(&id);
+                        }
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn transact_item_id(item: &TransactItem) -> &str {
+    match item {
+        TransactItem::Put { id, .. }
+        | TransactItem::Replace { id, .. }
+        | TransactItem::Delete { id, .. }
+        | TransactItem::CheckExists { id }
+        | TransactItem::SetOrder { id, .. } => id,
+    }
+}
+
+fn exists(partition: &Partition, id: &str) -> bool {
+    partition.get(id).is_some_and(|item| !item.contains_key("Deleted"))
+}
+
+/// Whether the lock is held with the token and hasn't expired.
+fn holds_lock(version_item: Option<&DynamoDbItem>, token: &str, now: u64) -> bool {
+    get_number(version_item, "LockedUntil") > now
+        && version_item.and_then(|i| i.get("LockToken")).is_some_and(|t| t.as_s().is_ok_and(|t| t == token))
+}
+
+fn get_number(item: Option<&DynamoDbItem>, name: &str) -> u64 {
+    item.and_then(|i| i.get(name)).map_or(0, common::as_number)
+}
+
+fn set_number(item: &mut DynamoDbItem, name: &str, value: u64) {
+    item.insert(name.into(), AttributeValue::N(value.to_string()));
+}
+
+fn insert_keys(item: &mut DynamoDbItem, user_id: &str, id: &str) {
+    item.insert("UserId".into(), AttributeValue::S(user_id.into()));
+    item.insert("Id".into(), AttributeValue::S(id.into()));
+}
+
+fn version_item_mut<'a>(partition: &'a mut Partition, user_id: &str) -> &'a mut DynamoDbItem {
+    partition.entry("VERSION".into()).or_insert_with(|| {
+        let mut item = HashMap::new();
+        insert_keys(&mut item, user_id, "VERSION");
+        item
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_ID: &str = "user";
+
+    fn item(modified_version: u64) -> DynamoDbItem {
+        let mut item = HashMap::new();
+        set_number(&mut item, "ModifiedVersion", modified_version);
+        item
+    }
+
+    fn put(id: &str, modified_version: u64) -> TransactItem {
+        TransactItem::Put { id: id.into(), item: item(modified_version) }
+    }
+
+    fn ids(items: &[DynamoDbItem]) -> Vec<&str> {
+        items.iter().map(|i| i["Id"].as_s().unwrap().as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn versioned_write_increments_version() {
+        let store = MemoryStore::new();
+
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 0);
+        assert!(matches!(
+            store.versioned_write(USER_ID, 0, vec![put("a", 1)]).await.unwrap(),
+            WriteOutcome::Written,
+        ));
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 1);
+        assert!(store.get_item(USER_ID, "a").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn versioned_write_conflicts_with_other_version() {
+        let store = MemoryStore::new();
+
+        store.versioned_write(USER_ID, 0, vec![put("a", 1)]).await.unwrap();
+
+        assert!(matches!(
+            store.versioned_write(USER_ID, 0, vec![put("b", 1)]).await.unwrap(),
+            WriteOutcome::Conflict { version: 1 },
+        ));
+        assert!(store.get_item(USER_ID, "b").await.unwrap().is_none());
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn versioned_write_reports_failed_conditions_per_item() {
+        let store = MemoryStore::new();
+
+        store.versioned_write(USER_ID, 0, vec![put("a", 1), put("b", 1)]).await.unwrap();
+        store.versioned_write(USER_ID, 1, vec![
+            TransactItem::Delete { id: "b".into(), new_version: 2 },
+        ]).await.unwrap();
+
+        // The reasons don't include the VERSION item so that they line up with
+        // the items that were passed in.
+
+        let outcome = store.versioned_write(USER_ID, 2, vec![
+            TransactItem::Replace { id: "a".into(), item: item(3) },
+            TransactItem::CheckExists { id: "b".into() },
+            put("c", 3),
+            TransactItem::SetOrder { id: "d".into(), order: 0, new_version: 3 },
+        ]).await.unwrap();
+
+        let WriteOutcome::Canceled(failed) = outcome else {
+            panic!("expected the transaction to be canceled");
+        };
+        assert_eq!(failed, vec![false, true, false, true]);
+
+        // Nothing is written if any condition fails.
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 2);
+        assert!(store.get_item(USER_ID, "c").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn versioned_write_rejects_multiple_operations_on_one_item() {
+        let store = MemoryStore::new();
+
+        let outcome = store.versioned_write(USER_ID, 0, vec![
+            put("a", 1),
+            TransactItem::Delete { id: "a".into(), new_version: 1 },
+        ]).await;
+
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn versioned_write_rejects_too_many_items() {
+        let store = MemoryStore::new();
+        let items = (0..MAX_TRANSACT_ITEMS).map(|i| put(&i.to_string(), 1)).collect();
+
+        assert!(store.versioned_write(USER_ID, 0, items).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn lock_blocks_writes_until_released() {
+        let store = MemoryStore::new();
+        let until = common::now() + 60;
+
+        store.versioned_write(USER_ID, 0, vec![put("a", 1)]).await.unwrap();
+
+        assert!(matches!(
+            store.acquire_import_lock(USER_ID, "first", until).await.unwrap(),
+            LockOutcome::Acquired { version: 1 },
+        ));
+        assert!(matches!(
+            store.acquire_import_lock(USER_ID, "second", until).await.unwrap(),
+            LockOutcome::Held { until: u } if u == until,
+        ));
+        assert!(matches!(
+            store.versioned_write(USER_ID, 1, vec![put("b", 2)]).await.unwrap(),
+            WriteOutcome::Locked { until: u } if u == until,
+        ));
+
+        assert!(store.extend_import_lock(USER_ID, "first", until + 60).await.unwrap());
+        assert!(store.release_import_lock(USER_ID, "first", 5).await.unwrap());
+
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 5);
+        assert!(matches!(
+            store.versioned_write(USER_ID, 5, vec![put("b", 6)]).await.unwrap(),
+            WriteOutcome::Written,
+        ));
+    }
+
+    #[tokio::test]
+    async fn lock_requires_its_token() {
+        let store = MemoryStore::new();
+        let until = common::now() + 60;
+
+        store.acquire_import_lock(USER_ID, "first", until).await.unwrap();
+
+        assert!(!store.extend_import_lock(USER_ID, "second", until + 60).await.unwrap());
+        assert!(!store.release_import_lock(USER_ID, "second", 5).await.unwrap());
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 0);
+        assert!(matches!(
+            store.acquire_import_lock(USER_ID, "second", until).await.unwrap(),
+            LockOutcome::Held { .. },
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_lock_can_be_acquired_by_someone_else() {
+        let store = MemoryStore::new();
+        let expired = common::now();
+
+        store.acquire_import_lock(USER_ID, "first", expired).await.unwrap();
+
+        // Writes are allowed once the lock expires so the holder can no longer
+        // extend or release it.
+
+        assert!(matches!(
+            store.versioned_write(USER_ID, 0, vec![put("a", 1)]).await.unwrap(),
+            WriteOutcome::Written,
+        ));
+        assert!(!store.extend_import_lock(USER_ID, "first", expired + 60).await.unwrap());
+        assert!(!store.release_import_lock(USER_ID, "first", 0).await.unwrap());
+        assert_eq!(store.read_version(USER_ID).await.unwrap(), 1);
+
+        assert!(matches!(
+            store.acquire_import_lock(USER_ID, "second", expired + 60).await.unwrap(),
+            LockOutcome::Acquired { version: 1 },
+        ));
+        assert!(!store.extend_import_lock(USER_ID, "first", expired + 60).await.unwrap());
+        assert!(store.release_import_lock(USER_ID, "second", 2).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn query_changed_since_orders_by_modified_version() {
+        let store = MemoryStore::new();
+
+        store.versioned_write(USER_ID, 0, vec![put("b", 1), put("c", 1)]).await.unwrap();
+        store.versioned_write(USER_ID, 1, vec![put("a", 2)]).await.unwrap();
+        store.versioned_write(USER_ID, 2, vec![
+            TransactItem::Delete { id: "c".into(), new_version: 3 },
+            TransactItem::Put { id: "NO_VERSION".into(), item: HashMap::new() },
+        ]).await.unwrap();
+
+        let changed = store.query_changed_since(USER_ID, 0).await.unwrap();
+        assert_eq!(ids(&changed), vec!["b", "a", "c"]);
+
+        let changed = store.query_changed_since(USER_ID, 1).await.unwrap();
+        assert_eq!(ids(&changed), vec!["a", "c"]);
+        assert!(changed[1].contains_key("Deleted"));
+
+        assert!(store.query_changed_since(USER_ID, 3).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn batch_write_retries_unprocessed_requests() {
+        let store = MemoryStore::new();
+
+        // Full batches always leave a request unprocessed so every one of these
+        // batches has a request retried from the one before.
+
+        let requests = (0..MAX_BATCH_SIZE * 3)
+            .map(|i| BatchWrite::Put { id: format!("{i:03}"), item: item(1) })
+            .collect();
+
+        store.batch_write(USER_ID, requests).await.unwrap();
+
+        let written = store.query_ids(USER_ID).await.unwrap();
+        assert_eq!(written.len(), MAX_BATCH_SIZE * 3);
+
+        let requests = (0..MAX_BATCH_SIZE * 3)
+            .map(|i| BatchWrite::Delete { id: format!("{i:03}") })
+            .collect();
+
+        store.batch_write(USER_ID, requests).await.unwrap();
+
+        assert!(store.query_ids(USER_ID).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn batch_write_rejects_multiple_operations_on_one_item() {
+        let store = MemoryStore::new();
+
+        let requests = vec![
+            BatchWrite::Put { id: "a".into(), item: item(1) },
+            BatchWrite::Delete { id: "a".into() },
+        ];
+
+        assert!(store.batch_write(USER_ID, requests).await.is_err());
+    }
+}
@@ -0,0 +1,220 @@
+mod dynamo;
+mod memory;
+
+pub use dynamo::*;
+pub use memory::*;
+
+use std::{fmt, future::Future};
+use crate::common::DynamoDbItem;
+
+// The store is everything that the handlers need from the database. All of a
+// user's data lives in a single partition. There is one special item with the
+// ID "VERSION" that holds the current Version of the user's data and, while an
+// import is in progress, a LockedUntil timestamp along with the LockToken of
+// the holder of the lock. The lock can only be extended or released with the
+// token that acquired it so a holder whose lock expired can't interfere with
+// whoever acquired it next. Every other item is an entity keyed by its
+// collection prefix, key prefix and ID. Items are represented as DynamoDB
+// attribute maps regardless of the backing store so that the conversions in
+// db_conv can be shared. The UserId and Id attributes are managed by the store
+// and shouldn't be present in items passed to it.
+
+/// A failure of the store itself, such as the database being unreachable or
+/// rejecting a request. It's kept distinct from other errors so that it can be
+/// reported as such even after being boxed.
+#[derive(Debug)]
+pub struct Error(lambda_http::Error);
+
+impl Error {
+    pub fn new(e: impl Into<lambda_http::Error>) -> Self {
+        Self(e.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// The maximum number of items in a transaction including the VERSION item.
+pub const MAX_TRANSACT_ITEMS: usize = 100;
+
+/// A single operation within a versioned transaction.
+pub enum TransactItem {
+    /// Create or replace the entity with the given ID.
+    Put {
+        id: String,
+        item: DynamoDbItem,
+    },
+    /// Replace an existing entity with the given ID. The condition fails if
+    /// the entity doesn't exist or has been deleted.
+    Replace {
+        id: String,
+        item: DynamoDbItem,
+    },
+    /// Mark an existing entity as deleted. The condition fails if the entity
+    /// doesn't exist or has already been deleted.
+    Delete {
+        id: String,
+        new_version: u64,
+    },
+    /// Check that the entity exists and hasn't been deleted without modifying
+    /// it.
+    CheckExists {
+        id: String,
+    },
+    /// Change the order of an existing exercise. The condition fails if the
+    /// exercise doesn't exist or has been deleted.
+    SetOrder {
+        id: String,
+        order: u32,
+        new_version: u64,
+    },
+}
+
+/// The result of a versioned transaction.
+pub enum WriteOutcome {
+    /// The transaction was applied and the version was incremented.
+    Written,
+    /// The client's version was not equal to the current version.
+    Conflict {
+        version: u64,
+    },
+    /// An import is in progress and writes are not allowed until the lock
+    /// expires.
+    Locked {
+        until: u64,
+    },
+    /// The transaction was canceled because one or more conditions failed.
+    /// There is one element for each item in the transaction that is true if
+    /// the condition on that item failed.
+    Canceled(Vec<bool>),
+}
+
+/// The result of trying to acquire the import lock.
+pub enum LockOutcome {
+    /// The lock was acquired. The version is the version at the time that the
+    /// lock was acquired.
+    Acquired {
+        version: u64,
+    },
+    /// The lock is currently held by someone else.
+    Held {
+        until: u64,
+    },
+}
+
+/// A single operation within a non-transactional batch write.
+pub enum BatchWrite {
+    Put {
+        id: String,
+        item: DynamoDbItem,
+    },
+    Delete {
+        id: String,
+    },
+}
+
+pub trait UserStore: Sync {
+    /// Get the current version of the user's data. This is 0 if the user has
+    /// never written anything.
+    fn read_version(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<u64, Error>> + Send;
+
+    /// Get a single item by its ID.
+    fn get_item(
+        &self,
+        user_id: &str,
+        id: &str,
+    ) -> impl Future<Output = Result<Option<DynamoDbItem>, Error>> + Send;
+
+    /// Get all items (including deleted items) that were modified after the
+    /// given version, from any collection.
+    fn query_changed_since(
+        &self,
+        user_id: &str,
+        version: u64,
+    ) -> impl Future<Output = Result<Vec<DynamoDbItem>, Error>> + Send;
+
+    /// Get all items in a collection, optionally including deleted items.
+    fn query_collection(
+        &self,
+        user_id: &str,
+        collection: u32,
+        include_deleted: bool,
+    ) -> impl Future<Output = Result<Vec<DynamoDbItem>, Error>> + Send;
+
+    /// Get all items whose ID starts with the prefix.
+    fn query_prefix(
+        &self,
+        user_id: &str,
+        prefix: &str,
+    ) -> impl Future<Output = Result<Vec<DynamoDbItem>, Error>> + Send;
+
+    /// Get the IDs of all of the user's items in every collection. The VERSION
+    /// item is not included.
+    fn query_ids(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Vec<String>, Error>> + Send;
+
+    /// Get the IDs of every user that has a VERSION item.
+    fn scan_user_ids(&self) -> impl Future<Output = Result<Vec<String>, Error>> + Send;
+
+    /// Atomically apply the items and increment the version from
+    /// `client_version` to `client_version + 1`. Nothing is written if the
+    /// client's version is not the current version, if the lock is held or if
+    /// any of the conditions on the items fail.
+    fn versioned_write(
+        &self,
+        user_id: &str,
+        client_version: u64,
+        items: Vec<TransactItem>,
+    ) -> impl Future<Output = Result<WriteOutcome, Error>> + Send;
+
+    /// Try to acquire the import lock until the given timestamp. The token
+    /// identifies the holder of the lock.
+    fn acquire_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        until: u64,
+    ) -> impl Future<Output = Result<LockOutcome, Error>> + Send;
+
+    /// Extend the import lock until the given timestamp. Returns false if the
+    /// lock has already expired or is held with a different token.
+    fn extend_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        until: u64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Release the import lock and set the version. Returns false without
+    /// changing anything if the lock has already expired or is held with a
+    /// different token. Writes are allowed once the lock expires so the version
+    /// could have moved on.
+    fn release_import_lock(
+        &self,
+        user_id: &str,
+        token: &str,
+        new_version: u64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Apply the writes in batches. This is not atomic but all of the writes
+    /// will eventually be applied if this succeeds.
+    fn batch_write(
+        &self,
+        user_id: &str,
+        requests: Vec<BatchWrite>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}